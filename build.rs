@@ -0,0 +1,5 @@
+// embedded migrations are only picked up again when the build script reruns
+fn main() {
+    println!("cargo:rerun-if-changed=db/migrations");
+    println!("cargo:rerun-if-changed=db/sqlite/migrations");
+}
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS movie_revisions (
+    movie_id uuid NOT NULL REFERENCES movies (id) ON DELETE CASCADE,
+    revision BIGINT NOT NULL,
+    title VARCHAR(100) NOT NULL,
+    director VARCHAR(100) NOT NULL,
+    release_date TIMESTAMP NOT NULL,
+    ticket_price DECIMAL(12, 2) NOT NULL,
+    created_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    updated_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    deleted_at TIMESTAMP WITHOUT TIME ZONE,
+    changed_fields TEXT[] NOT NULL,
+    recorded_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    PRIMARY KEY (movie_id, revision)
+);
+
+-- movies that existed before revisions were recorded start their history with their current state
+INSERT INTO movie_revisions (
+    movie_id, revision, title, director, release_date, ticket_price, created_at, updated_at,
+    deleted_at, changed_fields, recorded_at
+)
+SELECT
+    id, version, title, director, release_date, ticket_price, created_at, updated_at,
+    deleted_at, ARRAY['title', 'director', 'release_date', 'ticket_price'], updated_at
+FROM movies
+ON CONFLICT DO NOTHING;
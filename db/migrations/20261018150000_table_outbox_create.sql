@@ -0,0 +1,12 @@
+-- change events written in the same transaction as the change, delivered by the dispatcher
+CREATE TABLE IF NOT EXISTS outbox (
+    id BIGSERIAL PRIMARY KEY,
+    kind VARCHAR(16) NOT NULL,
+    -- no foreign key, events outlive purged movies
+    movie_id uuid NOT NULL,
+    payload JSONB NOT NULL,
+    occurred_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    delivered_at TIMESTAMP WITHOUT TIME ZONE
+);
+
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON outbox (id) WHERE delivered_at IS NULL;
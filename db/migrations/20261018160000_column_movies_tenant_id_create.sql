@@ -0,0 +1,5 @@
+-- movies stored before there were tenants belong to the default tenant
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64) NOT NULL DEFAULT 'default';
+ALTER TABLE movie_revisions ADD COLUMN IF NOT EXISTS tenant_id VARCHAR(64) NOT NULL DEFAULT 'default';
+
+CREATE INDEX IF NOT EXISTS movies_tenant_id_created_at_id_idx ON movies (tenant_id, created_at, id);
@@ -0,0 +1,36 @@
+-- a tenant can't have two live movies with the same title, director and release date, deleted
+-- movies don't count so a deleted movie can be created again
+--
+-- existing duplicates are not resolved here, deleting them would bypass the outbox and the
+-- change feed, the migration fails and lists them instead
+DO $$
+DECLARE
+    duplicates TEXT;
+BEGIN
+    SELECT string_agg(
+        format(
+            '%s by %s released %s in tenant %s: %s',
+            title, director, release_date, tenant_id, ids
+        ),
+        '; '
+    )
+    INTO duplicates
+    FROM (
+        SELECT
+            tenant_id, title, director, release_date,
+            string_agg(id::TEXT, ', ' ORDER BY created_at, id) AS ids
+        FROM movies
+        WHERE deleted_at IS NULL
+        GROUP BY tenant_id, title, director, release_date
+        HAVING count(*) > 1
+    ) duplicate_movies;
+
+    IF duplicates IS NOT NULL THEN
+        RAISE EXCEPTION 'delete or change the duplicate movies before migrating: %', duplicates;
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX IF NOT EXISTS movies_tenant_id_title_director_release_date_key
+    ON movies (tenant_id, title, director, release_date)
+    WHERE deleted_at IS NULL;
@@ -0,0 +1,47 @@
+-- the change feed, every change to a movie numbered per tenant in the order it was committed
+CREATE TABLE IF NOT EXISTS movie_changes (
+    tenant_id VARCHAR(64) NOT NULL,
+    sequence BIGINT NOT NULL,
+    kind VARCHAR(16) NOT NULL,
+    -- no foreign key, tombstones outlive purged movies
+    movie_id uuid NOT NULL,
+    -- the movie as json, NULL for deletes
+    movie JSONB,
+    changed_at TIMESTAMP WITHOUT TIME ZONE NOT NULL,
+    PRIMARY KEY (tenant_id, sequence)
+);
+
+-- the last sequence handed out to each tenant, the row stays locked until the change commits so
+-- a later sequence is never visible before an earlier one
+CREATE TABLE IF NOT EXISTS movie_change_sequences (
+    tenant_id VARCHAR(64) PRIMARY KEY,
+    last_sequence BIGINT NOT NULL
+);
+
+-- movies that existed before the feed start it with their current state
+INSERT INTO movie_changes (tenant_id, sequence, kind, movie_id, movie, changed_at)
+SELECT
+    tenant_id,
+    row_number() OVER (PARTITION BY tenant_id ORDER BY created_at, id),
+    CASE WHEN deleted_at IS NULL THEN 'created' ELSE 'deleted' END,
+    id,
+    CASE WHEN deleted_at IS NULL THEN jsonb_build_object(
+        'id', id,
+        'tenant_id', tenant_id,
+        'title', title,
+        'director', director,
+        'release_date', release_date,
+        'ticket_price', ticket_price::TEXT,
+        'created_at', created_at,
+        'updated_at', updated_at,
+        'version', version
+    ) END,
+    COALESCE(deleted_at, updated_at)
+FROM movies
+ON CONFLICT DO NOTHING;
+
+INSERT INTO movie_change_sequences (tenant_id, last_sequence)
+SELECT tenant_id, MAX(sequence)
+FROM movie_changes
+GROUP BY tenant_id
+ON CONFLICT DO NOTHING;
@@ -0,0 +1,15 @@
+-- the title and director folded to lower case by the application, the director and title
+-- filters compare against these so they match the same way whatever the database's locale
+--
+-- existing movies are folded by the database, a movie is folded by the application again the
+-- next time its title or director is written
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS title_folded TEXT;
+ALTER TABLE movies ADD COLUMN IF NOT EXISTS director_folded TEXT;
+
+UPDATE movies
+SET title_folded = lower(title),
+    director_folded = lower(director)
+WHERE title_folded IS NULL OR director_folded IS NULL;
+
+ALTER TABLE movies ALTER COLUMN title_folded SET NOT NULL;
+ALTER TABLE movies ALTER COLUMN director_folded SET NOT NULL;
@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS movies (
+    id TEXT PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    director TEXT NOT NULL,
+    -- timestamps are stored as fixed width text so they sort chronologically
+    release_date TEXT NOT NULL,
+    -- prices are stored in cents to keep them exact
+    ticket_price_cents INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS movies_created_at_id_idx ON movies (created_at, id);
\ No newline at end of file
@@ -0,0 +1,21 @@
+CREATE VIRTUAL TABLE IF NOT EXISTS movies_fts USING fts5(
+    title,
+    director,
+    content = 'movies',
+    content_rowid = 'rowid'
+);
+
+CREATE TRIGGER IF NOT EXISTS movies_fts_insert AFTER INSERT ON movies BEGIN
+    INSERT INTO movies_fts (rowid, title, director) VALUES (new.rowid, new.title, new.director);
+END;
+
+CREATE TRIGGER IF NOT EXISTS movies_fts_delete AFTER DELETE ON movies BEGIN
+    INSERT INTO movies_fts (movies_fts, rowid, title, director)
+    VALUES ('delete', old.rowid, old.title, old.director);
+END;
+
+CREATE TRIGGER IF NOT EXISTS movies_fts_update AFTER UPDATE ON movies BEGIN
+    INSERT INTO movies_fts (movies_fts, rowid, title, director)
+    VALUES ('delete', old.rowid, old.title, old.director);
+    INSERT INTO movies_fts (rowid, title, director) VALUES (new.rowid, new.title, new.director);
+END;
\ No newline at end of file
@@ -0,0 +1,25 @@
+CREATE TABLE IF NOT EXISTS movie_revisions (
+    movie_id TEXT NOT NULL REFERENCES movies (id) ON DELETE CASCADE,
+    revision INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    director TEXT NOT NULL,
+    release_date TEXT NOT NULL,
+    ticket_price_cents INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL,
+    deleted_at TEXT,
+    -- comma separated field names
+    changed_fields TEXT NOT NULL,
+    recorded_at TEXT NOT NULL,
+    PRIMARY KEY (movie_id, revision)
+);
+
+-- movies that existed before revisions were recorded start their history with their current state
+INSERT OR IGNORE INTO movie_revisions (
+    movie_id, revision, title, director, release_date, ticket_price_cents, created_at, updated_at,
+    deleted_at, changed_fields, recorded_at
+)
+SELECT
+    id, version, title, director, release_date, ticket_price_cents, created_at, updated_at,
+    deleted_at, 'title,director,release_date,ticket_price', updated_at
+FROM movies;
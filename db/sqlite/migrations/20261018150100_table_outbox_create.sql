@@ -0,0 +1,13 @@
+-- change events written in the same transaction as the change, delivered by the dispatcher
+CREATE TABLE IF NOT EXISTS outbox (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    -- no foreign key, events outlive purged movies
+    movie_id TEXT NOT NULL,
+    -- the movie as json
+    payload TEXT NOT NULL,
+    occurred_at TEXT NOT NULL,
+    delivered_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS outbox_pending_idx ON outbox (id) WHERE delivered_at IS NULL;
@@ -0,0 +1,8 @@
+-- a tenant can't have two live movies with the same title, director and release date, deleted
+-- movies don't count so a deleted movie can be created again
+--
+-- existing duplicates are not resolved here, deleting them would bypass the outbox and the
+-- change feed, creating the index fails and the store lists them instead
+CREATE UNIQUE INDEX IF NOT EXISTS movies_tenant_id_title_director_release_date_key
+    ON movies (tenant_id, title, director, release_date)
+    WHERE deleted_at IS NULL;
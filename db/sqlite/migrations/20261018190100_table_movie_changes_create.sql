@@ -0,0 +1,44 @@
+-- the change feed, every change to a movie numbered per tenant in the order it was committed
+CREATE TABLE IF NOT EXISTS movie_changes (
+    tenant_id TEXT NOT NULL,
+    sequence INTEGER NOT NULL,
+    kind TEXT NOT NULL,
+    -- no foreign key, tombstones outlive purged movies
+    movie_id TEXT NOT NULL,
+    -- the movie as json, NULL for deletes
+    movie TEXT,
+    changed_at TEXT NOT NULL,
+    PRIMARY KEY (tenant_id, sequence)
+);
+
+-- the last sequence handed out to each tenant
+CREATE TABLE IF NOT EXISTS movie_change_sequences (
+    tenant_id TEXT PRIMARY KEY,
+    last_sequence INTEGER NOT NULL
+);
+
+-- movies that existed before the feed start it with their current state
+INSERT OR IGNORE INTO movie_changes (tenant_id, sequence, kind, movie_id, movie, changed_at)
+SELECT
+    tenant_id,
+    row_number() OVER (PARTITION BY tenant_id ORDER BY created_at, id),
+    CASE WHEN deleted_at IS NULL THEN 'created' ELSE 'deleted' END,
+    id,
+    CASE WHEN deleted_at IS NULL THEN json_object(
+        'id', id,
+        'tenant_id', tenant_id,
+        'title', title,
+        'director', director,
+        'release_date', replace(release_date, ' ', 'T'),
+        'ticket_price', printf('%d.%02d', ticket_price_cents / 100, abs(ticket_price_cents % 100)),
+        'created_at', replace(created_at, ' ', 'T'),
+        'updated_at', replace(updated_at, ' ', 'T'),
+        'version', version
+    ) END,
+    COALESCE(deleted_at, updated_at)
+FROM movies;
+
+INSERT OR IGNORE INTO movie_change_sequences (tenant_id, last_sequence)
+SELECT tenant_id, MAX(sequence)
+FROM movie_changes
+GROUP BY tenant_id;
@@ -0,0 +1,6 @@
+-- the title and director folded to lower case by the application, the director and title
+-- filters compare against these as sqlite's lower() only folds ASCII letters
+--
+-- existing movies are folded by the application once the migrations ran
+ALTER TABLE movies ADD COLUMN title_folded TEXT;
+ALTER TABLE movies ADD COLUMN director_folded TEXT;
@@ -0,0 +1,2 @@
+-- a dispatcher claims the events it is delivering until then, so other dispatchers skip them
+ALTER TABLE outbox ADD COLUMN claimed_until TEXT;
@@ -0,0 +1,2 @@
+-- how long the request holding a key without a response has it before a retry may take it over
+ALTER TABLE idempotency_keys ADD COLUMN locked_until TEXT;
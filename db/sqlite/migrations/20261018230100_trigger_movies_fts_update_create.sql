@@ -0,0 +1,10 @@
+-- only a change to the title or director touches the search index. A statement writing to the
+-- index fails with SQLITE_BUSY right away when another connection is writing, instead of
+-- waiting for it, so updates that leave both alone stay clear of it.
+DROP TRIGGER IF EXISTS movies_fts_update;
+
+CREATE TRIGGER movies_fts_update AFTER UPDATE OF title, director ON movies BEGIN
+    INSERT INTO movies_fts (movies_fts, rowid, title, director)
+    VALUES ('delete', old.rowid, old.title, old.director);
+    INSERT INTO movies_fts (rowid, title, director) VALUES (new.rowid, new.title, new.director);
+END;
@@ -6,6 +6,11 @@ use std::env;
 pub struct Configuration {
     pub http_server: HttpServerConfiguration,
     pub database: DatabaseConfiguration,
+    pub pagination: PaginationConfiguration,
+    pub cache: CacheConfiguration,
+    pub outbox: OutboxConfiguration,
+    pub idempotency: IdempotencyConfiguration,
+    pub events: EventsConfiguration,
 }
 
 #[derive(Clone, serde::Deserialize)]
@@ -19,9 +24,81 @@ pub struct HttpServerConfiguration {
 pub struct DatabaseConfiguration {
     pub store_type: String,
     pub database_url: String,
+    /// A read replica of `database_url` for requests that opt in, only used by the sql store.
+    #[serde(default)]
+    pub read_database_url: Option<String>,
     pub log_level: String,
     #[serde(deserialize_with = "deserialize_number_from_string")]
     pub max_open_connections: u32,
+    /// Applies the embedded migrations on startup, ignored by the memory store.
+    pub run_migrations: bool,
+    pub persistence: PersistenceConfiguration,
+    /// How many of the latest changes of each tenant the memory store keeps as they were,
+    /// ignored by the other stores.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub change_retention: usize,
+}
+
+/// Persistence of the memory store to a local directory, ignored by the other stores.
+#[derive(Clone, serde::Deserialize)]
+pub struct PersistenceConfiguration {
+    pub enabled: bool,
+    pub directory: String,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub snapshot_interval_seconds: u64,
+}
+
+#[derive(Clone, serde::Deserialize)]
+pub struct PaginationConfiguration {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub default_page_size: u32,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_page_size: u32,
+}
+
+/// Caching of movies read by id in front of any store.
+#[derive(Clone, serde::Deserialize)]
+pub struct CacheConfiguration {
+    pub enabled: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub capacity: usize,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub ttl_seconds: u64,
+}
+
+/// Delivery of movie change events from the outbox.
+#[derive(Clone, serde::Deserialize)]
+pub struct OutboxConfiguration {
+    pub enabled: bool,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub poll_interval_ms: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub batch_size: u32,
+    pub log_sink: bool,
+    /// File the events are appended to as newline delimited JSON, no file sink when empty.
+    pub file_sink_path: String,
+}
+
+/// The live event stream at `/movies/events`.
+#[derive(Clone, serde::Deserialize)]
+pub struct EventsConfiguration {
+    /// How many of the latest events are kept for subscribers resuming with `Last-Event-ID`.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub buffer_size: usize,
+    /// How often an idle stream gets a heartbeat comment, at least every second.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub heartbeat_interval_seconds: u64,
+}
+
+/// How long the response to a request sent with an `Idempotency-Key` is replayed to retries.
+#[derive(Clone, serde::Deserialize)]
+pub struct IdempotencyConfiguration {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub ttl_seconds: u64,
+    /// How long a request holds its key before a retry may take over, should the request never
+    /// finish.
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub lock_seconds: u64,
 }
 
 pub fn get_configuration() -> Result<Configuration, config::ConfigError> {
@@ -3,16 +3,45 @@ use axum::Json;
 use axum::{extract::State, http::StatusCode};
 use serde::{Deserialize, Serialize};
 
+use crate::store::cached_store::CacheStats;
 use crate::store::store::DynStore;
 
 #[derive(Deserialize, Serialize)]
 struct HealthResponse {
     ok: bool,
     store_ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    replica_store_ok: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    movie_cache: Option<CacheStatsResponse>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct CacheStatsResponse {
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+impl From<CacheStats> for CacheStatsResponse {
+    fn from(stats: CacheStats) -> Self {
+        CacheStatsResponse {
+            hits: stats.hits,
+            misses: stats.misses,
+            evictions: stats.evictions,
+        }
+    }
 }
 
 pub async fn get(State(store): State<DynStore>) -> impl IntoResponse {
     let store_ok = store.is_connected().await;
-    let health_response = HealthResponse { ok: true, store_ok };
+    let replica_store_ok = store.is_replica_connected().await;
+    let movie_cache = store.movie_cache_stats().await.map(Into::into);
+    let health_response = HealthResponse {
+        ok: true,
+        store_ok,
+        replica_store_ok,
+        movie_cache,
+    };
     (StatusCode::OK, Json(health_response))
 }
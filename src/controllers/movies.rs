@@ -1,26 +1,65 @@
+use std::cmp::Ordering;
+use std::convert::Infallible;
 use std::str::FromStr;
+use std::time::Duration;
 
-use axum::extract::{Path, State};
-use axum::http::StatusCode;
+use axum::async_trait;
+use axum::body::StreamBody;
+use axum::extract::{BodyStream, FromRef, FromRequestParts, Path, Query, State};
+use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
 use axum::response::{IntoResponse, Response};
 use axum::Json;
 use bigdecimal::{BigDecimal, FromPrimitive, ToPrimitive};
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, SubsecRound, Utc};
+use futures::future;
+use futures::stream::{self, Stream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use tokio::sync::broadcast::error::RecvError;
 use uuid::Uuid;
 
-use crate::store::store::{CreateMovieParams, DynMovieStore, Movie, UpdateMovieParams};
+use crate::configuration::{
+    EventsConfiguration, IdempotencyConfiguration, PaginationConfiguration,
+};
+use crate::events::MovieEventHub;
+use crate::store::store::{
+    BatchError, BatchOperation, ChangeCursor, CreateMovieParams, DynIdempotencyStore,
+    DynMovieStore, IdempotentRequest, IdempotentResponse, ImportMode, ImportMovie, ImportOutcome,
+    Movie, MovieChange, MovieCursor, MovieEvent, MovieEventKind, MovieQuery, MovieRevision,
+    MovieSort, MovieStatsQuery, StoreError, TicketPriceStats, UpdateMovieParams, DEFAULT_TENANT_ID,
+};
 
 #[derive(Deserialize, Serialize)]
 pub struct MovieResponse {
-    id: Uuid,
-    title: String,
-    director: String,
-    release_date: String,
-    ticket_price: f64,
-    created_at: String,
-    updated_at: String,
+    pub id: Uuid,
+    pub title: String,
+    pub director: String,
+    pub release_date: String,
+    #[serde(serialize_with = "serialize_ticket_price")]
+    pub ticket_price: BigDecimal,
+    pub created_at: String,
+    pub updated_at: String,
+    pub deleted_at: Option<String>,
+}
+
+/// Writes the price as a JSON number, a price that has no `f64` fails the response rather than
+/// the request task.
+fn serialize_ticket_price<S>(ticket_price: &BigDecimal, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::Serializer,
+{
+    let ticket_price = ticket_price_to_f64(ticket_price).map_err(serde::ser::Error::custom)?;
+    serializer.serialize_f64(ticket_price)
+}
+
+fn ticket_price_to_f64(ticket_price: &BigDecimal) -> Result<f64, String> {
+    ticket_price
+        .to_f64()
+        .filter(|ticket_price| ticket_price.is_finite())
+        .ok_or_else(|| format!("ticket price {} is out of range", ticket_price))
 }
 
 impl From<Movie> for MovieResponse {
@@ -30,162 +69,1687 @@ impl From<Movie> for MovieResponse {
             title: movie.title,
             director: movie.director,
             release_date: movie.release_date.to_string(),
-            ticket_price: movie.ticket_price.to_f64().unwrap(),
+            ticket_price: movie.ticket_price,
             created_at: movie.created_at.to_string(),
             updated_at: movie.updated_at.to_string(),
+            deleted_at: movie.deleted_at.map(|deleted_at| deleted_at.to_string()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ListMoviesQuery {
+    limit: Option<u32>,
+    cursor: Option<String>,
+    director: Option<String>,
+    title_contains: Option<String>,
+    release_date_from: Option<String>,
+    release_date_to: Option<String>,
+    ticket_price_min: Option<f64>,
+    ticket_price_max: Option<f64>,
+    include_deleted: Option<bool>,
+    sort: Option<String>,
+}
+
+impl TryFrom<ListMoviesQuery> for MovieQuery {
+    type Error = AppError;
+
+    fn try_from(query: ListMoviesQuery) -> Result<Self, Self::Error> {
+        Ok(MovieQuery {
+            director: query.director,
+            title_contains: query.title_contains,
+            release_date_from: query
+                .release_date_from
+                .as_deref()
+                .map(parse_release_date)
+                .transpose()?,
+            release_date_to: query
+                .release_date_to
+                .as_deref()
+                .map(parse_release_date)
+                .transpose()?,
+            ticket_price_min: query.ticket_price_min.map(parse_ticket_price).transpose()?,
+            ticket_price_max: query.ticket_price_max.map(parse_ticket_price).transpose()?,
+            include_deleted: query.include_deleted.unwrap_or(false),
+            sort: match query.sort {
+                None => MovieSort::default(),
+                Some(sort) => MovieSort::from_str(&sort)?,
+            },
+        })
+    }
+}
+
+/// Lets a request read from the read replica, replica reads may not see the latest writes.
+pub const READ_REPLICA_HEADER: &str = "x-read-replica";
+
+/// The movie store reading from the read replica, if there is one.
+#[derive(Clone)]
+pub struct ReplicaMovieStore(pub Option<DynMovieStore>);
+
+/// The movie store a read should use, the replica when the request sends
+/// `X-Read-Replica: true` and there is one, the primary otherwise.
+pub struct ReadMovieStore(pub DynMovieStore);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for ReadMovieStore
+where
+    S: Send + Sync,
+    DynMovieStore: FromRef<S>,
+    ReplicaMovieStore: FromRef<S>,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let use_replica = parts
+            .headers
+            .get(READ_REPLICA_HEADER)
+            .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"true"));
+        match ReplicaMovieStore::from_ref(state) {
+            ReplicaMovieStore(Some(replica_movie_store)) if use_replica => {
+                Ok(ReadMovieStore(replica_movie_store))
+            }
+            _ => Ok(ReadMovieStore(DynMovieStore::from_ref(state))),
+        }
+    }
+}
+
+/// Names the tenant whose catalogue a request reads and writes.
+pub const TENANT_HEADER: &str = "x-tenant-id";
+
+/// The tenant from `X-Tenant-Id`, requests without the header belong to the default tenant.
+pub struct Tenant(pub String);
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Tenant
+where
+    S: Send + Sync,
+{
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let Some(tenant_id) = parts.headers.get(TENANT_HEADER) else {
+            return Ok(Tenant(DEFAULT_TENANT_ID.to_string()));
+        };
+        let tenant_id = tenant_id.to_str().unwrap_or_default();
+        let is_valid = !tenant_id.is_empty()
+            && tenant_id.len() <= 64
+            && tenant_id
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_');
+        if !is_valid {
+            return Err(AppError::ValidationError("Invalid tenant id".to_string()));
+        }
+        Ok(Tenant(tenant_id.to_string()))
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ListMoviesResponse {
+    pub movies: Vec<MovieResponse>,
+    pub next_cursor: Option<String>,
+}
+
+/// With `Accept: text/csv` every movie that passes the filters is exported as CSV instead, like
+/// `export_csv`, a CSV export can't be sorted or paginated.
+pub async fn list(
+    Query(mut query): Query<ListMoviesQuery>,
+    Tenant(tenant_id): Tenant,
+    ReadMovieStore(movie_store): ReadMovieStore,
+    State(pagination): State<PaginationConfiguration>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    if accepts_csv(&headers) {
+        if query.limit.is_some() || query.cursor.is_some() || query.sort.is_some() {
+            return Err(AppError::ValidationError(
+                "limit, cursor and sort are not supported for CSV".to_string(),
+            ));
+        }
+        let movie_query = MovieQuery::try_from(query)?;
+        return Ok(csv_export(&movie_store, &tenant_id, movie_query));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(pagination.default_page_size)
+        .clamp(1, pagination.max_page_size);
+    let cursor = match query.cursor.take() {
+        None => None,
+        Some(cursor) => Some(MovieCursor::decode(&cursor)?),
+    };
+    let movie_query = MovieQuery::try_from(query)?;
+
+    let page = movie_store
+        .find(&tenant_id, &movie_query, cursor, limit)
+        .await?;
+    let list_response = ListMoviesResponse {
+        movies: page.movies.into_iter().map(Into::into).collect(),
+        next_cursor: page.next_cursor.map(|cursor| cursor.encode()),
+    };
+
+    Ok((StatusCode::OK, Json(list_response)).into_response())
+}
+
+#[derive(Deserialize)]
+pub struct SearchMoviesQuery {
+    q: String,
+    limit: Option<u32>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MovieSearchResultResponse {
+    #[serde(flatten)]
+    pub movie: MovieResponse,
+    pub score: f32,
+}
+
+pub async fn search(
+    Query(query): Query<SearchMoviesQuery>,
+    Tenant(tenant_id): Tenant,
+    ReadMovieStore(movie_store): ReadMovieStore,
+    State(pagination): State<PaginationConfiguration>,
+) -> Result<Json<Vec<MovieSearchResultResponse>>, AppError> {
+    if query.q.trim().is_empty() {
+        return Err(AppError::ValidationError(
+            "Search query is empty".to_string(),
+        ));
+    }
+    let limit = query
+        .limit
+        .unwrap_or(pagination.default_page_size)
+        .clamp(1, pagination.max_page_size);
+
+    let results = movie_store.search(&tenant_id, &query.q, limit).await?;
+    let search_responses = results
+        .into_iter()
+        .map(|result| MovieSearchResultResponse {
+            movie: result.movie.into(),
+            score: result.score,
+        })
+        .collect();
+
+    Ok(Json(search_responses))
+}
+
+#[derive(Deserialize)]
+pub struct MovieStatsRequestQuery {
+    release_date_from: Option<String>,
+    release_date_to: Option<String>,
+}
+
+/// Ticket prices are decimal strings with the trailing zeros dropped, so they don't depend on
+/// how a store keeps them.
+#[derive(Deserialize, Serialize)]
+pub struct TicketPriceStatsResponse {
+    pub count: i64,
+    pub average_ticket_price: Option<BigDecimal>,
+    pub min_ticket_price: Option<BigDecimal>,
+    pub max_ticket_price: Option<BigDecimal>,
+}
+
+impl From<TicketPriceStats> for TicketPriceStatsResponse {
+    fn from(stats: TicketPriceStats) -> Self {
+        TicketPriceStatsResponse {
+            count: stats.count,
+            average_ticket_price: stats.average().map(|average| average.normalized()),
+            min_ticket_price: stats.min.map(|min| min.normalized()),
+            max_ticket_price: stats.max.map(|max| max.normalized()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct DirectorStatsResponse {
+    pub director: String,
+    #[serde(flatten)]
+    pub stats: TicketPriceStatsResponse,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ReleaseYearStatsResponse {
+    pub release_year: i32,
+    #[serde(flatten)]
+    pub stats: TicketPriceStatsResponse,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MovieStatsResponse {
+    pub overall: TicketPriceStatsResponse,
+    pub by_director: Vec<DirectorStatsResponse>,
+    pub by_release_year: Vec<ReleaseYearStatsResponse>,
+}
+
+pub async fn stats(
+    Query(query): Query<MovieStatsRequestQuery>,
+    Tenant(tenant_id): Tenant,
+    ReadMovieStore(movie_store): ReadMovieStore,
+) -> Result<Json<MovieStatsResponse>, AppError> {
+    let stats_query = MovieStatsQuery {
+        release_date_from: query
+            .release_date_from
+            .as_deref()
+            .map(parse_release_date)
+            .transpose()?,
+        release_date_to: query
+            .release_date_to
+            .as_deref()
+            .map(parse_release_date)
+            .transpose()?,
+    };
+
+    let stats = movie_store.stats(&tenant_id, &stats_query).await?;
+    let stats_response = MovieStatsResponse {
+        overall: stats.overall.into(),
+        by_director: stats
+            .by_director
+            .into_iter()
+            .map(|(director, stats)| DirectorStatsResponse {
+                director,
+                stats: stats.into(),
+            })
+            .collect(),
+        by_release_year: stats
+            .by_release_year
+            .into_iter()
+            .map(|(release_year, stats)| ReleaseYearStatsResponse {
+                release_year,
+                stats: stats.into(),
+            })
+            .collect(),
+    };
+
+    Ok(Json(stats_response))
+}
+
+#[derive(Deserialize)]
+pub struct ListChangesQuery {
+    since: Option<String>,
+    limit: Option<u32>,
+}
+
+/// A change to a movie, deletes are tombstones without the movie.
+#[derive(Deserialize, Serialize)]
+pub struct MovieChangeResponse {
+    pub sequence: i64,
+    pub kind: MovieEventKind,
+    pub movie_id: Uuid,
+    pub changed_at: String,
+    pub movie: Option<MovieResponse>,
+}
+
+impl From<MovieChange> for MovieChangeResponse {
+    fn from(change: MovieChange) -> Self {
+        MovieChangeResponse {
+            sequence: change.sequence,
+            kind: change.kind,
+            movie_id: change.movie_id,
+            changed_at: change.changed_at.to_string(),
+            movie: change.movie.map(Into::into),
         }
     }
 }
 
-pub async fn list(State(movie_store): State<DynMovieStore>) -> impl IntoResponse {
-    let movies = movie_store.get_all().await;
-    let movie_responses: Box<[MovieResponse]> = movies.into_iter().map(Into::into).collect();
+#[derive(Deserialize, Serialize)]
+pub struct ListChangesResponse {
+    pub changes: Vec<MovieChangeResponse>,
+    /// Where to carry on from, `None` only while there have been no changes at all.
+    pub next_since: Option<String>,
+    /// Whether there are more changes to read right away.
+    pub has_more: bool,
+}
+
+pub async fn changes(
+    Query(query): Query<ListChangesQuery>,
+    Tenant(tenant_id): Tenant,
+    ReadMovieStore(movie_store): ReadMovieStore,
+    State(pagination): State<PaginationConfiguration>,
+) -> Result<Json<ListChangesResponse>, AppError> {
+    let limit = query
+        .limit
+        .unwrap_or(pagination.default_page_size)
+        .clamp(1, pagination.max_page_size);
+    let since = query
+        .since
+        .as_deref()
+        .map(ChangeCursor::decode)
+        .transpose()?;
+
+    let page = movie_store.changes(&tenant_id, since, limit).await?;
+    let next_since = page
+        .changes
+        .last()
+        .map(MovieChange::cursor)
+        .or(since)
+        .map(|cursor| cursor.encode());
+    let changes_response = ListChangesResponse {
+        changes: page.changes.into_iter().map(Into::into).collect(),
+        next_since,
+        has_more: page.has_more,
+    };
+
+    Ok(Json(changes_response))
+}
+
+/// Sent by `EventSource` when it reconnects, the id of the last event it received.
+pub const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+#[derive(Deserialize)]
+pub struct MovieEventsQuery {
+    movie_id: Option<Uuid>,
+    /// Case insensitive match on the whole director name.
+    director: Option<String>,
+}
+
+impl MovieEventsQuery {
+    fn matches(&self, tenant_id: &str, event: &MovieEvent) -> bool {
+        event.payload.tenant_id == tenant_id
+            && self
+                .movie_id
+                .is_none_or(|movie_id| event.movie_id == movie_id)
+            && self.director.as_ref().is_none_or(|director| {
+                event.payload.director.to_lowercase() == director.to_lowercase()
+            })
+    }
+}
+
+/// Pushes the tenant's movie events as server-sent events, named after their kind with the
+/// movie as data. A client resuming with `Last-Event-ID` first gets the buffered events it
+/// missed, or a `lagged` event when they are no longer buffered.
+pub async fn events(
+    Query(query): Query<MovieEventsQuery>,
+    Tenant(tenant_id): Tenant,
+    State(hub): State<MovieEventHub>,
+    State(configuration): State<EventsConfiguration>,
+    headers: HeaderMap,
+) -> Result<Sse<impl Stream<Item = Result<Event, serde_json::Error>>>, AppError> {
+    let last_event_id = match headers.get(LAST_EVENT_ID_HEADER) {
+        None => None,
+        Some(value) => Some(
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.trim().parse::<i64>().ok())
+                .ok_or_else(|| AppError::ValidationError("Invalid Last-Event-ID".to_string()))?,
+        ),
+    };
+
+    let subscription = hub.subscribe(last_event_id);
+    let lagged = stream::iter(subscription.lagged.then(lagged_event));
+    let missed = stream::iter(subscription.missed.into_iter().map(Some));
+    let live = stream::unfold(subscription.receiver, |mut receiver| async move {
+        match receiver.recv().await {
+            Ok(event) => Some((Some(event), receiver)),
+            // the subscriber is told once and carries on with the events still buffered
+            Err(RecvError::Lagged(_)) => Some((None, receiver)),
+            Err(RecvError::Closed) => None,
+        }
+    });
+    let events = missed.chain(live).filter_map(move |event| {
+        let event = match event {
+            None => Some(lagged_event()),
+            Some(event) if query.matches(&tenant_id, &event) => Some(movie_event(event)),
+            Some(_) => None,
+        };
+        async move { event }
+    });
+
+    let keep_alive = KeepAlive::new()
+        .interval(Duration::from_secs(
+            configuration.heartbeat_interval_seconds.max(1),
+        ))
+        .text("heartbeat");
+    Ok(Sse::new(lagged.chain(events)).keep_alive(keep_alive))
+}
 
-    (StatusCode::OK, Json(movie_responses))
+fn movie_event(event: MovieEvent) -> Result<Event, serde_json::Error> {
+    Event::default()
+        .id(event.id.to_string())
+        .event(event.kind.name())
+        .json_data(MovieResponse::from(event.payload))
+}
+
+fn lagged_event() -> Result<Event, serde_json::Error> {
+    Ok(Event::default()
+        .event("lagged")
+        .data("events were missed, read the movies again"))
+}
+
+#[derive(Deserialize)]
+pub struct GetMovieQuery {
+    include_deleted: Option<bool>,
+    as_of: Option<String>,
 }
 
 pub async fn get(
     Path(id): Path<Uuid>,
-    State(movie_store): State<DynMovieStore>,
-) -> Result<Json<MovieResponse>, AppError> {
-    let movie = movie_store.get_by_id(id).await;
-    match movie {
-        None => {
+    Query(query): Query<GetMovieQuery>,
+    Tenant(tenant_id): Tenant,
+    ReadMovieStore(movie_store): ReadMovieStore,
+) -> Result<Response, AppError> {
+    let include_deleted = query.include_deleted.unwrap_or(false);
+    if let Some(as_of) = query.as_of {
+        let as_of = NaiveDateTime::from_str(&as_of)
+            .map_err(|_| AppError::ValidationError("Invalid as_of".to_string()))?;
+        // the movie as it was, there is no current version to send as an ETag
+        let revision = movie_store
+            .history(&tenant_id, id)
+            .await?
+            .into_iter()
+            .rev()
+            .find(|revision| revision.recorded_at <= as_of)
+            .ok_or(AppError::MovieNotFound)?;
+        if revision.movie.deleted_at.is_some() && !include_deleted {
             return Err(AppError::MovieNotFound);
         }
-        Some(movie) => {
-            let movie_response = MovieResponse::from(movie);
-            return Ok(Json(movie_response));
+        return Ok(Json(MovieResponse::from(revision.movie)).into_response());
+    }
+
+    let movie = movie_store
+        .get_by_id(&tenant_id, id, include_deleted)
+        .await?;
+    let etag = etag(&movie);
+    let movie_response = MovieResponse::from(movie);
+    Ok(([(header::ETAG, etag)], Json(movie_response)).into_response())
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct MovieRevisionResponse {
+    pub revision: i64,
+    pub changed_fields: Vec<String>,
+    pub recorded_at: String,
+    pub movie: MovieResponse,
+}
+
+impl From<MovieRevision> for MovieRevisionResponse {
+    fn from(revision: MovieRevision) -> Self {
+        MovieRevisionResponse {
+            revision: revision.revision,
+            changed_fields: revision.changed_fields,
+            recorded_at: revision.recorded_at.to_string(),
+            movie: revision.movie.into(),
         }
     }
 }
 
+pub async fn history(
+    Path(id): Path<Uuid>,
+    Tenant(tenant_id): Tenant,
+    State(movie_store): State<DynMovieStore>,
+) -> Result<Json<Vec<MovieRevisionResponse>>, AppError> {
+    let revisions = movie_store.history(&tenant_id, id).await?;
+    Ok(Json(revisions.into_iter().map(Into::into).collect()))
+}
+
+/// Writes the fields of an earlier revision as a new update, so the revert itself shows up
+/// in the history.
+pub async fn revert(
+    Path((id, revision)): Path<(Uuid, i64)>,
+    Tenant(tenant_id): Tenant,
+    State(movie_store): State<DynMovieStore>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let expected_version = parse_if_match(&headers)?;
+    let revision = movie_store
+        .history(&tenant_id, id)
+        .await?
+        .into_iter()
+        .find(|r| r.revision == revision)
+        .ok_or(AppError::MovieNotFound)?;
+
+    let params = UpdateMovieParams {
+        title: Some(revision.movie.title),
+        director: Some(revision.movie.director),
+        release_date: Some(revision.movie.release_date),
+        ticket_price: Some(revision.movie.ticket_price),
+    };
+    let movie = movie_store
+        .update(&tenant_id, id, params, expected_version)
+        .await?;
+
+    let etag = etag(&movie);
+    let movie_response = MovieResponse::from(movie);
+    Ok(([(header::ETAG, etag)], Json(movie_response)))
+}
+
+fn etag(movie: &Movie) -> HeaderValue {
+    HeaderValue::from_str(&format!("\"{}\"", movie.version)).unwrap()
+}
+
+/// Reads the version a write is conditional on from `If-Match`, `*` or no header means any.
+/// Only a single strong entity tag is supported, anything else can never match.
+fn parse_if_match(headers: &HeaderMap) -> Result<Option<i64>, AppError> {
+    let Some(if_match) = headers.get(header::IF_MATCH) else {
+        return Ok(None);
+    };
+    let if_match = if_match.to_str().unwrap_or_default().trim();
+    if if_match == "*" {
+        return Ok(None);
+    }
+
+    if_match
+        .strip_prefix('"')
+        .and_then(|if_match| if_match.strip_suffix('"'))
+        .and_then(|version| version.parse::<i64>().ok())
+        .map(Some)
+        .ok_or(AppError::PreconditionFailed)
+}
+
 // the input to our `create` handler
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct CreateMovieRequest {
-    title: String,
-    director: String,
-    release_date: String,
-    ticket_price: f64,
+    pub title: String,
+    pub director: String,
+    pub release_date: String,
+    pub ticket_price: f64,
 }
 
-impl From<CreateMovieRequest> for CreateMovieParams {
-    fn from(request: CreateMovieRequest) -> Self {
-        CreateMovieParams {
+impl TryFrom<CreateMovieRequest> for CreateMovieParams {
+    type Error = AppError;
+
+    fn try_from(request: CreateMovieRequest) -> Result<Self, Self::Error> {
+        Ok(CreateMovieParams {
             title: request.title,
             director: request.director,
-            release_date: NaiveDateTime::from_str(&request.release_date).unwrap(),
-            ticket_price: BigDecimal::from_f64(request.ticket_price).unwrap(),
-        }
+            release_date: parse_release_date(&request.release_date)?,
+            ticket_price: parse_ticket_price(request.ticket_price)?,
+        })
     }
 }
 
+/// Accepts `2001-01-01T00:00:00` and the `2001-01-01 00:00:00` responses use, so a movie read
+/// from the api can be written back as it is.
+fn parse_release_date(release_date: &str) -> Result<NaiveDateTime, AppError> {
+    NaiveDateTime::from_str(release_date)
+        .or_else(|_| NaiveDateTime::parse_from_str(release_date, "%Y-%m-%d %H:%M:%S%.f"))
+        .map_err(|_| AppError::ValidationError("Invalid release date".to_string()))
+}
+
+fn parse_ticket_price(ticket_price: f64) -> Result<BigDecimal, AppError> {
+    BigDecimal::from_f64(ticket_price)
+        .ok_or_else(|| AppError::ValidationError("Invalid ticket price".to_string()))
+}
+
+/// Lets a client retry a create without creating the movie twice.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+/// Set on a response that replays the first response to an idempotency key.
+pub const IDEMPOTENT_REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// With an `Idempotency-Key` the first response to the key is kept and replayed to every retry
+/// of the same request, the key can't be used for a different request until it expires.
 pub async fn create(
+    Tenant(tenant_id): Tenant,
     State(movie_store): State<DynMovieStore>,
+    State(idempotency_store): State<DynIdempotencyStore>,
+    State(idempotency): State<IdempotencyConfiguration>,
+    headers: HeaderMap,
     Json(request): Json<CreateMovieRequest>,
-) -> Result<Json<MovieResponse>, AppError> {
-    let release_date = NaiveDateTime::from_str(&request.release_date);
-    let release_date = match release_date {
-        Ok(release_date) => release_date,
-        Err(_) => {
-            return Err(AppError::ValidationError(
-                "Invalid release date".to_string(),
+) -> Result<Response, AppError> {
+    let Some(key) = parse_idempotency_key(&headers)? else {
+        let movie_response = create_movie(&tenant_id, &movie_store, request).await?;
+        return Ok(Json(movie_response).into_response());
+    };
+
+    // the parsed request is hashed, so formatting and field order don't make it a different one
+    let request_hash = format!(
+        "{:x}",
+        Sha256::digest(serde_json::to_vec(&request).unwrap())
+    );
+    // kept to the microsecond the databases store, releasing the claim matches on it
+    let now = Utc::now().naive_utc().trunc_subsecs(6);
+    let locked_until =
+        now + chrono::Duration::seconds(idempotency.lock_seconds.try_into().unwrap_or(i64::MAX));
+    let expires_at =
+        now + chrono::Duration::seconds(idempotency.ttl_seconds.try_into().unwrap_or(i64::MAX));
+    match idempotency_store
+        .claim(&tenant_id, key, &request_hash, locked_until, expires_at)
+        .await?
+    {
+        None => {}
+        Some(claimed) if claimed.request_hash != request_hash => {
+            return Err(AppError::IdempotencyKeyReused)
+        }
+        Some(IdempotentRequest {
+            response: Some(response),
+            ..
+        }) => {
+            let replayed = [(IDEMPOTENT_REPLAYED_HEADER, HeaderValue::from_static("true"))];
+            return Ok((replayed, json_response(response)).into_response());
+        }
+        Some(IdempotentRequest { response: None, .. }) => {
+            return Err(AppError::Conflict(
+                "a request with this idempotency key is in progress".to_string(),
             ))
         }
+    }
+
+    let claim = IdempotencyClaim {
+        idempotency_store,
+        tenant_id,
+        key: key.to_string(),
+        locked_until,
+        held: true,
     };
+    let (status, body) = match create_movie(&claim.tenant_id, &movie_store, request).await {
+        Ok(movie_response) => (StatusCode::OK, json!(movie_response)),
+        // server errors are not kept, a retry with the same key tries again
+        Err(error) if error.status_and_message().0.is_server_error() => {
+            claim.release().await;
+            return Err(error);
+        }
+        Err(error) => (error.status_and_message().0, error.body()),
+    };
+    let response = IdempotentResponse {
+        status: status.as_u16(),
+        body: body.to_string().into_bytes(),
+    };
+    // the movie is created either way, failing now would leave retries facing a held key
+    claim.complete(&response).await;
+
+    Ok((status, Json(body)).into_response())
+}
+
+/// An idempotency key claimed by a running request. Dropped before it completes, e.g. when the
+/// client goes away and the request is cancelled or the request panics, the key is released so
+/// retries don't find it in progress until the lock lapses.
+struct IdempotencyClaim {
+    idempotency_store: DynIdempotencyStore,
+    tenant_id: String,
+    key: String,
+    locked_until: NaiveDateTime,
+    held: bool,
+}
+
+impl IdempotencyClaim {
+    async fn complete(mut self, response: &IdempotentResponse) {
+        self.held = false;
+        if let Err(error) = self
+            .idempotency_store
+            .complete(&self.tenant_id, &self.key, response)
+            .await
+        {
+            tracing::warn!(
+                "failed to keep the response to an idempotency key: {}",
+                error
+            );
+        }
+    }
+
+    async fn release(mut self) {
+        self.held = false;
+        release_idempotency_key(
+            &self.idempotency_store,
+            &self.tenant_id,
+            &self.key,
+            self.locked_until,
+        )
+        .await;
+    }
+}
+
+impl Drop for IdempotencyClaim {
+    fn drop(&mut self) {
+        if !self.held {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let idempotency_store = self.idempotency_store.clone();
+        let tenant_id = std::mem::take(&mut self.tenant_id);
+        let key = std::mem::take(&mut self.key);
+        let locked_until = self.locked_until;
+        runtime.spawn(async move {
+            release_idempotency_key(&idempotency_store, &tenant_id, &key, locked_until).await;
+        });
+    }
+}
+
+async fn release_idempotency_key(
+    idempotency_store: &DynIdempotencyStore,
+    tenant_id: &str,
+    key: &str,
+    locked_until: NaiveDateTime,
+) {
+    if let Err(error) = idempotency_store
+        .release(tenant_id, key, locked_until)
+        .await
+    {
+        tracing::warn!("failed to release idempotency key: {}", error);
+    }
+}
+
+async fn create_movie(
+    tenant_id: &str,
+    movie_store: &DynMovieStore,
+    request: CreateMovieRequest,
+) -> Result<MovieResponse, AppError> {
+    let params = CreateMovieParams::try_from(request)?;
+    let movie = movie_store.create(tenant_id, params).await?;
+    Ok(MovieResponse::from(movie))
+}
 
-    let movie = movie_store.create(request.into()).await;
-    let movie = match movie {
-        Ok(movie) => movie,
-        Err(error_message) => return Err(AppError::Unknown(error_message)),
+fn parse_idempotency_key(headers: &HeaderMap) -> Result<Option<&str>, AppError> {
+    let Some(key) = headers.get(IDEMPOTENCY_KEY_HEADER) else {
+        return Ok(None);
     };
+    match key.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => Ok(Some(key)),
+        _ => Err(AppError::ValidationError(
+            "Invalid idempotency key".to_string(),
+        )),
+    }
+}
 
-    let movie_response = MovieResponse::from(movie);
-    Ok(movie_response.into())
+fn json_response(response: IdempotentResponse) -> Response {
+    let status = StatusCode::from_u16(response.status).unwrap_or(StatusCode::OK);
+    let content_type = [(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    )];
+    (status, content_type, response.body).into_response()
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Serialize)]
 pub struct UpdateMovieRequest {
-    title: Option<String>,
-    director: Option<String>,
-    release_date: Option<String>,
-    ticket_price: Option<f64>,
+    pub title: Option<String>,
+    pub director: Option<String>,
+    pub release_date: Option<String>,
+    pub ticket_price: Option<f64>,
+}
+
+impl TryFrom<UpdateMovieRequest> for UpdateMovieParams {
+    type Error = AppError;
+
+    fn try_from(request: UpdateMovieRequest) -> Result<Self, Self::Error> {
+        let release_date = match request.release_date {
+            None => None,
+            Some(release_date) => Some(parse_release_date(&release_date)?),
+        };
+
+        let ticket_price = match request.ticket_price {
+            None => None,
+            Some(ticket_price) => Some(parse_ticket_price(ticket_price)?),
+        };
+
+        Ok(UpdateMovieParams {
+            title: request.title,
+            director: request.director,
+            release_date,
+            ticket_price,
+        })
+    }
 }
 
 pub async fn update(
     Path(id): Path<Uuid>,
+    Tenant(tenant_id): Tenant,
     State(movie_store): State<DynMovieStore>,
+    headers: HeaderMap,
     Json(request): Json<UpdateMovieRequest>,
+) -> Result<impl IntoResponse, AppError> {
+    let expected_version = parse_if_match(&headers)?;
+    let params = UpdateMovieParams::try_from(request)?;
+    let movie = movie_store
+        .update(&tenant_id, id, params, expected_version)
+        .await?;
+
+    let etag = etag(&movie);
+    let movie_response = MovieResponse::from(movie);
+    Ok(([(header::ETAG, etag)], Json(movie_response)))
+}
+
+pub async fn delete(
+    Path(id): Path<Uuid>,
+    Tenant(tenant_id): Tenant,
+    State(movie_store): State<DynMovieStore>,
+    headers: HeaderMap,
 ) -> Result<Json<MovieResponse>, AppError> {
-    let release_date = match request.release_date {
-        None => None,
-        Some(release_date) => {
-            let release_date = NaiveDateTime::from_str(&release_date);
-            let release_date = match release_date {
-                Err(_) => {
-                    return Err(AppError::ValidationError(
-                        "Invalid release date".to_string(),
-                    ))
-                }
-                Ok(release_date) => release_date,
-            };
-            Some(release_date)
+    let expected_version = parse_if_match(&headers)?;
+    let movie = movie_store.delete(&tenant_id, id, expected_version).await?;
+    let movie_response = MovieResponse::from(movie);
+    Ok(movie_response.into())
+}
+
+pub async fn restore(
+    Path(id): Path<Uuid>,
+    Tenant(tenant_id): Tenant,
+    State(movie_store): State<DynMovieStore>,
+    headers: HeaderMap,
+) -> Result<impl IntoResponse, AppError> {
+    let expected_version = parse_if_match(&headers)?;
+    let movie = movie_store
+        .restore(&tenant_id, id, expected_version)
+        .await?;
+
+    let etag = etag(&movie);
+    let movie_response = MovieResponse::from(movie);
+    Ok(([(header::ETAG, etag)], Json(movie_response)))
+}
+
+pub async fn purge(
+    Path(id): Path<Uuid>,
+    Tenant(tenant_id): Tenant,
+    State(movie_store): State<DynMovieStore>,
+    headers: HeaderMap,
+) -> Result<Json<MovieResponse>, AppError> {
+    let expected_version = parse_if_match(&headers)?;
+    let movie = movie_store.purge(&tenant_id, id, expected_version).await?;
+    let movie_response = MovieResponse::from(movie);
+    Ok(movie_response.into())
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperationRequest {
+    Create {
+        movie: CreateMovieRequest,
+    },
+    Update {
+        id: Uuid,
+        expected_version: Option<i64>,
+        movie: UpdateMovieRequest,
+    },
+    Delete {
+        id: Uuid,
+        expected_version: Option<i64>,
+    },
+}
+
+impl BatchOperationRequest {
+    fn applied_status(&self) -> BatchItemStatus {
+        match self {
+            BatchOperationRequest::Create { .. } => BatchItemStatus::Created,
+            BatchOperationRequest::Update { .. } => BatchItemStatus::Updated,
+            BatchOperationRequest::Delete { .. } => BatchItemStatus::Deleted,
         }
-    };
+    }
+}
 
-    let ticket_price = match request.ticket_price {
-        None => None,
-        Some(ticket_price) => BigDecimal::from_f64(ticket_price),
+impl TryFrom<BatchOperationRequest> for BatchOperation {
+    type Error = AppError;
+
+    fn try_from(request: BatchOperationRequest) -> Result<Self, Self::Error> {
+        Ok(match request {
+            BatchOperationRequest::Create { movie } => BatchOperation::Create(movie.try_into()?),
+            BatchOperationRequest::Update {
+                id,
+                expected_version,
+                movie,
+            } => BatchOperation::Update {
+                id,
+                movie: movie.try_into()?,
+                expected_version,
+            },
+            BatchOperationRequest::Delete {
+                id,
+                expected_version,
+            } => BatchOperation::Delete {
+                id,
+                expected_version,
+            },
+        })
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperationRequest>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchItemStatus {
+    Created,
+    Updated,
+    Deleted,
+    /// Succeeded on its own but was undone because a later operation failed.
+    RolledBack,
+    Failed,
+    /// Never attempted because an earlier operation failed.
+    NotApplied,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BatchItemResponse {
+    pub index: usize,
+    pub status: BatchItemStatus,
+    /// The version an `expected_version` or `If-Match` must name to change the movie next.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub movie: Option<MovieResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BatchResponse {
+    pub results: Vec<BatchItemResponse>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failed_index: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_message: Option<String>,
+}
+
+/// Applies every operation or none of them, a failure is reported with the status code the
+/// failing operation would have had on its own.
+pub async fn batch(
+    Tenant(tenant_id): Tenant,
+    State(movie_store): State<DynMovieStore>,
+    Json(request): Json<BatchRequest>,
+) -> Result<Response, AppError> {
+    let statuses: Vec<_> = request
+        .operations
+        .iter()
+        .map(BatchOperationRequest::applied_status)
+        .collect();
+    let mut operations = Vec::with_capacity(statuses.len());
+    for (index, operation) in request.operations.into_iter().enumerate() {
+        match BatchOperation::try_from(operation) {
+            Ok(operation) => operations.push(operation),
+            Err(error) => return Ok(batch_failure(statuses.len(), index, error)),
+        }
+    }
+
+    let movies = match movie_store.batch(&tenant_id, operations).await {
+        Ok(movies) => movies,
+        Err(BatchError {
+            index: Some(index),
+            error,
+        }) => return Ok(batch_failure(statuses.len(), index, error.into())),
+        Err(BatchError { index: None, error }) => return Err(error.into()),
+    };
+    let results = statuses
+        .into_iter()
+        .zip(movies)
+        .enumerate()
+        .map(|(index, (status, movie))| BatchItemResponse {
+            index,
+            status,
+            version: Some(movie.version),
+            movie: Some(movie.into()),
+            error_message: None,
+        })
+        .collect();
+    let batch_response = BatchResponse {
+        results,
+        failed_index: None,
+        error_message: None,
     };
 
-    let params = UpdateMovieParams {
-        title: request.title,
-        director: request.director,
-        release_date: release_date,
-        ticket_price: ticket_price,
+    Ok(Json(batch_response).into_response())
+}
+
+fn batch_failure(count: usize, failed_index: usize, error: AppError) -> Response {
+    let (status, error_message) = error.status_and_message();
+    let results = (0..count)
+        .map(|index| BatchItemResponse {
+            index,
+            status: match index.cmp(&failed_index) {
+                Ordering::Less => BatchItemStatus::RolledBack,
+                Ordering::Equal => BatchItemStatus::Failed,
+                Ordering::Greater => BatchItemStatus::NotApplied,
+            },
+            version: None,
+            movie: None,
+            error_message: (index == failed_index).then(|| error_message.to_string()),
+        })
+        .collect();
+    let batch_response = BatchResponse {
+        results,
+        failed_index: Some(failed_index),
+        error_message: Some(error_message.to_string()),
     };
-    let movie = movie_store.update(id, params).await;
-    let movie = match movie {
-        Ok(movie) => movie,
-        Err(error_message) => return Err(AppError::Unknown(error_message)),
+
+    (status, Json(batch_response)).into_response()
+}
+
+#[derive(Deserialize)]
+pub struct ExportMoviesQuery {
+    include_deleted: Option<bool>,
+}
+
+/// Newline delimited JSON, a JSON document on every line.
+pub const NDJSON_CONTENT_TYPE: &str = "application/x-ndjson";
+
+/// Streams every movie of the tenant as a line of JSON, in the order they were created. The
+/// movies are read as the response is written, an error on the way cuts the response short.
+pub async fn export(
+    Query(query): Query<ExportMoviesQuery>,
+    Tenant(tenant_id): Tenant,
+    ReadMovieStore(movie_store): ReadMovieStore,
+) -> impl IntoResponse {
+    let lines = movie_store
+        .stream_all(&tenant_id, query.include_deleted.unwrap_or(false))
+        .map(|movie| {
+            let mut line = serde_json::to_vec(&MovieResponse::from(movie?))
+                .map_err(|error| StoreError::Unknown(error.to_string()))?;
+            line.push(b'\n');
+            Ok::<_, StoreError>(line)
+        })
+        .inspect_err(|error| tracing::error!("failed to export movies: {}", error));
+
+    (
+        [(header::CONTENT_TYPE, NDJSON_CONTENT_TYPE)],
+        StreamBody::new(lines),
+    )
+}
+
+/// How many movies of an import are written to the store at a time.
+const IMPORT_CHUNK_SIZE: usize = 100;
+/// Longer lines of an import fail without being read.
+const MAX_IMPORT_LINE_BYTES: usize = 64 * 1024;
+/// A strict CSV import holds its rows until the last of them was read, a longer file is
+/// refused.
+const MAX_STRICT_IMPORT_ROWS: usize = 10_000;
+
+#[derive(Deserialize)]
+pub struct ImportMoviesQuery {
+    #[serde(default)]
+    mode: ImportMode,
+}
+
+/// A line of an import, shaped like a `MovieResponse` so that an export can be imported as it
+/// is. A line without an id creates a new movie.
+#[derive(Deserialize, Serialize)]
+pub struct ImportMovieRequest {
+    pub id: Option<Uuid>,
+    #[serde(flatten)]
+    pub movie: CreateMovieRequest,
+}
+
+impl TryFrom<ImportMovieRequest> for ImportMovie {
+    type Error = AppError;
+
+    fn try_from(request: ImportMovieRequest) -> Result<Self, Self::Error> {
+        Ok(ImportMovie {
+            id: request.id.unwrap_or_else(Uuid::new_v4),
+            movie: request.movie.try_into()?,
+        })
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+pub struct ImportResponse {
+    pub created: usize,
+    pub updated: usize,
+    pub skipped: usize,
+    pub failed: Vec<ImportFailure>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct ImportFailure {
+    /// Counting from 1.
+    pub line: usize,
+    pub error_message: String,
+}
+
+/// Imports a movie from every line of JSON, writing them in chunks as the body comes in. A line
+/// that fails is reported and doesn't stop the import, an error that fails a whole chunk does,
+/// leaving the chunks before it imported.
+pub async fn import(
+    Query(query): Query<ImportMoviesQuery>,
+    Tenant(tenant_id): Tenant,
+    State(movie_store): State<DynMovieStore>,
+    mut body: BodyStream,
+) -> Result<Json<ImportResponse>, AppError> {
+    let mut import = MovieImport::new(movie_store, tenant_id, query.mode);
+    let mut buffer = Vec::new();
+    let mut line_number = 0;
+    // set while the rest of a line that is too long is skipped
+    let mut skipping = false;
+    while let Some(bytes) = body.next().await {
+        let bytes = bytes.map_err(|error| AppError::ValidationError(error.to_string()))?;
+        buffer.extend_from_slice(&bytes);
+        while let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = buffer.drain(..=end).collect();
+            if skipping {
+                skipping = false;
+                continue;
+            }
+            line_number += 1;
+            import.add_line(line_number, &line).await?;
+        }
+        if buffer.len() > MAX_IMPORT_LINE_BYTES {
+            if !skipping {
+                line_number += 1;
+                import.add_line(line_number, &buffer).await?;
+                skipping = true;
+            }
+            buffer.clear();
+        }
+    }
+    if !skipping {
+        import.add_line(line_number + 1, &buffer).await?;
+    }
+    import.flush().await?;
+
+    Ok(Json(import.summary))
+}
+
+struct MovieImport {
+    movie_store: DynMovieStore,
+    tenant_id: String,
+    mode: ImportMode,
+    /// Movies waiting to be written, with the line each was read from.
+    pending: Vec<(usize, ImportMovie)>,
+    summary: ImportResponse,
+}
+
+impl MovieImport {
+    fn new(movie_store: DynMovieStore, tenant_id: String, mode: ImportMode) -> Self {
+        MovieImport {
+            movie_store,
+            tenant_id,
+            mode,
+            pending: Vec::with_capacity(IMPORT_CHUNK_SIZE),
+            summary: ImportResponse::default(),
+        }
+    }
+
+    async fn add_line(&mut self, line_number: usize, line: &[u8]) -> Result<(), AppError> {
+        if line.len() > MAX_IMPORT_LINE_BYTES {
+            self.fail(
+                line_number,
+                AppError::ValidationError("line is too long".to_string()),
+            );
+            return Ok(());
+        }
+        if line.iter().all(u8::is_ascii_whitespace) {
+            return Ok(());
+        }
+        let movie = serde_json::from_slice::<ImportMovieRequest>(line)
+            .map_err(|error| AppError::ValidationError(format!("invalid movie: {}", error)))
+            .and_then(ImportMovie::try_from);
+        self.add(line_number, movie).await
+    }
+
+    /// Queues a movie to be written, or reports the line it failed to be read from.
+    async fn add(
+        &mut self,
+        line_number: usize,
+        movie: Result<ImportMovie, AppError>,
+    ) -> Result<(), AppError> {
+        match movie {
+            Ok(movie) => self.pending.push((line_number, movie)),
+            Err(error) => self.fail(line_number, error),
+        }
+        if self.pending.len() == IMPORT_CHUNK_SIZE {
+            self.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> Result<(), AppError> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let (line_numbers, movies): (Vec<_>, Vec<_>) = self.pending.drain(..).unzip();
+        let outcomes = self
+            .movie_store
+            .import(&self.tenant_id, movies, self.mode)
+            .await?;
+        for (line_number, outcome) in line_numbers.into_iter().zip(outcomes) {
+            match outcome {
+                Ok(outcome) => self.count(outcome),
+                Err(error) => self.fail(line_number, error.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the movies in one go, reporting every invalid one or else the first one the
+    /// store fails to write. Nothing is written unless every movie is.
+    async fn import_all(
+        mut self,
+        movies: Vec<(usize, Result<ImportMovie, AppError>)>,
+    ) -> Result<Response, AppError> {
+        let mut line_numbers = Vec::with_capacity(movies.len());
+        let mut valid = Vec::with_capacity(movies.len());
+        for (line_number, movie) in movies {
+            match movie {
+                Ok(movie) => {
+                    line_numbers.push(line_number);
+                    valid.push(movie);
+                }
+                Err(error) => self.fail(line_number, error),
+            }
+        }
+        if !self.summary.failed.is_empty() {
+            return Ok((StatusCode::BAD_REQUEST, Json(self.summary)).into_response());
+        }
+
+        match self
+            .movie_store
+            .import_all(&self.tenant_id, valid, self.mode)
+            .await
+        {
+            Ok(outcomes) => {
+                for outcome in outcomes {
+                    self.count(outcome);
+                }
+                Ok(Json(self.summary).into_response())
+            }
+            Err(BatchError {
+                index: Some(index),
+                error,
+            }) => {
+                let error = AppError::from(error);
+                let (status, _) = error.status_and_message();
+                self.fail(line_numbers[index], error);
+                Ok((status, Json(self.summary)).into_response())
+            }
+            Err(BatchError { index: None, error }) => Err(error.into()),
+        }
+    }
+
+    fn count(&mut self, outcome: ImportOutcome) {
+        match outcome {
+            ImportOutcome::Created(_) => self.summary.created += 1,
+            ImportOutcome::Updated(_) => self.summary.updated += 1,
+            ImportOutcome::Skipped => self.summary.skipped += 1,
+        }
+    }
+
+    fn fail(&mut self, line_number: usize, error: AppError) {
+        self.summary.failed.push(ImportFailure {
+            line: line_number,
+            error_message: error.detailed_message(),
+        });
+    }
+}
+
+pub const CSV_CONTENT_TYPE: &str = "text/csv";
+
+/// The columns of a CSV export, the ones an import reads unless told otherwise.
+const CSV_COLUMNS: [&str; 8] = [
+    "id",
+    "title",
+    "director",
+    "release_date",
+    "ticket_price",
+    "created_at",
+    "updated_at",
+    "deleted_at",
+];
+
+fn accepts_csv(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(header::ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .any(|value| value.contains(CSV_CONTENT_TYPE))
+}
+
+/// Like `export`, with a header row and a row for every movie.
+pub async fn export_csv(
+    Query(query): Query<ExportMoviesQuery>,
+    Tenant(tenant_id): Tenant,
+    ReadMovieStore(movie_store): ReadMovieStore,
+) -> Response {
+    let movie_query = MovieQuery {
+        include_deleted: query.include_deleted.unwrap_or(false),
+        ..MovieQuery::default()
     };
+    csv_export(&movie_store, &tenant_id, movie_query)
+}
 
-    let movie_response = MovieResponse::from(movie);
-    Ok(movie_response.into())
+/// Writes the price with two decimals, `12.50` rather than `12.5`.
+fn csv_ticket_price(ticket_price: &BigDecimal) -> String {
+    ticket_price.round(2).with_scale(2).to_string()
 }
 
-pub async fn delete(
-    Path(id): Path<Uuid>,
+/// Streams the movies of the tenant that pass the filters of `movie_query`, its sort is ignored.
+fn csv_export(movie_store: &DynMovieStore, tenant_id: &str, movie_query: MovieQuery) -> Response {
+    let header_row = stream::once(async { csv_row(CSV_COLUMNS) });
+    let rows = movie_store
+        .stream_all(tenant_id, movie_query.include_deleted)
+        .try_filter(move |movie| future::ready(movie_query.matches(movie)))
+        .map(|movie| {
+            let movie = MovieResponse::from(movie?);
+            csv_row([
+                movie.id.to_string(),
+                movie.title,
+                movie.director,
+                movie.release_date,
+                csv_ticket_price(&movie.ticket_price),
+                movie.created_at,
+                movie.updated_at,
+                movie.deleted_at.unwrap_or_default(),
+            ])
+        })
+        .inspect_err(|error| tracing::error!("failed to export movies: {}", error));
+
+    (
+        [(header::CONTENT_TYPE, CSV_CONTENT_TYPE)],
+        StreamBody::new(header_row.chain(rows)),
+    )
+        .into_response()
+}
+
+fn csv_row<I, T>(fields: I) -> Result<Vec<u8>, StoreError>
+where
+    I: IntoIterator<Item = T>,
+    T: AsRef<[u8]>,
+{
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(fields)
+        .map_err(|error| StoreError::Unknown(error.to_string()))?;
+    writer
+        .into_inner()
+        .map_err(|error| StoreError::Unknown(error.to_string()))
+}
+
+/// Names the column each field of a movie is read from, when it isn't the column named after
+/// the field.
+#[derive(Deserialize)]
+pub struct ImportCsvQuery {
+    #[serde(default)]
+    mode: ImportMode,
+    /// Imports nothing unless every row is valid.
+    #[serde(default)]
+    strict: bool,
+    id_column: Option<String>,
+    title_column: Option<String>,
+    director_column: Option<String>,
+    release_date_column: Option<String>,
+    ticket_price_column: Option<String>,
+}
+
+/// Like `import`, with a movie from every row after the header row, written in chunks as the
+/// body comes in. Rows are checked like the body of a create. With `strict=true` the movies of
+/// up to `MAX_STRICT_IMPORT_ROWS` rows are written all together once every row was read, or
+/// not at all when a row is invalid or fails to be written.
+pub async fn import_csv(
+    Query(query): Query<ImportCsvQuery>,
+    Tenant(tenant_id): Tenant,
     State(movie_store): State<DynMovieStore>,
-) -> Result<Json<MovieResponse>, AppError> {
-    let movie = movie_store.delete(id).await;
-    match movie {
-        Err(_) => return Err(AppError::MovieNotFound),
-        Ok(movie) => {
-            let movie_response = MovieResponse::from(movie);
-            return Ok(movie_response.into());
+    mut body: BodyStream,
+) -> Result<Response, AppError> {
+    let mut import = CsvImport {
+        import: MovieImport::new(movie_store, tenant_id, query.mode),
+        columns: None,
+        held: query.strict.then(Vec::new),
+        query,
+    };
+    let mut rows = CsvRows::default();
+    while let Some(bytes) = body.next().await {
+        let bytes = bytes.map_err(|error| AppError::ValidationError(error.to_string()))?;
+        let (first_line, whole_rows) = rows.push(&bytes)?;
+        import.add_rows(first_line, &whole_rows).await?;
+    }
+    let (first_line, last_rows) = rows.finish();
+    import.add_rows(first_line, &last_rows).await?;
+    import.finish().await
+}
+
+/// Cuts a CSV body into whole rows as it comes in, a newline between quotes is part of a row.
+#[derive(Default)]
+struct CsvRows {
+    buffer: Vec<u8>,
+    /// How much of the buffer was looked through for the end of a row.
+    scanned: usize,
+    in_quotes: bool,
+    /// Lines taken out of the buffer so far.
+    lines_taken: usize,
+}
+
+impl CsvRows {
+    /// Adds the bytes and takes out every row they complete, returns them with the line the
+    /// first of them starts on.
+    fn push(&mut self, bytes: &[u8]) -> Result<(usize, Vec<u8>), AppError> {
+        self.buffer.extend_from_slice(bytes);
+        let mut end = 0;
+        for (index, byte) in self.buffer.iter().enumerate().skip(self.scanned) {
+            match byte {
+                b'"' => self.in_quotes = !self.in_quotes,
+                b'\n' if !self.in_quotes => end = index + 1,
+                _ => {}
+            }
         }
+        self.scanned = self.buffer.len() - end;
+        if self.scanned > MAX_IMPORT_LINE_BYTES {
+            // a row can't be skipped like a line, where it ends is not known
+            return Err(AppError::ValidationError(format!(
+                "invalid csv: the row on line {} is too long",
+                self.lines_taken + 1
+            )));
+        }
+        let rows: Vec<u8> = self.buffer.drain(..end).collect();
+        Ok(self.take(rows))
+    }
+
+    /// Takes out what is left after the last newline.
+    fn finish(&mut self) -> (usize, Vec<u8>) {
+        let rows = std::mem::take(&mut self.buffer);
+        self.take(rows)
     }
+
+    fn take(&mut self, rows: Vec<u8>) -> (usize, Vec<u8>) {
+        let first_line = self.lines_taken + 1;
+        self.lines_taken += rows.iter().filter(|byte| **byte == b'\n').count();
+        (first_line, rows)
+    }
+}
+
+struct CsvImport {
+    import: MovieImport,
+    query: ImportCsvQuery,
+    /// Found in the header row.
+    columns: Option<CsvColumns>,
+    /// The rows of a strict import, written in one go once the last of them was read.
+    held: Option<Vec<(usize, Result<ImportMovie, AppError>)>>,
 }
+
+impl CsvImport {
+    async fn add_rows(&mut self, first_line: usize, rows: &[u8]) -> Result<(), AppError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .flexible(true)
+            .from_reader(rows);
+        for record in reader.records() {
+            let (line_number, movie) = match record {
+                Ok(record) => {
+                    let Some(columns) = &self.columns else {
+                        self.columns = Some(CsvColumns::find(&record, &self.query)?);
+                        continue;
+                    };
+                    (
+                        csv_line(first_line, record.position()),
+                        columns.movie(&record),
+                    )
+                }
+                Err(error) => (
+                    csv_line(first_line, error.position()),
+                    Err(csv_error(error)),
+                ),
+            };
+            match &mut self.held {
+                Some(held) if held.len() == MAX_STRICT_IMPORT_ROWS => {
+                    return Err(AppError::PayloadTooLarge(format!(
+                        "a strict import takes up to {} rows",
+                        MAX_STRICT_IMPORT_ROWS
+                    )));
+                }
+                Some(held) => held.push((line_number, movie)),
+                None => self.import.add(line_number, movie).await?,
+            }
+        }
+        Ok(())
+    }
+
+    async fn finish(mut self) -> Result<Response, AppError> {
+        if self.columns.is_none() {
+            CsvColumns::find(&csv::StringRecord::new(), &self.query)?;
+        }
+        if let Some(held) = self.held.take() {
+            return self.import.import_all(held).await;
+        }
+        self.import.flush().await?;
+
+        Ok(Json(self.import.summary).into_response())
+    }
+}
+
+/// The line a row starts on, counting the header row as line 1. `first_line` is the line the
+/// rows read with it start on.
+fn csv_line(first_line: usize, position: Option<&csv::Position>) -> usize {
+    position.map_or(0, |position| first_line + position.line() as usize - 1)
+}
+
+fn csv_error(error: csv::Error) -> AppError {
+    AppError::ValidationError(format!("invalid csv: {}", error))
+}
+
+/// Where each field of a movie is in the rows of a CSV import.
+struct CsvColumns {
+    id: Option<usize>,
+    title: usize,
+    director: usize,
+    release_date: usize,
+    ticket_price: usize,
+}
+
+impl CsvColumns {
+    fn find(headers: &csv::StringRecord, query: &ImportCsvQuery) -> Result<Self, AppError> {
+        let position = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header.trim().eq_ignore_ascii_case(name.trim()))
+        };
+        let required = |column: &Option<String>, field: &str| {
+            let name = column.as_deref().unwrap_or(field);
+            position(name)
+                .ok_or_else(|| AppError::ValidationError(format!("missing column '{}'", name)))
+        };
+        Ok(CsvColumns {
+            // without an id column every row is a new movie
+            id: match &query.id_column {
+                None => position("id"),
+                Some(_) => Some(required(&query.id_column, "id")?),
+            },
+            title: required(&query.title_column, "title")?,
+            director: required(&query.director_column, "director")?,
+            release_date: required(&query.release_date_column, "release_date")?,
+            ticket_price: required(&query.ticket_price_column, "ticket_price")?,
+        })
+    }
+
+    fn movie(&self, record: &csv::StringRecord) -> Result<ImportMovie, AppError> {
+        let cell = |index: usize| record.get(index).unwrap_or_default();
+        let id = match self.id.map(cell).map(str::trim) {
+            None | Some("") => None,
+            Some(id) => Some(
+                Uuid::parse_str(id)
+                    .map_err(|_| AppError::ValidationError("Invalid id".to_string()))?,
+            ),
+        };
+        let ticket_price = f64::from_str(cell(self.ticket_price).trim())
+            .map_err(|_| AppError::ValidationError("Invalid ticket price".to_string()))?;
+        let request = ImportMovieRequest {
+            id,
+            movie: CreateMovieRequest {
+                title: cell(self.title).to_string(),
+                director: cell(self.director).to_string(),
+                release_date: cell(self.release_date).trim().to_string(),
+                ticket_price,
+            },
+        };
+        ImportMovie::try_from(request)
+    }
+}
+
+#[derive(Debug)]
 pub enum AppError {
     MovieNotFound,
     ValidationError(String),
+    Conflict(String),
+    /// The id of the movie with the same title, director and release date.
+    DuplicateMovie(Uuid),
+    IdempotencyKeyReused,
+    PreconditionFailed,
+    ChangeCursorExpired,
+    ServiceUnavailable(String),
+    Timeout,
+    PayloadTooLarge(String),
     Unknown(String),
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, error_message) = match self {
+impl From<StoreError> for AppError {
+    fn from(error: StoreError) -> Self {
+        match error {
+            StoreError::NotFound => AppError::MovieNotFound,
+            StoreError::Conflict(error_message) => AppError::Conflict(error_message),
+            StoreError::Duplicate(id) => AppError::DuplicateMovie(id),
+            StoreError::VersionMismatch => AppError::PreconditionFailed,
+            StoreError::Validation(error_message) => AppError::ValidationError(error_message),
+            StoreError::Unavailable(error_message) => AppError::ServiceUnavailable(error_message),
+            StoreError::Timeout => AppError::Timeout,
+            StoreError::CursorExpired => AppError::ChangeCursorExpired,
+            StoreError::Migration(error_message) | StoreError::Unknown(error_message) => {
+                AppError::Unknown(error_message)
+            }
+        }
+    }
+}
+
+impl AppError {
+    fn body(&self) -> Value {
+        let (_status, error_message) = self.status_and_message();
+        match self {
+            AppError::DuplicateMovie(existing_id) => {
+                json!({ "error_message": error_message, "existing_id": existing_id })
+            }
+            _ => json!({ "error_message": error_message }),
+        }
+    }
+
+    /// The message with the detail of a validation error, for reports that list the errors of
+    /// many items rather than failing the request.
+    fn detailed_message(&self) -> String {
+        match self {
+            AppError::ValidationError(error_message) => error_message.clone(),
+            _ => self.status_and_message().1.to_string(),
+        }
+    }
+
+    fn status_and_message(&self) -> (StatusCode, &'static str) {
+        match self {
             AppError::MovieNotFound => (StatusCode::NOT_FOUND, "Movie not found"),
             AppError::ValidationError(_error_message) => {
                 (StatusCode::BAD_REQUEST, "validation error")
             }
+            AppError::Conflict(_error_message) => (StatusCode::CONFLICT, "conflict"),
+            AppError::DuplicateMovie(_existing_id) => {
+                (StatusCode::CONFLICT, "movie already exists")
+            }
+            AppError::IdempotencyKeyReused => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "idempotency key was used for a different request",
+            ),
+            AppError::PreconditionFailed => {
+                (StatusCode::PRECONDITION_FAILED, "precondition failed")
+            }
+            AppError::ChangeCursorExpired => (
+                StatusCode::GONE,
+                "change cursor expired, read the changes again from the start",
+            ),
+            AppError::ServiceUnavailable(_error_message) => {
+                (StatusCode::SERVICE_UNAVAILABLE, "service unavailable")
+            }
+            AppError::Timeout => (StatusCode::GATEWAY_TIMEOUT, "timeout"),
+            AppError::PayloadTooLarge(_error_message) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, "payload too large")
+            }
             AppError::Unknown(_error_message) => {
                 (StatusCode::INTERNAL_SERVER_ERROR, "unknown error")
             }
-        };
+        }
+    }
+}
 
-        let body = Json(json!({ "error_message": error_message }));
-        (status, body).into_response()
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, _error_message) = self.status_and_message();
+        (status, Json(self.body())).into_response()
     }
 }
@@ -0,0 +1,335 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use axum::async_trait;
+use chrono::Utc;
+use parking_lot::Mutex;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::store::store::{
+    BatchError, BatchOperation, ChangeCursor, ChangePage, CreateMovieParams, DynMovieStore,
+    ImportMode, ImportMovie, ImportOutcome, Movie, MovieCursor, MovieEvent, MovieEventKind,
+    MoviePage, MovieQuery, MovieRevision, MovieSearchResult, MovieStats, MovieStatsQuery,
+    MovieStore, MovieStream, StoreError, UpdateMovieParams,
+};
+
+/// Hands the movies written through this instance to the live subscribers of `/movies/events`,
+/// keeping the latest events so a subscriber that reconnects can pick up where it left off.
+#[derive(Clone)]
+pub struct MovieEventHub {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    sender: broadcast::Sender<MovieEvent>,
+    recent: Mutex<RecentEvents>,
+}
+
+struct RecentEvents {
+    events: VecDeque<MovieEvent>,
+    capacity: usize,
+    /// The id of the last event published. Ids are handed out by the hub as events are
+    /// published, so they only ever go up, and start over from 1 when the process restarts.
+    last_id: i64,
+    /// The id of the last event dropped from the buffer to make room.
+    last_dropped_id: i64,
+    /// The version and kind of the last event published for each movie, to tell a write that
+    /// changed the movie from one that changed nothing or finished after a later one.
+    published: HashMap<Uuid, (i64, MovieEventKind)>,
+}
+
+/// What a subscriber gets on top of the events published after it subscribed.
+pub struct MovieEventSubscription {
+    /// Buffered events after the last one the subscriber saw, oldest first.
+    pub missed: Vec<MovieEvent>,
+    /// Set when events the subscriber hasn't seen are no longer buffered.
+    pub lagged: bool,
+    pub receiver: broadcast::Receiver<MovieEvent>,
+}
+
+impl MovieEventHub {
+    /// Buffers up to `capacity` events for subscribers that reconnect, a subscriber that falls
+    /// further behind than that is told it lagged.
+    pub fn new(capacity: usize) -> Self {
+        let capacity = capacity.max(1);
+        let (sender, _) = broadcast::channel(capacity);
+        let recent = RecentEvents {
+            events: VecDeque::with_capacity(capacity),
+            capacity,
+            last_id: 0,
+            last_dropped_id: 0,
+            published: HashMap::new(),
+        };
+        Self {
+            inner: Arc::new(Inner {
+                sender,
+                recent: Mutex::new(recent),
+            }),
+        }
+    }
+
+    /// Subscribes to the events published from now on, along with the buffered events after
+    /// `last_event_id` when resuming.
+    pub fn subscribe(&self, last_event_id: Option<i64>) -> MovieEventSubscription {
+        // publishing takes the same lock, so no event is both missed and received or neither
+        let recent = self.inner.recent.lock();
+        let receiver = self.inner.sender.subscribe();
+        let Some(last_event_id) = last_event_id else {
+            return MovieEventSubscription {
+                missed: Vec::new(),
+                lagged: false,
+                receiver,
+            };
+        };
+
+        // an id from the future was handed out before a restart, the buffer can't tell what
+        // came after it
+        let restarted = last_event_id > recent.last_id;
+        let missed = recent
+            .events
+            .iter()
+            .filter(|event| restarted || event.id > last_event_id)
+            .cloned()
+            .collect();
+        MovieEventSubscription {
+            missed,
+            lagged: restarted || last_event_id < recent.last_dropped_id,
+            receiver,
+        }
+    }
+
+    /// Publishes a write as the next event, unless the version of the movie it left was
+    /// published already. That leaves out writes that changed nothing and writes that finished
+    /// after a later write to the same movie, which would hand subscribers the older movie.
+    fn publish(&self, kind: MovieEventKind, movie: Movie) {
+        let mut recent = self.inner.recent.lock();
+        let is_new = match recent.published.get(&movie.id) {
+            None => true,
+            // purging keeps the version, it is news unless the movie was deleted already
+            Some((version, last_kind)) => {
+                movie.version > *version
+                    || (movie.version == *version
+                        && kind == MovieEventKind::Deleted
+                        && *last_kind != MovieEventKind::Deleted)
+            }
+        };
+        if !is_new {
+            return;
+        }
+        recent.published.insert(movie.id, (movie.version, kind));
+        recent.last_id += 1;
+        let event = MovieEvent {
+            id: recent.last_id,
+            kind,
+            movie_id: movie.id,
+            payload: movie,
+            occurred_at: Utc::now().naive_utc(),
+        };
+        if recent.events.len() == recent.capacity {
+            if let Some(dropped) = recent.events.pop_front() {
+                recent.last_dropped_id = dropped.id;
+            }
+        }
+        recent.events.push_back(event.clone());
+        // fails only when nobody is subscribed
+        let _ = self.inner.sender.send(event);
+    }
+
+    /// Forgets the versions published for a purged movie, a movie imported with its id later
+    /// starts over from the first version.
+    fn forget(&self, id: &Uuid) {
+        self.inner.recent.lock().published.remove(id);
+    }
+}
+
+/// Publishes every write through the wrapped movie store to `hub` once it succeeded. Writes
+/// that change nothing are not published.
+#[derive(Clone)]
+pub struct PublishingMovieStore {
+    inner: DynMovieStore,
+    hub: MovieEventHub,
+}
+
+impl PublishingMovieStore {
+    pub fn new(inner: DynMovieStore, hub: MovieEventHub) -> Self {
+        Self { inner, hub }
+    }
+
+    fn publish_import(&self, outcome: &ImportOutcome) {
+        match outcome {
+            ImportOutcome::Created(movie) => {
+                self.hub.publish(MovieEventKind::Created, movie.clone())
+            }
+            ImportOutcome::Updated(movie) => {
+                self.hub.publish(MovieEventKind::Updated, movie.clone())
+            }
+            ImportOutcome::Skipped => {}
+        }
+    }
+}
+
+#[async_trait]
+impl MovieStore for PublishingMovieStore {
+    async fn get_all(
+        &self,
+        tenant_id: &str,
+        include_deleted: bool,
+    ) -> Result<Vec<Movie>, StoreError> {
+        self.inner.get_all(tenant_id, include_deleted).await
+    }
+
+    fn stream_all(&self, tenant_id: &str, include_deleted: bool) -> MovieStream {
+        self.inner.stream_all(tenant_id, include_deleted)
+    }
+
+    async fn find(
+        &self,
+        tenant_id: &str,
+        query: &MovieQuery,
+        cursor: Option<MovieCursor>,
+        limit: u32,
+    ) -> Result<MoviePage, StoreError> {
+        self.inner.find(tenant_id, query, cursor, limit).await
+    }
+
+    async fn search(
+        &self,
+        tenant_id: &str,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<MovieSearchResult>, StoreError> {
+        self.inner.search(tenant_id, query, limit).await
+    }
+
+    async fn stats(
+        &self,
+        tenant_id: &str,
+        query: &MovieStatsQuery,
+    ) -> Result<MovieStats, StoreError> {
+        self.inner.stats(tenant_id, query).await
+    }
+
+    async fn get_by_id(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        include_deleted: bool,
+    ) -> Result<Movie, StoreError> {
+        self.inner.get_by_id(tenant_id, id, include_deleted).await
+    }
+
+    async fn create(&self, tenant_id: &str, movie: CreateMovieParams) -> Result<Movie, StoreError> {
+        let movie = self.inner.create(tenant_id, movie).await?;
+        self.hub.publish(MovieEventKind::Created, movie.clone());
+        Ok(movie)
+    }
+
+    async fn update(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        movie: UpdateMovieParams,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let movie = self
+            .inner
+            .update(tenant_id, id, movie, expected_version)
+            .await?;
+        self.hub.publish(MovieEventKind::Updated, movie.clone());
+        Ok(movie)
+    }
+
+    async fn delete(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let movie = self.inner.delete(tenant_id, id, expected_version).await?;
+        self.hub.publish(MovieEventKind::Deleted, movie.clone());
+        Ok(movie)
+    }
+
+    async fn restore(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let movie = self.inner.restore(tenant_id, id, expected_version).await?;
+        self.hub.publish(MovieEventKind::Updated, movie.clone());
+        Ok(movie)
+    }
+
+    async fn purge(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let movie = self.inner.purge(tenant_id, id, expected_version).await?;
+        self.hub.publish(MovieEventKind::Deleted, movie.clone());
+        self.hub.forget(&movie.id);
+        Ok(movie)
+    }
+
+    async fn history(&self, tenant_id: &str, id: Uuid) -> Result<Vec<MovieRevision>, StoreError> {
+        self.inner.history(tenant_id, id).await
+    }
+
+    async fn batch(
+        &self,
+        tenant_id: &str,
+        operations: Vec<BatchOperation>,
+    ) -> Result<Vec<Movie>, BatchError> {
+        let kinds: Vec<MovieEventKind> = operations
+            .iter()
+            .map(|operation| match operation {
+                BatchOperation::Create(_) => MovieEventKind::Created,
+                BatchOperation::Update { .. } => MovieEventKind::Updated,
+                BatchOperation::Delete { .. } => MovieEventKind::Deleted,
+            })
+            .collect();
+        let movies = self.inner.batch(tenant_id, operations).await?;
+        for (kind, movie) in kinds.into_iter().zip(&movies) {
+            self.hub.publish(kind, movie.clone());
+        }
+        Ok(movies)
+    }
+
+    async fn import(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<Result<ImportOutcome, StoreError>>, StoreError> {
+        let outcomes = self.inner.import(tenant_id, movies, mode).await?;
+        for outcome in outcomes.iter().flatten() {
+            self.publish_import(outcome);
+        }
+        Ok(outcomes)
+    }
+
+    async fn import_all(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<ImportOutcome>, BatchError> {
+        let outcomes = self.inner.import_all(tenant_id, movies, mode).await?;
+        for outcome in &outcomes {
+            self.publish_import(outcome);
+        }
+        Ok(outcomes)
+    }
+
+    async fn changes(
+        &self,
+        tenant_id: &str,
+        since: Option<ChangeCursor>,
+        limit: u32,
+    ) -> Result<ChangePage, StoreError> {
+        self.inner.changes(tenant_id, since, limit).await
+    }
+}
@@ -1,5 +1,7 @@
 pub mod configuration;
 pub mod controllers;
+pub mod events;
+pub mod outbox;
 pub mod startup;
 pub mod store;
 pub mod telemetry;
@@ -0,0 +1,146 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use tokio::fs::{self, OpenOptions};
+use tokio::io::AsyncWriteExt;
+
+use crate::store::store::{DynOutboxStore, MovieEvent};
+
+/// How long a batch stays claimed by the dispatcher that took it, another dispatcher picks the
+/// events up once the claim lapses.
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub type DynEventSink = Arc<dyn EventSink + Send + Sync>;
+
+/// Somewhere movie change events are delivered to.
+#[async_trait]
+pub trait EventSink {
+    fn name(&self) -> &'static str;
+    /// Delivers the events in the order given. On error every event is delivered again later,
+    /// so sinks may see an event more than once.
+    async fn deliver(&self, events: &[MovieEvent]) -> Result<(), anyhow::Error>;
+}
+
+/// Writes every event to the application log.
+pub struct LogSink;
+
+#[async_trait]
+impl EventSink for LogSink {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn deliver(&self, events: &[MovieEvent]) -> Result<(), anyhow::Error> {
+        for event in events {
+            tracing::info!(
+                event_id = event.id,
+                kind = event.kind.name(),
+                movie_id = %event.movie_id,
+                "movie {}",
+                event.kind.name()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Appends every event as a line of JSON to a local file.
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: impl Into<PathBuf>) -> FileSink {
+        Self { path: path.into() }
+    }
+}
+
+#[async_trait]
+impl EventSink for FileSink {
+    fn name(&self) -> &'static str {
+        "file"
+    }
+
+    async fn deliver(&self, events: &[MovieEvent]) -> Result<(), anyhow::Error> {
+        let mut lines = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut lines, event)?;
+            lines.push(b'\n');
+        }
+
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory).await?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(&lines).await?;
+        file.sync_data().await?;
+        Ok(())
+    }
+}
+
+/// Moves events from the outbox to the sinks, an event is marked delivered once every sink has
+/// taken it.
+pub struct Dispatcher {
+    outbox_store: DynOutboxStore,
+    sinks: Vec<DynEventSink>,
+    batch_size: u32,
+}
+
+impl Dispatcher {
+    pub fn new(outbox_store: DynOutboxStore, sinks: Vec<DynEventSink>, batch_size: u32) -> Self {
+        Self {
+            outbox_store,
+            sinks,
+            batch_size,
+        }
+    }
+
+    /// Claims the oldest pending events, up to one batch, delivers them and returns how many
+    /// there were. When a sink fails the claim is released so the batch is retried.
+    pub async fn dispatch(&self) -> Result<usize, anyhow::Error> {
+        let claimed_until = chrono::Utc::now().naive_utc() + CLAIM_TIMEOUT;
+        let events = self
+            .outbox_store
+            .claim_pending_events(self.batch_size, claimed_until)
+            .await?;
+        if events.is_empty() {
+            return Ok(0);
+        }
+
+        let ids: Vec<i64> = events.iter().map(|event| event.id).collect();
+        for sink in &self.sinks {
+            if let Err(error) = sink.deliver(&events).await {
+                if let Err(release_error) = self.outbox_store.release_events(&ids).await {
+                    tracing::warn!("failed to release movie events: {}", release_error);
+                }
+                return Err(error.context(format!("{} sink", sink.name())));
+            }
+        }
+        self.outbox_store.mark_delivered(&ids).await?;
+
+        Ok(events.len())
+    }
+
+    /// Dispatches in the background, a full batch is followed by the next one right away,
+    /// otherwise the outbox is polled every `poll_interval`.
+    pub fn spawn(self, poll_interval: Duration) {
+        tokio::spawn(async move {
+            loop {
+                match self.dispatch().await {
+                    Ok(delivered) if delivered > 0 && delivered == self.batch_size as usize => {
+                        continue
+                    }
+                    Ok(_) => {}
+                    Err(error) => tracing::error!("failed to dispatch movie events: {:#}", error),
+                }
+                tokio::time::sleep(poll_interval).await;
+            }
+        });
+    }
+}
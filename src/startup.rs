@@ -1,16 +1,33 @@
-use crate::configuration::{Configuration, DatabaseConfiguration};
+use crate::configuration::{
+    Configuration, DatabaseConfiguration, EventsConfiguration, IdempotencyConfiguration,
+    OutboxConfiguration, PaginationConfiguration,
+};
+use crate::controllers::movies::ReplicaMovieStore;
 use crate::controllers::{health, movies};
+use crate::events::{MovieEventHub, PublishingMovieStore};
+use crate::outbox::{Dispatcher, DynEventSink, FileSink, LogSink};
+use crate::store::cached_store::CachedStore;
 use crate::store::memory_store::MemoryStore;
 use crate::store::sql_store::SqlStore;
-use crate::store::store::DynStore;
-use axum::{routing::get, Router};
+use crate::store::sqlite_store::SqliteStore;
+use crate::store::store::{DynIdempotencyStore, DynMovieStore, DynStore, StoreError};
+use axum::extract::FromRef;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use axum::{Json, Router};
+use serde_json::json;
 use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
-use sqlx::{ConnectOptions, PgPool};
+use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
+use sqlx::{ConnectOptions, PgPool, SqlitePool};
+use std::any::Any;
 use std::net::SocketAddr;
+use std::path::Path;
 use std::str::FromStr;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::signal;
+use tower_http::catch_panic::CatchPanicLayer;
 use tracing::log::LevelFilter;
 
 pub struct Application {
@@ -20,19 +37,88 @@ pub struct Application {
 
 impl Application {
     pub async fn build(configuration: Configuration) -> Result<Self, anyhow::Error> {
-        let connection_pool = get_connection_pool(&configuration.database);
         let dyn_store = match configuration.database.store_type.as_ref() {
-            "sql" => Arc::new(SqlStore::new(connection_pool)) as DynStore,
-            _ => Arc::new(MemoryStore::new()) as DynStore,
+            "sql" => {
+                let connection_pool = get_connection_pool(&configuration.database);
+                let read_connection_pool = get_read_connection_pool(&configuration.database);
+                Arc::new(SqlStore::with_read_pool(
+                    connection_pool,
+                    read_connection_pool,
+                )) as DynStore
+            }
+            "sqlite" => {
+                let connection_pool = get_sqlite_connection_pool(&configuration.database).await?;
+                Arc::new(SqliteStore::new(connection_pool)) as DynStore
+            }
+            _ => {
+                let persistence = &configuration.database.persistence;
+                if persistence.enabled {
+                    let memory_store = MemoryStore::open(Path::new(&persistence.directory))?
+                        .with_outbox(configuration.outbox.enabled)
+                        .with_change_retention(configuration.database.change_retention);
+                    spawn_snapshots(
+                        memory_store.clone(),
+                        Duration::from_secs(persistence.snapshot_interval_seconds),
+                    );
+                    Arc::new(memory_store) as DynStore
+                } else {
+                    let memory_store = MemoryStore::new()
+                        .with_outbox(configuration.outbox.enabled)
+                        .with_change_retention(configuration.database.change_retention);
+                    Arc::new(memory_store) as DynStore
+                }
+            }
         };
 
+        // an in memory sqlite database starts empty every time, so it is always migrated
+        let database = &configuration.database;
+        let run_migrations = database.run_migrations
+            || (database.store_type == "sqlite" && database.database_url.contains(":memory:"));
+        match dyn_store.check_schema_version().await {
+            // an unreachable database is reported by /health, it isn't a reason to refuse to start
+            Err(StoreError::Unavailable(error_message)) if !run_migrations => {
+                tracing::warn!(
+                    "could not check the database schema version: {}",
+                    error_message
+                )
+            }
+            result => result?,
+        }
+        if run_migrations {
+            dyn_store.migrate().await?;
+        }
+
+        let cache = &configuration.cache;
+        let dyn_store = if cache.enabled {
+            let cached_store = CachedStore::new(
+                dyn_store,
+                cache.capacity,
+                Duration::from_secs(cache.ttl_seconds),
+            )
+            .await;
+            Arc::new(cached_store) as DynStore
+        } else {
+            dyn_store
+        };
+
+        let outbox = &configuration.outbox;
+        if outbox.enabled {
+            let dispatcher = Dispatcher::new(
+                dyn_store.outbox_store().await,
+                event_sinks(outbox),
+                outbox.batch_size,
+            );
+            dispatcher.spawn(Duration::from_millis(outbox.poll_interval_ms));
+        }
+
         let address = format!(
             "{}:{}",
             configuration.http_server.host, configuration.http_server.port
         );
         let socket_addr: SocketAddr = address.parse().expect("invalid host address");
 
-        let app = app(dyn_store).await;
+        let events = MovieEventHub::new(configuration.events.buffer_size);
+        let app = app(dyn_store, &configuration, events).await;
 
         Ok(Self { socket_addr, app })
     }
@@ -47,24 +133,138 @@ impl Application {
     }
 }
 
-pub async fn app(store: DynStore) -> Router {
-    let movie_store = store.movie_store().await;
+/// State shared by all handlers, each handler extracts the parts it needs.
+#[derive(Clone)]
+pub struct AppState {
+    pub store: DynStore,
+    pub movie_store: DynMovieStore,
+    pub replica_movie_store: ReplicaMovieStore,
+    pub idempotency_store: DynIdempotencyStore,
+    pub pagination: PaginationConfiguration,
+    pub idempotency: IdempotencyConfiguration,
+    pub events: MovieEventHub,
+    pub events_configuration: EventsConfiguration,
+}
+
+impl FromRef<AppState> for DynStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.store.clone()
+    }
+}
+
+impl FromRef<AppState> for DynMovieStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.movie_store.clone()
+    }
+}
+
+impl FromRef<AppState> for ReplicaMovieStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.replica_movie_store.clone()
+    }
+}
+
+impl FromRef<AppState> for DynIdempotencyStore {
+    fn from_ref(state: &AppState) -> Self {
+        state.idempotency_store.clone()
+    }
+}
+
+impl FromRef<AppState> for PaginationConfiguration {
+    fn from_ref(state: &AppState) -> Self {
+        state.pagination.clone()
+    }
+}
+
+impl FromRef<AppState> for IdempotencyConfiguration {
+    fn from_ref(state: &AppState) -> Self {
+        state.idempotency.clone()
+    }
+}
+
+impl FromRef<AppState> for MovieEventHub {
+    fn from_ref(state: &AppState) -> Self {
+        state.events.clone()
+    }
+}
+
+impl FromRef<AppState> for EventsConfiguration {
+    fn from_ref(state: &AppState) -> Self {
+        state.events_configuration.clone()
+    }
+}
+
+/// Every write through the app is published to `events`.
+pub async fn app(store: DynStore, configuration: &Configuration, events: MovieEventHub) -> Router {
+    let movie_store = PublishingMovieStore::new(store.movie_store().await, events.clone());
+    let movie_store = Arc::new(movie_store) as DynMovieStore;
+    let replica_movie_store = ReplicaMovieStore(store.replica_movie_store().await);
+    let idempotency_store = store.idempotency_store().await;
+    let state = AppState {
+        store,
+        movie_store,
+        replica_movie_store,
+        idempotency_store,
+        pagination: configuration.pagination.clone(),
+        idempotency: configuration.idempotency.clone(),
+        events,
+        events_configuration: configuration.events.clone(),
+    };
 
     Router::new()
-        .route("/health", get(health::get).with_state(store))
+        .route("/health", get(health::get))
         .route("/movies", get(movies::list).post(movies::create))
+        .route("/movies.csv", get(movies::export_csv))
+        .route("/movies/search", get(movies::search))
+        .route("/movies/stats", get(movies::stats))
+        .route("/movies/changes", get(movies::changes))
+        .route("/movies/events", get(movies::events))
+        .route("/movies/batch", post(movies::batch))
+        .route("/movies/export", get(movies::export))
+        .route("/movies/import", post(movies::import))
+        .route("/movies/import/csv", post(movies::import_csv))
         .route(
             "/movies/:id",
             get(movies::get).put(movies::update).delete(movies::delete),
         )
-        .with_state(movie_store)
+        .route("/movies/:id/restore", post(movies::restore))
+        .route("/movies/:id/purge", delete(movies::purge))
+        .route("/movies/:id/history", get(movies::history))
+        .route("/movies/:id/revert/:revision", post(movies::revert))
+        .with_state(state)
+        .layer(CatchPanicLayer::custom(handle_panic))
+}
+
+fn handle_panic(error: Box<dyn Any + Send + 'static>) -> Response {
+    let details = if let Some(message) = error.downcast_ref::<String>() {
+        message.as_str()
+    } else if let Some(message) = error.downcast_ref::<&str>() {
+        message
+    } else {
+        "unknown panic message"
+    };
+    tracing::error!("request handler panicked: {}", details);
+
+    let body = Json(json!({ "error_message": "unknown error" }));
+    (StatusCode::INTERNAL_SERVER_ERROR, body).into_response()
 }
 
 pub fn get_connection_pool(configuration: &DatabaseConfiguration) -> PgPool {
+    connect_lazy(configuration, &configuration.database_url)
+}
+
+/// Returns a pool for `read_database_url`, `None` when no read replica is configured.
+pub fn get_read_connection_pool(configuration: &DatabaseConfiguration) -> Option<PgPool> {
+    match configuration.read_database_url.as_deref() {
+        None | Some("") => None,
+        Some(read_database_url) => Some(connect_lazy(configuration, read_database_url)),
+    }
+}
+
+fn connect_lazy(configuration: &DatabaseConfiguration, database_url: &str) -> PgPool {
     let mut connect_options =
-        PgConnectOptions::from_str(&configuration.database_url).expect("invalid connection string");
-    let log_level =
-        LevelFilter::from_str(&configuration.log_level).unwrap_or_else(|_| LevelFilter::Error);
+        PgConnectOptions::from_str(database_url).expect("invalid connection string");
+    let log_level = LevelFilter::from_str(&configuration.log_level).unwrap_or(LevelFilter::Error);
     connect_options.log_statements(log_level);
 
     PgPoolOptions::new()
@@ -73,6 +273,61 @@ pub fn get_connection_pool(configuration: &DatabaseConfiguration) -> PgPool {
         .connect_lazy_with(connect_options)
 }
 
+pub async fn get_sqlite_connection_pool(
+    configuration: &DatabaseConfiguration,
+) -> Result<SqlitePool, sqlx::Error> {
+    let mut connect_options =
+        SqliteConnectOptions::from_str(&configuration.database_url)?.create_if_missing(true);
+    let log_level = LevelFilter::from_str(&configuration.log_level).unwrap_or(LevelFilter::Error);
+    connect_options.log_statements(log_level);
+
+    let pool_options = if configuration.database_url.contains(":memory:") {
+        // an in memory database lives as long as its connection, keep exactly one open
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+    } else {
+        SqlitePoolOptions::new().max_connections(configuration.max_open_connections)
+    };
+
+    pool_options
+        .acquire_timeout(Duration::from_secs(2))
+        .connect_with(connect_options)
+        .await
+}
+
+fn event_sinks(configuration: &OutboxConfiguration) -> Vec<DynEventSink> {
+    let mut sinks = Vec::new();
+    if configuration.log_sink {
+        sinks.push(Arc::new(LogSink) as DynEventSink);
+    }
+    if !configuration.file_sink_path.is_empty() {
+        sinks.push(Arc::new(FileSink::new(&configuration.file_sink_path)) as DynEventSink);
+    }
+    sinks
+}
+
+/// Periodically compacts the memory store's change log into a snapshot.
+fn spawn_snapshots(memory_store: MemoryStore, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // the first tick completes immediately and the store was just compacted when opened
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let memory_store = memory_store.clone();
+            match tokio::task::spawn_blocking(move || memory_store.snapshot()).await {
+                Ok(Ok(())) => tracing::debug!("memory store snapshot written"),
+                Ok(Err(error)) => {
+                    tracing::error!("failed to write memory store snapshot: {}", error)
+                }
+                Err(error) => tracing::error!("memory store snapshot task failed: {}", error),
+            }
+        }
+    });
+}
+
 async fn shutdown_signal() {
     let ctrl_c = async {
         signal::ctrl_c()
@@ -1,3 +1,7 @@
+pub mod cached_store;
+pub mod memory_journal;
 pub mod memory_store;
 pub mod sql_store;
+pub mod sqlite_store;
+#[allow(clippy::module_inception)]
 pub mod store;
@@ -0,0 +1,357 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use axum::async_trait;
+use parking_lot::Mutex;
+use uuid::Uuid;
+
+use super::store::{
+    BatchError, BatchOperation, ChangeCursor, ChangePage, CreateMovieParams, DynIdempotencyStore,
+    DynMovieStore, DynOutboxStore, DynStore, ImportMode, ImportMovie, ImportOutcome, Movie,
+    MovieCursor, MoviePage, MovieQuery, MovieRevision, MovieSearchResult, MovieStats,
+    MovieStatsQuery, MovieStore, MovieStream, Store, StoreError, UpdateMovieParams,
+};
+
+/// Wraps a store so that its movie store is a `CachedMovieStore`.
+pub struct CachedStore {
+    inner: DynStore,
+    movie_store: CachedMovieStore,
+}
+
+impl CachedStore {
+    pub async fn new(inner: DynStore, capacity: usize, ttl: Duration) -> CachedStore {
+        let movie_store = CachedMovieStore::new(inner.movie_store().await, capacity, ttl);
+        Self { inner, movie_store }
+    }
+}
+
+#[async_trait]
+impl Store for CachedStore {
+    async fn is_connected(&self) -> bool {
+        self.inner.is_connected().await
+    }
+
+    async fn movie_store(&self) -> DynMovieStore {
+        Arc::new(self.movie_store.clone()) as DynMovieStore
+    }
+
+    async fn outbox_store(&self) -> DynOutboxStore {
+        self.inner.outbox_store().await
+    }
+
+    async fn idempotency_store(&self) -> DynIdempotencyStore {
+        self.inner.idempotency_store().await
+    }
+
+    /// Replica reads are not cached, a stale replica must not hide writes from primary reads.
+    async fn replica_movie_store(&self) -> Option<DynMovieStore> {
+        self.inner.replica_movie_store().await
+    }
+
+    async fn is_replica_connected(&self) -> Option<bool> {
+        self.inner.is_replica_connected().await
+    }
+
+    async fn movie_cache_stats(&self) -> Option<CacheStats> {
+        Some(self.movie_store.stats())
+    }
+
+    async fn migrate(&self) -> Result<(), StoreError> {
+        self.inner.migrate().await
+    }
+
+    async fn check_schema_version(&self) -> Result<(), StoreError> {
+        self.inner.check_schema_version().await
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Entries dropped to make room for another, expired entries are not counted.
+    pub evictions: u64,
+}
+
+/// Keeps the most recently read `get_by_id` results of the wrapped store for up to `ttl`.
+/// Every other read goes straight to the wrapped store and every write invalidates the movies
+/// it touched.
+#[derive(Clone)]
+pub struct CachedMovieStore {
+    inner: DynMovieStore,
+    cache: Arc<Mutex<Lru>>,
+}
+
+impl CachedMovieStore {
+    pub fn new(inner: DynMovieStore, capacity: usize, ttl: Duration) -> CachedMovieStore {
+        let cache = Lru {
+            capacity,
+            ttl,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            tick: 0,
+            generation: 0,
+            stats: CacheStats::default(),
+        };
+        Self {
+            inner,
+            cache: Arc::new(Mutex::new(cache)),
+        }
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        self.cache.lock().stats
+    }
+
+    fn invalidate(&self, tenant_id: &str, ids: impl IntoIterator<Item = Uuid>) {
+        let mut cache = self.cache.lock();
+        for id in ids {
+            cache.remove(&(tenant_id.to_string(), id, false));
+            cache.remove(&(tenant_id.to_string(), id, true));
+        }
+        cache.generation += 1;
+    }
+}
+
+type CacheKey = (String, Uuid, bool);
+
+struct CacheEntry {
+    movie: Movie,
+    expires_at: Instant,
+    used_at: u64,
+}
+
+struct Lru {
+    capacity: usize,
+    ttl: Duration,
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// Keys by the tick they were last used at, least recently used first.
+    recency: BTreeMap<u64, CacheKey>,
+    tick: u64,
+    /// Bumped by every write, a read that started before a write must not cache what it read.
+    generation: u64,
+    stats: CacheStats,
+}
+
+impl Lru {
+    fn get(&mut self, key: &CacheKey) -> Option<Movie> {
+        let expired = match self.entries.get(key) {
+            None => {
+                self.stats.misses += 1;
+                return None;
+            }
+            Some(entry) => entry.expires_at <= Instant::now(),
+        };
+        if expired {
+            self.remove(key);
+            self.stats.misses += 1;
+            return None;
+        }
+
+        self.tick += 1;
+        let entry = self.entries.get_mut(key).unwrap();
+        self.recency.remove(&entry.used_at);
+        self.recency.insert(self.tick, key.clone());
+        entry.used_at = self.tick;
+        self.stats.hits += 1;
+        Some(entry.movie.clone())
+    }
+
+    fn insert(&mut self, key: CacheKey, movie: Movie, generation: u64) {
+        if generation != self.generation || self.capacity == 0 {
+            return;
+        }
+
+        self.remove(&key);
+        while self.entries.len() >= self.capacity {
+            let (_, oldest) = self.recency.pop_first().unwrap();
+            self.entries.remove(&oldest);
+            self.stats.evictions += 1;
+        }
+        self.tick += 1;
+        self.recency.insert(self.tick, key.clone());
+        self.entries.insert(
+            key,
+            CacheEntry {
+                movie,
+                expires_at: Instant::now() + self.ttl,
+                used_at: self.tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.recency.remove(&entry.used_at);
+        }
+    }
+}
+
+#[async_trait]
+impl MovieStore for CachedMovieStore {
+    async fn get_all(
+        &self,
+        tenant_id: &str,
+        include_deleted: bool,
+    ) -> Result<Vec<Movie>, StoreError> {
+        self.inner.get_all(tenant_id, include_deleted).await
+    }
+
+    fn stream_all(&self, tenant_id: &str, include_deleted: bool) -> MovieStream {
+        self.inner.stream_all(tenant_id, include_deleted)
+    }
+
+    async fn find(
+        &self,
+        tenant_id: &str,
+        query: &MovieQuery,
+        cursor: Option<MovieCursor>,
+        limit: u32,
+    ) -> Result<MoviePage, StoreError> {
+        self.inner.find(tenant_id, query, cursor, limit).await
+    }
+
+    async fn search(
+        &self,
+        tenant_id: &str,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<MovieSearchResult>, StoreError> {
+        self.inner.search(tenant_id, query, limit).await
+    }
+
+    async fn stats(
+        &self,
+        tenant_id: &str,
+        query: &MovieStatsQuery,
+    ) -> Result<MovieStats, StoreError> {
+        self.inner.stats(tenant_id, query).await
+    }
+
+    async fn get_by_id(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        include_deleted: bool,
+    ) -> Result<Movie, StoreError> {
+        let key = (tenant_id.to_string(), id, include_deleted);
+        let generation = {
+            let mut cache = self.cache.lock();
+            if let Some(movie) = cache.get(&key) {
+                return Ok(movie);
+            }
+            cache.generation
+        };
+
+        let movie = self.inner.get_by_id(tenant_id, id, include_deleted).await?;
+        self.cache.lock().insert(key, movie.clone(), generation);
+        Ok(movie)
+    }
+
+    async fn create(&self, tenant_id: &str, movie: CreateMovieParams) -> Result<Movie, StoreError> {
+        self.inner.create(tenant_id, movie).await
+    }
+
+    async fn update(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        movie: UpdateMovieParams,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let result = self
+            .inner
+            .update(tenant_id, id, movie, expected_version)
+            .await;
+        self.invalidate(tenant_id, [id]);
+        result
+    }
+
+    async fn delete(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let result = self.inner.delete(tenant_id, id, expected_version).await;
+        self.invalidate(tenant_id, [id]);
+        result
+    }
+
+    async fn restore(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let result = self.inner.restore(tenant_id, id, expected_version).await;
+        self.invalidate(tenant_id, [id]);
+        result
+    }
+
+    async fn purge(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let result = self.inner.purge(tenant_id, id, expected_version).await;
+        self.invalidate(tenant_id, [id]);
+        result
+    }
+
+    async fn history(&self, tenant_id: &str, id: Uuid) -> Result<Vec<MovieRevision>, StoreError> {
+        self.inner.history(tenant_id, id).await
+    }
+
+    async fn batch(
+        &self,
+        tenant_id: &str,
+        operations: Vec<BatchOperation>,
+    ) -> Result<Vec<Movie>, BatchError> {
+        let ids: Vec<Uuid> = operations
+            .iter()
+            .filter_map(|operation| match operation {
+                BatchOperation::Create(_) => None,
+                BatchOperation::Update { id, .. } | BatchOperation::Delete { id, .. } => Some(*id),
+            })
+            .collect();
+        let result = self.inner.batch(tenant_id, operations).await;
+        self.invalidate(tenant_id, ids);
+        result
+    }
+
+    async fn import(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<Result<ImportOutcome, StoreError>>, StoreError> {
+        let ids: Vec<Uuid> = movies.iter().map(|movie| movie.id).collect();
+        let result = self.inner.import(tenant_id, movies, mode).await;
+        self.invalidate(tenant_id, ids);
+        result
+    }
+
+    async fn import_all(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<ImportOutcome>, BatchError> {
+        let ids: Vec<Uuid> = movies.iter().map(|movie| movie.id).collect();
+        let result = self.inner.import_all(tenant_id, movies, mode).await;
+        self.invalidate(tenant_id, ids);
+        result
+    }
+
+    async fn changes(
+        &self,
+        tenant_id: &str,
+        since: Option<ChangeCursor>,
+        limit: u32,
+    ) -> Result<ChangePage, StoreError> {
+        self.inner.changes(tenant_id, since, limit).await
+    }
+}
@@ -0,0 +1,151 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use super::store::{Movie, MovieRevision};
+
+const SNAPSHOT_FILE: &str = "movies.snapshot.json";
+const LOG_FILE: &str = "movies.log";
+
+/// A change to the memory store, appended to the log before it is applied.
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum JournalRecord {
+    /// Stores a movie without history, only written before revisions were recorded.
+    Put { movie: Movie },
+    /// Stores the movie in the revision and adds the revision to its history.
+    Revise { revision: MovieRevision },
+    /// Removes a movie and its history.
+    Delete { id: Uuid },
+    /// Records written as one line, so they are recovered all together or not at all.
+    Batch { records: Vec<JournalRecord> },
+}
+
+#[derive(Deserialize, Default)]
+struct Snapshot {
+    movies: Vec<Movie>,
+    #[serde(default)]
+    revisions: Vec<MovieRevision>,
+}
+
+#[derive(Serialize)]
+struct SnapshotRef<'a> {
+    movies: Vec<&'a Movie>,
+    revisions: Vec<&'a MovieRevision>,
+}
+
+/// State recovered from disk when a journal is opened.
+pub struct Recovered {
+    pub movies: Vec<Movie>,
+    pub revisions: Vec<MovieRevision>,
+    pub records: Vec<JournalRecord>,
+}
+
+/// Durable storage for the memory store: a JSON snapshot of every movie plus an append-only
+/// log of newline delimited records written since that snapshot.
+pub struct Journal {
+    directory: PathBuf,
+    log: File,
+}
+
+impl Journal {
+    /// Opens the journal in `directory`, creating it if needed, and returns the last snapshot
+    /// with the log records to replay on top of it.
+    ///
+    /// A process killed halfway through an append leaves a last record without its newline,
+    /// that record was never acknowledged so it is dropped and the log is truncated.
+    pub fn open(directory: &Path) -> io::Result<(Journal, Recovered)> {
+        fs::create_dir_all(directory)?;
+
+        let snapshot = match fs::read(directory.join(SNAPSHOT_FILE)) {
+            Ok(bytes) => serde_json::from_slice::<Snapshot>(&bytes)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Snapshot::default(),
+            Err(e) => return Err(e),
+        };
+
+        let log_path = directory.join(LOG_FILE);
+        let mut log = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(&log_path)?;
+        let mut bytes = Vec::new();
+        log.read_to_end(&mut bytes)?;
+
+        let mut records = Vec::new();
+        let mut complete_len = 0;
+        for line in bytes.split_inclusive(|b| *b == b'\n') {
+            if line.last() != Some(&b'\n') {
+                tracing::warn!(
+                    "dropping incomplete record at the end of {}",
+                    log_path.display()
+                );
+                break;
+            }
+            let record = serde_json::from_slice::<JournalRecord>(line)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            records.push(record);
+            complete_len += line.len();
+        }
+        if complete_len < bytes.len() {
+            log.set_len(complete_len as u64)?;
+            log.sync_data()?;
+        }
+
+        let journal = Journal {
+            directory: directory.to_path_buf(),
+            log,
+        };
+        let recovered = Recovered {
+            movies: snapshot.movies,
+            revisions: snapshot.revisions,
+            records,
+        };
+        Ok((journal, recovered))
+    }
+
+    /// Appends a record and waits for it to reach the disk.
+    pub fn append(&mut self, record: &JournalRecord) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.log.write_all(&line)?;
+        self.log.sync_data()
+    }
+
+    /// Replaces the snapshot with `movies` and their `revisions` and empties the log, the caller
+    /// must make sure nothing is appended while this runs.
+    pub fn compact<'a>(
+        &mut self,
+        movies: impl Iterator<Item = &'a Movie>,
+        revisions: impl Iterator<Item = &'a MovieRevision>,
+    ) -> io::Result<()> {
+        let snapshot_path = self.directory.join(SNAPSHOT_FILE);
+        let temporary_path = snapshot_path.with_extension("json.tmp");
+
+        let file = File::create(&temporary_path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer(
+            &mut writer,
+            &SnapshotRef {
+                movies: movies.collect(),
+                revisions: revisions.collect(),
+            },
+        )?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        file.sync_all()?;
+        // the rename is atomic, a crash leaves either the old or the new snapshot in place
+        fs::rename(&temporary_path, &snapshot_path)?;
+        if let Ok(directory) = File::open(&self.directory) {
+            let _ = directory.sync_all();
+        }
+
+        // crashing before this replays records the snapshot already holds, the store skips the
+        // revisions it already has
+        self.log.set_len(0)?;
+        self.log.sync_data()
+    }
+}
@@ -1,23 +1,613 @@
-use std::{collections::HashMap, sync::Arc};
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::Arc;
 
 use axum::async_trait;
-use chrono::Utc;
-use parking_lot::RwLock;
+use chrono::{NaiveDateTime, Utc};
+use futures::stream::{self, StreamExt};
+use parking_lot::{Mutex, RwLock};
 use uuid::Uuid;
 
-use super::store::{CreateMovieParams, DynMovieStore, Movie, MovieStore, Store, UpdateMovieParams};
+use super::memory_journal::{Journal, JournalRecord};
 
-type Movies = HashMap<Uuid, Movie>;
+use super::cached_store::CacheStats;
+use super::store::{
+    BatchError, BatchOperation, ChangeCursor, ChangePage, CreateMovieParams, DynIdempotencyStore,
+    DynMovieStore, DynOutboxStore, IdempotencyStore, IdempotentRequest, IdempotentResponse,
+    ImportMode, ImportMovie, ImportOutcome, Movie, MovieChange, MovieCursor, MovieEvent,
+    MovieEventKind, MoviePage, MovieQuery, MovieRevision, MovieSearchResult, MovieStats,
+    MovieStatsQuery, MovieStore, MovieStream, OutboxStore, Store, StoreError, UpdateMovieParams,
+    MOVIE_STREAM_BUFFER,
+};
 
-#[derive(Clone)]
+type MovieKey = (NaiveDateTime, Uuid);
+/// The fields that tell the live movies of a tenant apart.
+type NaturalKey = (String, String, String, NaiveDateTime);
+
+fn natural_key(movie: &Movie) -> NaturalKey {
+    (
+        movie.tenant_id.clone(),
+        movie.title.clone(),
+        movie.director.clone(),
+        movie.release_date,
+    )
+}
+
+/// Movies kept in `(created_at, id)` order, with indexes to look them up by id and by words,
+/// the revisions recorded for each of them and the change events not delivered yet.
+#[derive(Default)]
+struct Movies {
+    ordered: BTreeMap<MovieKey, Movie>,
+    created_at_by_id: HashMap<Uuid, NaiveDateTime>,
+    /// Only movies that are not deleted.
+    live_id_by_natural_key: HashMap<NaturalKey, Uuid>,
+    search_index: SearchIndex,
+    revisions: HashMap<Uuid, Vec<MovieRevision>>,
+    /// Only kept in process, events still pending when the process exits are lost.
+    outbox: VecDeque<PendingEvent>,
+    /// Set when nothing dispatches the events, so they are not queued at all.
+    outbox_disabled: bool,
+    last_event_id: i64,
+    /// Only kept in process, a store opened from its journal starts the feeds over with the
+    /// movies as they are.
+    changes: ChangeFeeds,
+    /// Set while a batch is applied, the feeds are only compacted once it is done so that a
+    /// failed batch can take its changes back.
+    in_batch: bool,
+}
+
+impl Movies {
+    fn key(&self, id: &Uuid) -> Option<MovieKey> {
+        self.created_at_by_id
+            .get(id)
+            .map(|created_at| (*created_at, *id))
+    }
+
+    fn get(&self, id: &Uuid) -> Option<&Movie> {
+        self.key(id).and_then(|key| self.ordered.get(&key))
+    }
+
+    /// Looks up a movie of one tenant, movies of other tenants are not there.
+    fn get_in(&self, tenant_id: &str, id: &Uuid) -> Option<&Movie> {
+        self.get(id).filter(|movie| movie.tenant_id == tenant_id)
+    }
+
+    /// Inserts a movie, replacing any movie with the same id.
+    fn insert(&mut self, movie: Movie) {
+        self.remove(&movie.id);
+        self.search_index.add(&movie);
+        if movie.deleted_at.is_none() {
+            self.live_id_by_natural_key
+                .insert(natural_key(&movie), movie.id);
+        }
+        self.created_at_by_id.insert(movie.id, movie.created_at);
+        self.ordered.insert((movie.created_at, movie.id), movie);
+    }
+
+    fn remove(&mut self, id: &Uuid) -> Option<Movie> {
+        let key = self.key(id)?;
+        self.created_at_by_id.remove(id);
+        let movie = self.ordered.remove(&key)?;
+        self.search_index.remove(&movie);
+        let natural_key = natural_key(&movie);
+        if self.live_id_by_natural_key.get(&natural_key) == Some(id) {
+            self.live_id_by_natural_key.remove(&natural_key);
+        }
+        Some(movie)
+    }
+
+    /// Fails if `movie` is live and another live movie of its tenant has the same title,
+    /// director and release date.
+    fn check_unique(&self, movie: &Movie) -> Result<(), StoreError> {
+        if movie.deleted_at.is_some() {
+            return Ok(());
+        }
+        match self.live_id_by_natural_key.get(&natural_key(movie)) {
+            Some(id) if *id != movie.id => Err(StoreError::Duplicate(*id)),
+            _ => Ok(()),
+        }
+    }
+
+    fn values(&self) -> impl Iterator<Item = &Movie> {
+        self.ordered.values()
+    }
+
+    fn revisions(&self) -> impl Iterator<Item = &MovieRevision> {
+        self.revisions.values().flatten()
+    }
+
+    /// Queues the event, unless the outbox is disabled, and adds the change to the feed of the
+    /// movie's tenant.
+    fn record_event(&mut self, kind: MovieEventKind, movie: Movie) {
+        let occurred_at = Utc::now().naive_utc();
+        self.record_change(kind, movie.clone(), occurred_at);
+        self.last_event_id += 1;
+        if self.outbox_disabled {
+            return;
+        }
+        self.outbox.push_back(PendingEvent {
+            event: MovieEvent {
+                id: self.last_event_id,
+                kind,
+                movie_id: movie.id,
+                payload: movie,
+                occurred_at,
+            },
+            claimed_until: None,
+        });
+    }
+
+    fn record_change(&mut self, kind: MovieEventKind, movie: Movie, changed_at: NaiveDateTime) {
+        let tenant_id = movie.tenant_id.clone();
+        let feed = self.changes.feeds.entry(tenant_id).or_default();
+        feed.push(kind, movie, changed_at);
+        if !self.in_batch {
+            feed.compact(self.changes.retention);
+        }
+    }
+
+    /// Starts the change feeds with a change for every movie, creating the live ones and
+    /// deleting the deleted ones.
+    fn start_change_feeds(&mut self) {
+        let movies: Vec<Movie> = self.values().cloned().collect();
+        for movie in movies {
+            match movie.deleted_at {
+                None => {
+                    let changed_at = movie.updated_at;
+                    self.record_change(MovieEventKind::Created, movie, changed_at);
+                }
+                Some(deleted_at) => self.record_change(MovieEventKind::Deleted, movie, deleted_at),
+            }
+        }
+    }
+
+    fn create_revision(
+        tenant_id: &str,
+        id: Uuid,
+        movie_to_create: CreateMovieParams,
+    ) -> MovieRevision {
+        let now = Utc::now().naive_utc();
+        MovieRevision::created(Movie {
+            id,
+            tenant_id: tenant_id.to_string(),
+            title: movie_to_create.title,
+            director: movie_to_create.director,
+            release_date: movie_to_create.release_date,
+            ticket_price: movie_to_create.ticket_price,
+            created_at: now,
+            updated_at: now,
+            version: 1,
+            deleted_at: None,
+        })
+    }
+
+    fn update_revision(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        movie_to_update: UpdateMovieParams,
+        expected_version: Option<i64>,
+    ) -> Result<MovieRevision, StoreError> {
+        let previous = match self.get_in(tenant_id, &id) {
+            Some(m) if m.deleted_at.is_none() => m,
+            _ => return Err(StoreError::NotFound),
+        };
+        if expected_version.is_some_and(|expected_version| expected_version != previous.version) {
+            return Err(StoreError::VersionMismatch);
+        }
+        let mut m = previous.clone();
+        if let Some(title) = movie_to_update.title {
+            m.title = title;
+        }
+        if let Some(director) = movie_to_update.director {
+            m.director = director;
+        }
+        if let Some(release_date) = movie_to_update.release_date {
+            m.release_date = release_date;
+        }
+        if let Some(ticket_price) = movie_to_update.ticket_price {
+            m.ticket_price = ticket_price;
+        }
+        let mut revision = MovieRevision::updated(previous, m);
+        // only an update that changes a field makes a new version of the movie
+        if !revision.changed_fields.is_empty() {
+            revision.movie.updated_at = Utc::now().naive_utc();
+            revision.movie.version += 1;
+            revision.revision = revision.movie.version;
+        }
+        Ok(revision)
+    }
+
+    fn delete_revision(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<MovieRevision, StoreError> {
+        let mut m = match self.get_in(tenant_id, &id) {
+            Some(m) if m.deleted_at.is_none() => m.clone(),
+            _ => return Err(StoreError::NotFound),
+        };
+        if expected_version.is_some_and(|expected_version| expected_version != m.version) {
+            return Err(StoreError::VersionMismatch);
+        }
+        m.deleted_at = Some(Utc::now().naive_utc());
+        m.version += 1;
+        Ok(MovieRevision::deleted_or_restored(m))
+    }
+
+    /// The revision importing `movie` makes with the kind of its event, `None` when the movie
+    /// is left as it is.
+    fn import_revision(
+        &self,
+        tenant_id: &str,
+        movie: ImportMovie,
+        mode: ImportMode,
+    ) -> Result<Option<(MovieRevision, MovieEventKind)>, StoreError> {
+        match self.get(&movie.id) {
+            None => {
+                let revision = Movies::create_revision(tenant_id, movie.id, movie.movie);
+                Ok(Some((revision, MovieEventKind::Created)))
+            }
+            // ids are unique across tenants, like the primary key of the sql stores
+            Some(existing) if existing.tenant_id != tenant_id => Err(StoreError::Conflict(
+                "movie id belongs to another tenant".to_string(),
+            )),
+            Some(_) => match mode {
+                ImportMode::SkipExisting => Ok(None),
+                ImportMode::Upsert => {
+                    let revision =
+                        self.update_revision(tenant_id, movie.id, movie.movie.into(), None)?;
+                    Ok(Some((revision, MovieEventKind::Updated)))
+                }
+            },
+        }
+    }
+
+    /// Applies a revision of a batch, leaving it to the caller to log `records` once the batch
+    /// is done, and notes in `savepoint` what the revision replaced. A revision that changes no
+    /// field is left out.
+    fn apply_in_batch(
+        &mut self,
+        savepoint: &mut Savepoint,
+        records: &mut Vec<JournalRecord>,
+        revision: MovieRevision,
+        kind: MovieEventKind,
+    ) -> Result<Movie, StoreError> {
+        if revision.changed_fields.is_empty() {
+            return Ok(revision.movie);
+        }
+        self.check_unique(&revision.movie)?;
+        let movie = revision.movie.clone();
+        savepoint
+            .previous
+            .push((movie.id, self.get(&movie.id).cloned()));
+        let record = JournalRecord::Revise { revision };
+        self.apply(record.clone());
+        self.record_event(kind, movie.clone());
+        records.push(record);
+        Ok(movie)
+    }
+
+    /// Marks where a batch starts, so that its writes can be undone if one of them fails.
+    fn savepoint(&mut self, tenant_id: &str) -> Savepoint {
+        self.in_batch = true;
+        Savepoint {
+            tenant_id: tenant_id.to_string(),
+            previous: Vec::new(),
+            last_event_id: self.last_event_id,
+            last_change_sequence: self
+                .changes
+                .feeds
+                .get(tenant_id)
+                .map_or(0, |feed| feed.last_sequence),
+        }
+    }
+
+    /// Keeps the writes applied since `savepoint`, compacting the change feed they added to.
+    fn release(&mut self, savepoint: Savepoint) {
+        self.in_batch = false;
+        if let Some(feed) = self.changes.feeds.get_mut(&savepoint.tenant_id) {
+            feed.compact(self.changes.retention);
+        }
+    }
+
+    /// Undoes the revisions applied since `savepoint` in reverse order, with their events and
+    /// changes.
+    fn rollback(&mut self, savepoint: Savepoint) {
+        for (id, previous) in savepoint.previous.into_iter().rev() {
+            if let Entry::Occupied(mut revisions) = self.revisions.entry(id) {
+                revisions.get_mut().pop();
+                if revisions.get().is_empty() {
+                    revisions.remove();
+                }
+            }
+            match previous {
+                Some(movie) => self.insert(movie),
+                None => {
+                    self.remove(&id);
+                }
+            }
+        }
+        while self
+            .outbox
+            .back()
+            .is_some_and(|pending| pending.event.id > savepoint.last_event_id)
+        {
+            self.outbox.pop_back();
+        }
+        self.last_event_id = savepoint.last_event_id;
+        if let Some(feed) = self.changes.feeds.get_mut(&savepoint.tenant_id) {
+            feed.truncate(savepoint.last_change_sequence);
+        }
+        self.in_batch = false;
+    }
+
+    fn apply(&mut self, record: JournalRecord) {
+        match record {
+            JournalRecord::Put { movie } => self.insert(movie),
+            JournalRecord::Revise { revision } => {
+                let revisions = self.revisions.entry(revision.movie.id).or_default();
+                // a log left behind by a crash right after a snapshot replays revisions the
+                // snapshot already holds
+                if revisions
+                    .last()
+                    .is_some_and(|last| last.revision >= revision.revision)
+                {
+                    return;
+                }
+                revisions.push(revision.clone());
+                self.insert(revision.movie);
+            }
+            JournalRecord::Delete { id } => {
+                self.remove(&id);
+                self.revisions.remove(&id);
+            }
+            JournalRecord::Batch { records } => {
+                for record in records {
+                    self.apply(record);
+                }
+            }
+        }
+    }
+}
+
+/// How many of the latest changes of a tenant are kept as they were by default.
+const DEFAULT_CHANGE_RETENTION: usize = 10_000;
+
+struct ChangeFeeds {
+    feeds: HashMap<String, ChangeFeed>,
+    retention: usize,
+}
+
+impl Default for ChangeFeeds {
+    fn default() -> Self {
+        Self {
+            feeds: HashMap::new(),
+            retention: DEFAULT_CHANGE_RETENTION,
+        }
+    }
+}
+
+/// The change feed of a tenant, numbered from 1. The latest changes are kept as they were,
+/// the ones before them are compacted to the latest change of each movie, which is still enough
+/// to copy the catalogue from the first change. A cursor pointing at a change that was
+/// compacted away has expired.
+#[derive(Default)]
+struct ChangeFeed {
+    changes: VecDeque<MovieChange>,
+    /// How many changes at the front are what was left by compacting.
+    compacted: usize,
+    last_sequence: i64,
+}
+
+impl ChangeFeed {
+    fn push(&mut self, kind: MovieEventKind, movie: Movie, changed_at: NaiveDateTime) {
+        self.last_sequence += 1;
+        self.changes.push_back(MovieChange::new(
+            self.last_sequence,
+            kind,
+            movie,
+            changed_at,
+        ));
+    }
+
+    /// Drops the changes after `sequence`, which have to be newer than the last compaction.
+    fn truncate(&mut self, sequence: i64) {
+        while self
+            .changes
+            .back()
+            .is_some_and(|change| change.sequence > sequence)
+        {
+            self.changes.pop_back();
+        }
+        self.last_sequence = sequence;
+    }
+
+    /// Keeps the latest `retention` changes as they were and compacts the ones before them,
+    /// once twice as many have piled up so that compacting takes turns with `retention` writes.
+    fn compact(&mut self, retention: usize) {
+        if self.changes.len() - self.compacted <= 2 * retention {
+            return;
+        }
+        let compact_through = self.changes.len() - retention;
+        let mut seen = HashSet::new();
+        let mut kept = VecDeque::with_capacity(self.changes.len());
+        for (i, change) in self.changes.drain(..).enumerate().rev() {
+            let latest = seen.insert(change.movie_id);
+            if i >= compact_through || latest {
+                kept.push_front(change);
+            }
+        }
+        self.compacted = kept.len() - retention;
+        self.changes = kept;
+    }
+}
+
+fn import_outcome(kind: MovieEventKind, movie: Movie) -> ImportOutcome {
+    match kind {
+        MovieEventKind::Created => ImportOutcome::Created(movie),
+        _ => ImportOutcome::Updated(movie),
+    }
+}
+
+struct PendingEvent {
+    event: MovieEvent,
+    claimed_until: Option<NaiveDateTime>,
+}
+
+/// The state of the movies a batch revised as it was before, to put back if the batch fails.
+struct Savepoint {
+    tenant_id: String,
+    /// Each revised movie as it was, `None` for a movie the batch created.
+    previous: Vec<(Uuid, Option<Movie>)>,
+    last_event_id: i64,
+    last_change_sequence: i64,
+}
+
+/// Inverted index from lower cased words to the movies containing them, with a weight per movie
+/// that favours matches in the title over matches in the director.
+#[derive(Default)]
+struct SearchIndex {
+    postings: HashMap<String, HashMap<Uuid, f32>>,
+}
+
+impl SearchIndex {
+    const TITLE_WEIGHT: f32 = 1.0;
+    const DIRECTOR_WEIGHT: f32 = 0.4;
+
+    fn weighted_words(movie: &Movie) -> impl Iterator<Item = (String, f32)> + '_ {
+        let title = words(&movie.title).map(|word| (word, Self::TITLE_WEIGHT));
+        let director = words(&movie.director).map(|word| (word, Self::DIRECTOR_WEIGHT));
+        title.chain(director)
+    }
+
+    fn add(&mut self, movie: &Movie) {
+        for (word, weight) in Self::weighted_words(movie) {
+            *self
+                .postings
+                .entry(word)
+                .or_default()
+                .entry(movie.id)
+                .or_default() += weight;
+        }
+    }
+
+    fn remove(&mut self, movie: &Movie) {
+        for (word, _) in Self::weighted_words(movie) {
+            if let Some(movies) = self.postings.get_mut(&word) {
+                movies.remove(&movie.id);
+                if movies.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Returns the ids of movies containing every word of the query, with their scores.
+    fn search(&self, query: &str) -> Vec<(Uuid, f32)> {
+        let mut words: Vec<String> = words(query).collect();
+        words.sort();
+        words.dedup();
+
+        let mut postings = Vec::with_capacity(words.len());
+        for word in &words {
+            match self.postings.get(word) {
+                None => return Vec::new(),
+                Some(movies) => postings.push(movies),
+            }
+        }
+        // start from the rarest word so the candidate set is as small as possible
+        postings.sort_by_key(|movies| movies.len());
+
+        let Some((first, rest)) = postings.split_first() else {
+            return Vec::new();
+        };
+        first
+            .iter()
+            .filter_map(|(id, weight)| {
+                rest.iter()
+                    .map(|movies| movies.get(id))
+                    .sum::<Option<f32>>()
+                    .map(|rest_weight| (*id, weight + rest_weight))
+            })
+            .collect()
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+#[derive(Clone, Default)]
 pub struct MemoryStore {
     movie_store: MemoryMovieStore,
+    idempotency_store: MemoryIdempotencyStore,
 }
 
 impl MemoryStore {
     pub fn new() -> MemoryStore {
         let movie_store = MemoryMovieStore::new();
-        Self { movie_store }
+        Self {
+            movie_store,
+            idempotency_store: MemoryIdempotencyStore::default(),
+        }
+    }
+
+    /// Opens a memory store that persists every change to `directory`, restoring the movies
+    /// saved there by a previous run.
+    pub fn open(directory: &Path) -> Result<MemoryStore, StoreError> {
+        let (journal, recovered) = Journal::open(directory).map_err(journal_error)?;
+
+        let mut movies = Movies::default();
+        for movie in recovered.movies {
+            movies.insert(movie);
+        }
+        for revision in recovered.revisions {
+            movies
+                .revisions
+                .entry(revision.movie.id)
+                .or_default()
+                .push(revision);
+        }
+        for record in recovered.records {
+            movies.apply(record);
+        }
+        movies.start_change_feeds();
+
+        let movie_store = MemoryMovieStore {
+            movies: Arc::new(RwLock::new(movies)),
+            journal: Some(Arc::new(Mutex::new(journal))),
+        };
+        movie_store.snapshot()?;
+        Ok(Self {
+            movie_store,
+            idempotency_store: MemoryIdempotencyStore::default(),
+        })
+    }
+
+    /// Queues the change events of every write for the dispatcher, the default. Without a
+    /// dispatcher nothing would ever take them off the queue.
+    pub fn with_outbox(self, enabled: bool) -> MemoryStore {
+        self.movie_store.movies.write().outbox_disabled = !enabled;
+        self
+    }
+
+    /// Keeps the latest `retention` changes of each tenant as they were, at least one, before
+    /// them only the latest change of each movie is kept.
+    pub fn with_change_retention(self, retention: usize) -> MemoryStore {
+        self.movie_store.movies.write().changes.retention = retention.max(1);
+        self
+    }
+
+    /// Writes a snapshot of every movie and truncates the change log, does nothing when the
+    /// store is not persisted.
+    pub fn snapshot(&self) -> Result<(), StoreError> {
+        self.movie_store.snapshot()
     }
 }
 
@@ -30,106 +620,603 @@ impl Store for MemoryStore {
     async fn movie_store(&self) -> DynMovieStore {
         Arc::new(self.movie_store.clone()) as DynMovieStore
     }
+
+    async fn outbox_store(&self) -> DynOutboxStore {
+        Arc::new(self.movie_store.clone()) as DynOutboxStore
+    }
+
+    async fn idempotency_store(&self) -> DynIdempotencyStore {
+        Arc::new(self.idempotency_store.clone()) as DynIdempotencyStore
+    }
+
+    async fn replica_movie_store(&self) -> Option<DynMovieStore> {
+        None
+    }
+
+    async fn is_replica_connected(&self) -> Option<bool> {
+        None
+    }
+
+    async fn movie_cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    async fn migrate(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
+
+    async fn check_schema_version(&self) -> Result<(), StoreError> {
+        Ok(())
+    }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct MemoryMovieStore {
     movies: Arc<RwLock<Movies>>,
+    journal: Option<Arc<Mutex<Journal>>>,
 }
 
 impl MemoryMovieStore {
     fn new() -> Self {
-        Self {
-            movies: Arc::new(RwLock::new(HashMap::new())),
+        Self::default()
+    }
+
+    /// Makes a change durable before it is applied, callers hold the write lock on `movies`
+    /// so records are logged in the order they are applied.
+    fn log(&self, record: &JournalRecord) -> Result<(), StoreError> {
+        match &self.journal {
+            None => Ok(()),
+            Some(journal) => journal.lock().append(record).map_err(journal_error),
         }
     }
+
+    /// Logs and applies a revision that duplicates no other movie and queues its event, returning
+    /// the movie it captures. A revision that changes no field is left out.
+    fn revise(
+        &self,
+        movies: &mut Movies,
+        revision: MovieRevision,
+        kind: MovieEventKind,
+    ) -> Result<Movie, StoreError> {
+        if revision.changed_fields.is_empty() {
+            return Ok(revision.movie);
+        }
+        movies.check_unique(&revision.movie)?;
+        let movie = revision.movie.clone();
+        let record = JournalRecord::Revise { revision };
+        self.log(&record)?;
+        movies.apply(record);
+        movies.record_event(kind, movie.clone());
+        Ok(movie)
+    }
+
+    fn import_movie(
+        &self,
+        movies: &mut Movies,
+        tenant_id: &str,
+        movie: ImportMovie,
+        mode: ImportMode,
+    ) -> Result<ImportOutcome, StoreError> {
+        match movies.import_revision(tenant_id, movie, mode)? {
+            None => Ok(ImportOutcome::Skipped),
+            Some((revision, kind)) => {
+                let movie = self.revise(movies, revision, kind)?;
+                Ok(import_outcome(kind, movie))
+            }
+        }
+    }
+
+    /// Applies the operations of a batch and logs them as one record, noting in `savepoint`
+    /// what each revision replaced. The caller undoes the applied operations on failure.
+    fn apply_batch(
+        &self,
+        movies: &mut Movies,
+        savepoint: &mut Savepoint,
+        tenant_id: &str,
+        operations: Vec<BatchOperation>,
+    ) -> Result<Vec<Movie>, BatchError> {
+        let mut records = Vec::with_capacity(operations.len());
+        let mut revised = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.into_iter().enumerate() {
+            let (revision, kind) = match operation {
+                BatchOperation::Create(movie) => {
+                    let revision = Movies::create_revision(tenant_id, Uuid::new_v4(), movie);
+                    Ok((revision, MovieEventKind::Created))
+                }
+                BatchOperation::Update {
+                    id,
+                    movie,
+                    expected_version,
+                } => movies
+                    .update_revision(tenant_id, id, movie, expected_version)
+                    .map(|revision| (revision, MovieEventKind::Updated)),
+                BatchOperation::Delete {
+                    id,
+                    expected_version,
+                } => movies
+                    .delete_revision(tenant_id, id, expected_version)
+                    .map(|revision| (revision, MovieEventKind::Deleted)),
+            }
+            .map_err(BatchError::at(index))?;
+            let movie = movies
+                .apply_in_batch(savepoint, &mut records, revision, kind)
+                .map_err(BatchError::at(index))?;
+            revised.push(movie);
+        }
+
+        self.log(&JournalRecord::Batch { records })?;
+
+        Ok(revised)
+    }
+
+    /// Imports every movie and logs them as one record, like `apply_batch`.
+    fn apply_import(
+        &self,
+        movies: &mut Movies,
+        savepoint: &mut Savepoint,
+        tenant_id: &str,
+        imports: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<ImportOutcome>, BatchError> {
+        let mut records = Vec::with_capacity(imports.len());
+        let mut outcomes = Vec::with_capacity(imports.len());
+        for (index, movie) in imports.into_iter().enumerate() {
+            let outcome = match movies
+                .import_revision(tenant_id, movie, mode)
+                .map_err(BatchError::at(index))?
+            {
+                None => ImportOutcome::Skipped,
+                Some((revision, kind)) => {
+                    let movie = movies
+                        .apply_in_batch(savepoint, &mut records, revision, kind)
+                        .map_err(BatchError::at(index))?;
+                    import_outcome(kind, movie)
+                }
+            };
+            outcomes.push(outcome);
+        }
+
+        self.log(&JournalRecord::Batch { records })?;
+
+        Ok(outcomes)
+    }
+
+    fn snapshot(&self) -> Result<(), StoreError> {
+        let Some(journal) = &self.journal else {
+            return Ok(());
+        };
+        // the read lock keeps writers out until the log is truncated
+        let r = self.movies.read();
+        journal
+            .lock()
+            .compact(r.values(), r.revisions())
+            .map_err(journal_error)
+    }
+}
+
+fn journal_error(error: std::io::Error) -> StoreError {
+    StoreError::Unavailable(format!("memory store journal: {}", error))
 }
 
 #[async_trait]
 impl MovieStore for MemoryMovieStore {
-    async fn get_all(&self) -> Vec<Movie> {
+    async fn get_all(
+        &self,
+        tenant_id: &str,
+        include_deleted: bool,
+    ) -> Result<Vec<Movie>, StoreError> {
         let mut result = Vec::new();
         let r = self.movies.read();
 
-        for (_, value) in r.iter() {
-            result.push((*value).clone());
+        for value in r.values() {
+            if value.tenant_id == tenant_id && (include_deleted || value.deleted_at.is_none()) {
+                result.push((*value).clone());
+            }
         }
 
-        result
+        Ok(result)
+    }
+
+    fn stream_all(&self, tenant_id: &str, include_deleted: bool) -> MovieStream {
+        let movies = self.movies.clone();
+        let tenant_id = tenant_id.to_string();
+        // movies are copied out a chunk at a time, so a slow reader doesn't hold up writers
+        let chunks = stream::unfold(Some(Bound::Unbounded), move |after| {
+            let movies = movies.clone();
+            let tenant_id = tenant_id.clone();
+            async move {
+                let r = movies.read();
+                let mut chunk = Vec::with_capacity(MOVIE_STREAM_BUFFER);
+                let mut last = None;
+                let range = r.ordered.range((after?, Bound::Unbounded));
+                for (key, movie) in range.take(MOVIE_STREAM_BUFFER) {
+                    last = Some(*key);
+                    if movie.tenant_id == tenant_id
+                        && (include_deleted || movie.deleted_at.is_none())
+                    {
+                        chunk.push(Ok(movie.clone()));
+                    }
+                }
+                Some((stream::iter(chunk), last.map(Bound::Excluded)))
+            }
+        });
+        chunks.flatten().boxed()
+    }
+
+    async fn find(
+        &self,
+        tenant_id: &str,
+        query: &MovieQuery,
+        cursor: Option<MovieCursor>,
+        limit: u32,
+    ) -> Result<MoviePage, StoreError> {
+        if let Some(cursor) = &cursor {
+            cursor.check_sort(query.sort)?;
+        }
+
+        let r = self.movies.read();
+        let mut movies: Vec<&Movie> = r
+            .values()
+            .filter(|movie| movie.tenant_id == tenant_id && query.matches(movie))
+            .filter(|movie| cursor.as_ref().is_none_or(|c| c.is_before(movie)))
+            .collect();
+        movies.sort_by(|a, b| query.sort.compare(a, b));
+        let movies = movies
+            .into_iter()
+            .take(limit as usize + 1)
+            .cloned()
+            .collect();
+
+        Ok(MoviePage::from_rows(movies, limit, query.sort))
     }
 
-    async fn get_by_id(&self, id: Uuid) -> Option<Movie> {
+    async fn search(
+        &self,
+        tenant_id: &str,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<MovieSearchResult>, StoreError> {
         let r = self.movies.read();
-        let movie = r.get(&id);
+        let mut results: Vec<MovieSearchResult> = r
+            .search_index
+            .search(query)
+            .into_iter()
+            .filter_map(|(id, score)| {
+                r.get_in(tenant_id, &id)
+                    .filter(|movie| movie.deleted_at.is_none())
+                    .map(|movie| MovieSearchResult {
+                        movie: movie.clone(),
+                        score,
+                    })
+            })
+            .collect();
+        results.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then_with(|| a.movie.created_at.cmp(&b.movie.created_at))
+                .then_with(|| a.movie.id.cmp(&b.movie.id))
+        });
+        results.truncate(limit as usize);
+
+        Ok(results)
+    }
+
+    async fn stats(
+        &self,
+        tenant_id: &str,
+        query: &MovieStatsQuery,
+    ) -> Result<MovieStats, StoreError> {
+        let r = self.movies.read();
+        let mut stats = MovieStats::default();
+        for movie in r.values() {
+            if movie.tenant_id == tenant_id && query.matches(movie) {
+                stats.add(movie);
+            }
+        }
+
+        Ok(stats)
+    }
+
+    async fn get_by_id(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        include_deleted: bool,
+    ) -> Result<Movie, StoreError> {
+        let r = self.movies.read();
+        let movie = r.get_in(tenant_id, &id);
 
         match movie {
-            None => None,
-            Some(movie) => Some((*movie).clone()),
+            Some(movie) if include_deleted || movie.deleted_at.is_none() => Ok((*movie).clone()),
+            _ => Err(StoreError::NotFound),
         }
     }
 
-    async fn create(&self, movie_to_create: CreateMovieParams) -> Result<Movie, String> {
-        let movie = Movie {
-            id: Uuid::new_v4(),
-            title: movie_to_create.title,
-            director: movie_to_create.director,
-            release_date: movie_to_create.release_date,
-            ticket_price: movie_to_create.ticket_price,
-            created_at: Utc::now().naive_utc(),
-            updated_at: Utc::now().naive_utc(),
+    async fn create(
+        &self,
+        tenant_id: &str,
+        movie_to_create: CreateMovieParams,
+    ) -> Result<Movie, StoreError> {
+        let revision = Movies::create_revision(tenant_id, Uuid::new_v4(), movie_to_create);
+        let mut w = self.movies.write();
+        self.revise(&mut w, revision, MovieEventKind::Created)
+    }
+
+    async fn update(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        movie_to_update: UpdateMovieParams,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        // the version is checked and the fields are merged and written under one write lock
+        let mut w = self.movies.write();
+        let revision = w.update_revision(tenant_id, id, movie_to_update, expected_version)?;
+        self.revise(&mut w, revision, MovieEventKind::Updated)
+    }
+
+    async fn delete(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut w = self.movies.write();
+        let revision = w.delete_revision(tenant_id, id, expected_version)?;
+        self.revise(&mut w, revision, MovieEventKind::Deleted)
+    }
+
+    async fn restore(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut w = self.movies.write();
+        let mut m = match w.get_in(tenant_id, &id) {
+            None => return Err(StoreError::NotFound),
+            Some(m) => m.clone(),
         };
+        if m.deleted_at.is_none() {
+            return Err(StoreError::Conflict("movie is not deleted".to_string()));
+        }
+        if expected_version.is_some_and(|expected_version| expected_version != m.version) {
+            return Err(StoreError::VersionMismatch);
+        }
+        m.deleted_at = None;
+        m.version += 1;
+        self.revise(
+            &mut w,
+            MovieRevision::deleted_or_restored(m),
+            MovieEventKind::Updated,
+        )
+    }
 
-        self.movies.write().insert(movie.id, movie.clone());
+    async fn purge(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut w = self.movies.write();
+        let Some(movie) = w.get_in(tenant_id, &id) else {
+            return Err(StoreError::NotFound);
+        };
+        if expected_version.is_some_and(|expected_version| expected_version != movie.version) {
+            return Err(StoreError::VersionMismatch);
+        }
+        let movie = movie.clone();
+        let record = JournalRecord::Delete { id };
+        self.log(&record)?;
+        w.apply(record);
+        w.record_event(MovieEventKind::Deleted, movie.clone());
 
         Ok(movie)
     }
 
-    async fn update(&self, id: Uuid, movie_to_update: UpdateMovieParams) -> Result<Movie, String> {
-        let movie = self.get_by_id(id).await;
-        let movie = match movie {
-            None => return Err("not found".to_string()),
-            Some(movie) => movie,
-        };
+    async fn history(&self, tenant_id: &str, id: Uuid) -> Result<Vec<MovieRevision>, StoreError> {
+        let r = self.movies.read();
+        if r.get_in(tenant_id, &id).is_none() {
+            return Err(StoreError::NotFound);
+        }
+        match r.revisions.get(&id) {
+            Some(revisions) if !revisions.is_empty() => Ok(revisions.clone()),
+            _ => Err(StoreError::NotFound),
+        }
+    }
 
-        self.movies.write().entry(movie.id).and_modify(|m| {
-            match movie_to_update.title {
-                Some(title) => {
-                    m.title = title;
-                    m.updated_at = Utc::now().naive_utc();
-                }
-                _ => {}
+    async fn batch(
+        &self,
+        tenant_id: &str,
+        operations: Vec<BatchOperation>,
+    ) -> Result<Vec<Movie>, BatchError> {
+        let mut w = self.movies.write();
+        // operations are applied in place and undone if any of them fails
+        let mut savepoint = w.savepoint(tenant_id);
+        let result = self.apply_batch(&mut w, &mut savepoint, tenant_id, operations);
+        match result {
+            Ok(_) => w.release(savepoint),
+            Err(_) => w.rollback(savepoint),
+        }
+        result
+    }
+
+    async fn import(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<Result<ImportOutcome, StoreError>>, StoreError> {
+        let mut w = self.movies.write();
+        let outcomes = movies
+            .into_iter()
+            .map(|movie| self.import_movie(&mut w, tenant_id, movie, mode))
+            .collect();
+
+        Ok(outcomes)
+    }
+
+    async fn import_all(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<ImportOutcome>, BatchError> {
+        let mut w = self.movies.write();
+        let mut savepoint = w.savepoint(tenant_id);
+        let result = self.apply_import(&mut w, &mut savepoint, tenant_id, movies, mode);
+        match result {
+            Ok(_) => w.release(savepoint),
+            Err(_) => w.rollback(savepoint),
+        }
+        result
+    }
+
+    async fn changes(
+        &self,
+        tenant_id: &str,
+        since: Option<ChangeCursor>,
+        limit: u32,
+    ) -> Result<ChangePage, StoreError> {
+        let r = self.movies.read();
+        let changes = match r.changes.feeds.get(tenant_id) {
+            None => Vec::new(),
+            Some(feed) => {
+                let from = since.map_or(0, |since| since.sequence);
+                let start = feed
+                    .changes
+                    .partition_point(|change| change.sequence < from);
+                feed.changes
+                    .range(start..)
+                    .take(limit as usize + 2)
+                    .cloned()
+                    .collect()
             }
-            match movie_to_update.director {
-                Some(director) => {
-                    m.director = director;
-                    m.updated_at = Utc::now().naive_utc();
-                }
-                _ => {}
+        };
+
+        ChangePage::from_rows(changes, since, limit)
+    }
+}
+
+#[async_trait]
+impl OutboxStore for MemoryMovieStore {
+    async fn claim_pending_events(
+        &self,
+        limit: u32,
+        claimed_until: NaiveDateTime,
+    ) -> Result<Vec<MovieEvent>, StoreError> {
+        let now = Utc::now().naive_utc();
+        let mut w = self.movies.write();
+        let events = w
+            .outbox
+            .iter_mut()
+            .filter(|pending| pending.claimed_until.is_none_or(|until| until <= now))
+            .take(limit as usize)
+            .map(|pending| {
+                pending.claimed_until = Some(claimed_until);
+                pending.event.clone()
+            })
+            .collect();
+        Ok(events)
+    }
+
+    /// Delivered events are dropped, nothing reads them again.
+    async fn mark_delivered(&self, ids: &[i64]) -> Result<(), StoreError> {
+        let mut w = self.movies.write();
+        w.outbox.retain(|pending| !ids.contains(&pending.event.id));
+        Ok(())
+    }
+
+    async fn release_events(&self, ids: &[i64]) -> Result<(), StoreError> {
+        let mut w = self.movies.write();
+        for pending in w.outbox.iter_mut() {
+            if ids.contains(&pending.event.id) {
+                pending.claimed_until = None;
             }
-            match movie_to_update.release_date {
-                Some(release_date) => {
-                    m.release_date = release_date;
-                    m.updated_at = Utc::now().naive_utc();
+        }
+        Ok(())
+    }
+}
+
+/// Idempotency keys are only kept in process and are not persisted with the movies.
+#[derive(Clone, Default)]
+pub struct MemoryIdempotencyStore {
+    keys: Arc<Mutex<HashMap<(String, String), ClaimedKey>>>,
+}
+
+struct ClaimedKey {
+    request: IdempotentRequest,
+    locked_until: NaiveDateTime,
+    expires_at: NaiveDateTime,
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn claim(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        request_hash: &str,
+        locked_until: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> Result<Option<IdempotentRequest>, StoreError> {
+        let now = Utc::now().naive_utc();
+        let mut keys = self.keys.lock();
+        keys.retain(|_, claimed| claimed.expires_at > now);
+
+        match keys.entry((tenant_id.to_string(), key.to_string())) {
+            Entry::Occupied(mut entry) => {
+                let claimed = entry.get_mut();
+                let abandoned = claimed.request.response.is_none()
+                    && claimed.request.request_hash == request_hash
+                    && claimed.locked_until <= now;
+                if !abandoned {
+                    return Ok(Some(claimed.request.clone()));
                 }
-                _ => {}
+                claimed.locked_until = locked_until;
+                claimed.expires_at = expires_at;
+                Ok(None)
             }
-            match movie_to_update.ticket_price {
-                Some(ticket_price) => {
-                    m.ticket_price = ticket_price;
-                    m.updated_at = Utc::now().naive_utc();
-                }
-                _ => {}
+            Entry::Vacant(entry) => {
+                entry.insert(ClaimedKey {
+                    request: IdempotentRequest {
+                        request_hash: request_hash.to_string(),
+                        response: None,
+                    },
+                    locked_until,
+                    expires_at,
+                });
+                Ok(None)
             }
-        });
+        }
+    }
 
-        Ok(self.get_by_id(movie.id).await.unwrap())
+    async fn complete(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        response: &IdempotentResponse,
+    ) -> Result<(), StoreError> {
+        let mut keys = self.keys.lock();
+        if let Some(claimed) = keys.get_mut(&(tenant_id.to_string(), key.to_string())) {
+            claimed.request.response = Some(response.clone());
+        }
+        Ok(())
     }
 
-    async fn delete(&self, id: Uuid) -> Result<Movie, String> {
-        let movie = self.movies.write().remove(&id);
-        match movie {
-            None => Err("not found".to_string()),
-            Some(movie) => Ok(movie),
+    async fn release(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        locked_until: NaiveDateTime,
+    ) -> Result<(), StoreError> {
+        let key = (tenant_id.to_string(), key.to_string());
+        let mut keys = self.keys.lock();
+        if keys.get(&key).is_some_and(|claimed| {
+            claimed.request.response.is_none() && claimed.locked_until == locked_until
+        }) {
+            keys.remove(&key);
         }
+        Ok(())
     }
 }
@@ -1,183 +1,1288 @@
 use std::sync::Arc;
 
-use super::store::{CreateMovieParams, DynMovieStore, Movie, MovieStore, Store, UpdateMovieParams};
+use super::cached_store::CacheStats;
+use super::store::{
+    check_applied_migration, fold_case, spawn_movie_stream, BatchError, BatchOperation,
+    ChangeCursor, ChangePage, CreateMovieParams, DynIdempotencyStore, DynMovieStore,
+    DynOutboxStore, IdempotencyStore, IdempotentRequest, IdempotentResponse, ImportMode,
+    ImportMovie, ImportOutcome, Movie, MovieChange, MovieCursor, MovieEvent, MovieEventKind,
+    MoviePage, MovieQuery, MovieRevision, MovieSearchResult, MovieSortField, MovieStats,
+    MovieStatsQuery, MovieStore, MovieStream, OutboxStore, SortDirection, SortValue, Store,
+    StoreError, TicketPriceStats, UpdateMovieParams,
+};
 use axum::async_trait;
-use chrono::Utc;
-use sqlx::{query_scalar, PgPool};
+use chrono::{NaiveDateTime, Utc};
+use futures::StreamExt;
+use sqlx::migrate::Migrator;
+use sqlx::types::Json;
+use sqlx::{query_scalar, Acquire, PgPool, Postgres, QueryBuilder, Transaction};
 use uuid::Uuid;
 
+static MIGRATOR: Migrator = sqlx::migrate!("./db/migrations");
+
+/// Keeps the live movies of a tenant apart by title, director and release date.
+const UNIQUE_MOVIE_INDEX: &str = "movies_tenant_id_title_director_release_date_key";
+
 pub struct SqlStore {
     db_pool: PgPool,
+    read_pool: Option<PgPool>,
     movie_store: SqlMovieStore,
 }
 
 impl SqlStore {
     pub fn new(db_pool: PgPool) -> SqlStore {
-        let movie_store = SqlMovieStore::new(db_pool.clone());
+        Self::with_read_pool(db_pool, None)
+    }
+
+    /// Uses `read_pool`, a connection to a read replica of `db_pool`, for the reads of the
+    /// replica movie store.
+    pub fn with_read_pool(db_pool: PgPool, read_pool: Option<PgPool>) -> SqlStore {
+        let movie_store = SqlMovieStore::new(db_pool.clone(), db_pool.clone());
         Self {
             db_pool,
+            read_pool,
             movie_store,
         }
     }
 }
 
+async fn is_pool_connected(pool: &PgPool) -> bool {
+    let connected = query_scalar!("SELECT TRUE;").fetch_one(pool).await;
+    match connected {
+        Ok(connected) => connected.unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
 #[async_trait]
 impl Store for SqlStore {
     async fn is_connected(&self) -> bool {
-        let connected = query_scalar!("SELECT TRUE;").fetch_one(&self.db_pool).await;
-        let connected = match connected {
-            Ok(connected) => connected.unwrap(),
-            Err(_) => false,
-        };
-
-        connected
+        is_pool_connected(&self.db_pool).await
     }
 
     async fn movie_store(&self) -> DynMovieStore {
         Arc::new(self.movie_store.clone()) as DynMovieStore
     }
+
+    async fn outbox_store(&self) -> DynOutboxStore {
+        Arc::new(self.movie_store.clone()) as DynOutboxStore
+    }
+
+    async fn idempotency_store(&self) -> DynIdempotencyStore {
+        Arc::new(self.movie_store.clone()) as DynIdempotencyStore
+    }
+
+    async fn replica_movie_store(&self) -> Option<DynMovieStore> {
+        let read_pool = self.read_pool.as_ref()?;
+        let movie_store = SqlMovieStore::new(self.db_pool.clone(), read_pool.clone());
+        Some(Arc::new(movie_store) as DynMovieStore)
+    }
+
+    async fn is_replica_connected(&self) -> Option<bool> {
+        match &self.read_pool {
+            Some(read_pool) => Some(is_pool_connected(read_pool).await),
+            None => None,
+        }
+    }
+
+    async fn movie_cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    async fn migrate(&self) -> Result<(), StoreError> {
+        MIGRATOR
+            .run(&self.db_pool)
+            .await
+            .map_err(|e| StoreError::Migration(e.to_string()))
+    }
+
+    async fn check_schema_version(&self) -> Result<(), StoreError> {
+        // the migrations table only exists once the first migration ran
+        let applied_version = sqlx::query_scalar::<_, Option<i64>>(
+            "SELECT MAX(version) FROM _sqlx_migrations WHERE success",
+        )
+        .fetch_one(&self.db_pool)
+        .await;
+        let applied_version = match applied_version {
+            Ok(version) => version,
+            Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some("42P01") => None,
+            Err(e) => return Err(e.into()),
+        };
+
+        check_applied_migration(applied_version, MIGRATOR.iter().map(|m| m.version).max())
+    }
 }
 
 #[derive(Clone)]
 pub struct SqlMovieStore {
     db_pool: PgPool,
+    /// Serves `get_all`, `find`, `search`, `stats`, `get_by_id` and `changes`, the same pool as
+    /// `db_pool` unless reading from a replica.
+    read_pool: PgPool,
 }
 
 impl SqlMovieStore {
-    fn new(db_pool: PgPool) -> Self {
-        SqlMovieStore { db_pool }
+    fn new(db_pool: PgPool, read_pool: PgPool) -> Self {
+        SqlMovieStore { db_pool, read_pool }
     }
 }
 
 #[async_trait]
 impl MovieStore for SqlMovieStore {
-    async fn get_all(&self) -> Vec<Movie> {
+    async fn get_all(
+        &self,
+        tenant_id: &str,
+        include_deleted: bool,
+    ) -> Result<Vec<Movie>, StoreError> {
         let movies = sqlx::query_as!(
             Movie,
             r#"
             SELECT
-                id, title, director, release_date, ticket_price, created_at, updated_at
+                id, tenant_id, title, director, release_date, ticket_price, created_at,
+                updated_at, version, deleted_at
             FROM movies
-            "#
+            WHERE tenant_id = $1 AND ($2 OR deleted_at IS NULL)
+            ORDER BY created_at, id
+            "#,
+            tenant_id,
+            include_deleted
         )
-        .fetch_all(&self.db_pool)
-        .await
-        .unwrap();
+        .fetch_all(&self.read_pool)
+        .await?;
 
-        movies
+        Ok(movies)
     }
 
-    async fn get_by_id(&self, id: Uuid) -> Option<Movie> {
-        let movie = sqlx::query_as!(
-            Movie,
+    fn stream_all(&self, tenant_id: &str, include_deleted: bool) -> MovieStream {
+        let read_pool = self.read_pool.clone();
+        let tenant_id = tenant_id.to_string();
+        spawn_movie_stream(|sender| async move {
+            let mut movies = sqlx::query_as!(
+                Movie,
+                r#"
+                SELECT
+                    id, tenant_id, title, director, release_date, ticket_price, created_at,
+                    updated_at, version, deleted_at
+                FROM movies
+                WHERE tenant_id = $1 AND ($2 OR deleted_at IS NULL)
+                ORDER BY created_at, id
+                "#,
+                tenant_id,
+                include_deleted
+            )
+            .fetch(&read_pool);
+            while let Some(movie) = movies.next().await {
+                let failed = movie.is_err();
+                if sender.send(movie.map_err(Into::into)).await.is_err() || failed {
+                    break;
+                }
+            }
+        })
+    }
+
+    async fn find(
+        &self,
+        tenant_id: &str,
+        query: &MovieQuery,
+        cursor: Option<MovieCursor>,
+        limit: u32,
+    ) -> Result<MoviePage, StoreError> {
+        if let Some(cursor) = &cursor {
+            cursor.check_sort(query.sort)?;
+        }
+
+        let mut builder = QueryBuilder::new(
             r#"
             SELECT
-                id, title, director, release_date, ticket_price, created_at, updated_at
+                id, tenant_id, title, director, release_date, ticket_price, created_at,
+                updated_at, version, deleted_at
             FROM movies
-            WHERE id = $1
+            WHERE tenant_id = "#,
+        );
+        builder.push_bind(tenant_id.to_string());
+        push_movie_filters(&mut builder, query);
+
+        let sort_column = sort_column(query.sort.field);
+        let (comparison, direction) = match query.sort.direction {
+            SortDirection::Asc => (">", "ASC"),
+            SortDirection::Desc => ("<", "DESC"),
+        };
+        if let Some(cursor) = cursor {
+            builder.push(format_args!(" AND ({}, id) {} (", sort_column, comparison));
+            match cursor.value {
+                SortValue::Text(value) => builder.push_bind(value),
+                SortValue::Timestamp(value) => builder.push_bind(value),
+                SortValue::Decimal(value) => builder.push_bind(value),
+            };
+            builder.push(", ").push_bind(cursor.id).push(")");
+        }
+        builder.push(format_args!(
+            " ORDER BY {} {}, id {}",
+            sort_column, direction, direction
+        ));
+        // fetch one extra row to find out if there is a next page
+        builder.push(" LIMIT ").push_bind(i64::from(limit) + 1);
+
+        let movies = builder
+            .build_query_as::<Movie>()
+            .fetch_all(&self.read_pool)
+            .await?;
+
+        Ok(MoviePage::from_rows(movies, limit, query.sort))
+    }
+
+    async fn search(
+        &self,
+        tenant_id: &str,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<MovieSearchResult>, StoreError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                id, tenant_id, title, director, release_date, ticket_price, created_at,
+                updated_at, version, deleted_at, ts_rank(search_vector, query) AS "score!"
+            FROM movies, plainto_tsquery('simple', $1) query
+            WHERE tenant_id = $3 AND search_vector @@ query AND deleted_at IS NULL
+            ORDER BY 11 DESC, created_at, id
+            LIMIT $2
             "#,
-            id
+            query,
+            i64::from(limit),
+            tenant_id
         )
-        .fetch_optional(&self.db_pool)
-        .await
-        .ok()?;
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        let results = rows
+            .into_iter()
+            .map(|row| MovieSearchResult {
+                movie: Movie {
+                    id: row.id,
+                    tenant_id: row.tenant_id,
+                    title: row.title,
+                    director: row.director,
+                    release_date: row.release_date,
+                    ticket_price: row.ticket_price,
+                    created_at: row.created_at,
+                    updated_at: row.updated_at,
+                    version: row.version,
+                    deleted_at: row.deleted_at,
+                },
+                score: row.score,
+            })
+            .collect();
 
-        movie
+        Ok(results)
     }
 
-    async fn create(&self, create_movie: CreateMovieParams) -> Result<Movie, String> {
-        let movie = sqlx::query_as!(
-            Movie,
+    async fn stats(
+        &self,
+        tenant_id: &str,
+        query: &MovieStatsQuery,
+    ) -> Result<MovieStats, StoreError> {
+        // GROUPING tells the sets apart: 3 is the overall row, 1 a director and 2 a year
+        let rows = sqlx::query!(
             r#"
-            INSERT INTO movies (id, title, director, release_date, ticket_price, created_at, updated_at)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)
-            RETURNING id, title, director, release_date, ticket_price, created_at, updated_at
+            SELECT
+                GROUPING(director, EXTRACT(YEAR FROM release_date)) AS "grouping!",
+                director AS "director?",
+                EXTRACT(YEAR FROM release_date)::INTEGER AS "release_year?",
+                COUNT(*) AS "count!",
+                COALESCE(SUM(ticket_price), 0) AS "total!",
+                MIN(ticket_price) AS min,
+                MAX(ticket_price) AS max
+            FROM movies
+            WHERE tenant_id = $1 AND deleted_at IS NULL
+                AND ($2::TIMESTAMP IS NULL OR release_date >= $2)
+                AND ($3::TIMESTAMP IS NULL OR release_date <= $3)
+            GROUP BY GROUPING SETS ((), (director), (EXTRACT(YEAR FROM release_date)))
             "#,
-            Uuid::new_v4(),
-            create_movie.title,
-            create_movie.director,
-            create_movie.release_date,
-            create_movie.ticket_price,
-            Utc::now().naive_utc(),
-            Utc::now().naive_utc()
+            tenant_id,
+            query.release_date_from,
+            query.release_date_to
         )
-        .fetch_one(&self.db_pool)
-        .await
-        .map_err(|e| e.to_string())?;
+        .fetch_all(&self.read_pool)
+        .await?;
 
-        Ok(movie)
+        let mut stats = MovieStats::default();
+        for row in rows {
+            let group = TicketPriceStats {
+                count: row.count,
+                total: row.total,
+                min: row.min,
+                max: row.max,
+            };
+            match (row.grouping, row.director, row.release_year) {
+                (1, Some(director), _) => {
+                    stats.by_director.insert(director, group);
+                }
+                (2, _, Some(release_year)) => {
+                    stats.by_release_year.insert(release_year, group);
+                }
+                _ => stats.overall = group,
+            }
+        }
+
+        Ok(stats)
     }
 
-    async fn update(&self, id: Uuid, movie_to_update: UpdateMovieParams) -> Result<Movie, String> {
+    async fn get_by_id(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        include_deleted: bool,
+    ) -> Result<Movie, StoreError> {
         let movie = sqlx::query_as!(
             Movie,
             r#"
             SELECT
-                id, title, director, release_date, ticket_price, created_at, updated_at
+                id, tenant_id, title, director, release_date, ticket_price, created_at,
+                updated_at, version, deleted_at
             FROM movies
-            WHERE id = $1
+            WHERE id = $1 AND tenant_id = $3 AND ($2 OR deleted_at IS NULL)
             "#,
-            id
+            id,
+            include_deleted,
+            tenant_id
         )
-        .fetch_one(&self.db_pool)
-        .await
-        .unwrap();
+        .fetch_one(&self.read_pool)
+        .await?;
 
-        let title = match movie_to_update.title {
-            Some(title) => title,
-            _ => movie.title,
-        };
-        let director = match movie_to_update.director {
-            Some(director) => director,
-            _ => movie.director,
-        };
-        let release_date = match movie_to_update.release_date {
-            Some(release_date) => release_date,
-            _ => movie.release_date,
-        };
-        let ticket_price = match movie_to_update.ticket_price {
-            Some(ticket_price) => ticket_price,
-            _ => movie.ticket_price,
-        };
+        Ok(movie)
+    }
+
+    async fn create(
+        &self,
+        tenant_id: &str,
+        create_movie: CreateMovieParams,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await?;
+        let movie = create_movie_in(&mut tx, tenant_id, Uuid::new_v4(), create_movie).await?;
+        tx.commit().await?;
+
+        Ok(movie)
+    }
+
+    async fn update(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        movie_to_update: UpdateMovieParams,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await?;
+        let movie =
+            update_movie_in(&mut tx, tenant_id, id, movie_to_update, expected_version).await?;
+        tx.commit().await?;
+
+        Ok(movie)
+    }
+
+    async fn delete(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await?;
+        let movie = delete_movie_in(&mut tx, tenant_id, id, expected_version).await?;
+        tx.commit().await?;
+
+        Ok(movie)
+    }
+
+    async fn restore(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await?;
+        let mut savepoint = tx.begin().await?;
         let movie = sqlx::query_as!(
             Movie,
             r#"
             UPDATE movies
-            SET title = $2, 
-                director = $3,
-                release_date = $4,
-                ticket_price = $5,
-                updated_at = $6
+            SET deleted_at = NULL,
+                version = version + 1
             WHERE id = $1
-            RETURNING id, title, director, release_date, ticket_price, created_at, updated_at
+                AND tenant_id = $3
+                AND deleted_at IS NOT NULL
+                AND ($2::BIGINT IS NULL OR version = $2)
+            RETURNING
+                id, tenant_id, title, director, release_date, ticket_price, created_at,
+                updated_at, version, deleted_at
             "#,
             id,
-            title,
-            director,
-            release_date,
-            ticket_price,
-            Utc::now().naive_utc()
+            expected_version,
+            tenant_id
         )
-        .fetch_one(&self.db_pool)
-        .await
-        .map_err(|e| e.to_string())?;
+        .fetch_optional(&mut savepoint)
+        .await;
+        let movie = match movie {
+            Ok(movie) => {
+                savepoint.commit().await?;
+                movie
+            }
+            Err(error) if is_duplicate_movie(&error) => {
+                savepoint.rollback().await?;
+                let deleted = sqlx::query!(
+                    "SELECT title, director, release_date FROM movies WHERE id = $1",
+                    id
+                )
+                .fetch_one(&mut tx)
+                .await?;
+                return Err(duplicate_movie_error(
+                    &mut tx,
+                    tenant_id,
+                    &deleted.title,
+                    &deleted.director,
+                    deleted.release_date,
+                )
+                .await?);
+            }
+            Err(error) => return Err(error.into()),
+        };
+
+        let Some(movie) = movie else {
+            if movie_exists(&mut tx, tenant_id, id, false).await? {
+                return Err(StoreError::Conflict("movie is not deleted".to_string()));
+            }
+            return Err(missing_movie_error(&mut tx, tenant_id, id, expected_version).await?);
+        };
+        insert_revision(&mut tx, &MovieRevision::deleted_or_restored(movie.clone())).await?;
+        insert_event(&mut tx, MovieEventKind::Updated, &movie).await?;
+        tx.commit().await?;
 
         Ok(movie)
     }
 
-    async fn delete(&self, id: Uuid) -> Result<Movie, String> {
+    async fn purge(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await?;
         let movie = sqlx::query_as!(
             Movie,
             r#"
-            DELETE FROM movies WHERE id = $1
-            RETURNING id, title, director, release_date, ticket_price, created_at, updated_at
+            DELETE FROM movies
+            WHERE id = $1 AND tenant_id = $3 AND ($2::BIGINT IS NULL OR version = $2)
+            RETURNING
+                id, tenant_id, title, director, release_date, ticket_price, created_at,
+                updated_at, version, deleted_at
             "#,
-            id
+            id,
+            expected_version,
+            tenant_id
         )
-        .fetch_one(&self.db_pool)
-        .await
-        .map_err(|e| e.to_string())?;
+        .fetch_optional(&mut tx)
+        .await?;
+
+        let Some(movie) = movie else {
+            return Err(missing_movie_error(&mut tx, tenant_id, id, expected_version).await?);
+        };
+        insert_event(&mut tx, MovieEventKind::Deleted, &movie).await?;
+        tx.commit().await?;
 
         Ok(movie)
     }
+
+    async fn history(&self, tenant_id: &str, id: Uuid) -> Result<Vec<MovieRevision>, StoreError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                movie_id, tenant_id, revision, title, director, release_date, ticket_price,
+                created_at, updated_at, deleted_at, changed_fields, recorded_at
+            FROM movie_revisions
+            WHERE movie_id = $1 AND tenant_id = $2
+            ORDER BY revision
+            "#,
+            id,
+            tenant_id
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        if rows.is_empty() {
+            return Err(StoreError::NotFound);
+        }
+
+        let revisions = rows
+            .into_iter()
+            .map(|row| MovieRevision {
+                revision: row.revision,
+                movie: Movie {
+                    id: row.movie_id,
+                    tenant_id: row.tenant_id,
+                    title: row.title,
+                    director: row.director,
+                    release_date: row.release_date,
+                    ticket_price: row.ticket_price,
+                    created_at: row.created_at,
+                    updated_at: row.updated_at,
+                    version: row.revision,
+                    deleted_at: row.deleted_at,
+                },
+                changed_fields: row.changed_fields,
+                recorded_at: row.recorded_at,
+            })
+            .collect();
+
+        Ok(revisions)
+    }
+
+    async fn batch(
+        &self,
+        tenant_id: &str,
+        operations: Vec<BatchOperation>,
+    ) -> Result<Vec<Movie>, BatchError> {
+        let mut tx = self.db_pool.begin().await.map_err(StoreError::from)?;
+        let mut movies = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.into_iter().enumerate() {
+            let movie = match operation {
+                BatchOperation::Create(movie) => {
+                    create_movie_in(&mut tx, tenant_id, Uuid::new_v4(), movie).await
+                }
+                BatchOperation::Update {
+                    id,
+                    movie,
+                    expected_version,
+                } => update_movie_in(&mut tx, tenant_id, id, movie, expected_version).await,
+                BatchOperation::Delete {
+                    id,
+                    expected_version,
+                } => delete_movie_in(&mut tx, tenant_id, id, expected_version).await,
+            }
+            .map_err(BatchError::at(index))?;
+            movies.push(movie);
+        }
+        tx.commit().await.map_err(StoreError::from)?;
+
+        Ok(movies)
+    }
+
+    async fn import(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<Result<ImportOutcome, StoreError>>, StoreError> {
+        let mut tx = self.db_pool.begin().await?;
+        let mut outcomes = Vec::with_capacity(movies.len());
+        for movie in movies {
+            // a failed movie only rolls back to its savepoint, the others are kept
+            let mut savepoint = tx.begin().await?;
+            let outcome = import_movie_in(&mut savepoint, tenant_id, movie, mode).await;
+            match outcome {
+                Ok(_) => savepoint.commit().await?,
+                Err(_) => savepoint.rollback().await?,
+            }
+            outcomes.push(outcome);
+        }
+        tx.commit().await?;
+
+        Ok(outcomes)
+    }
+
+    async fn import_all(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<ImportOutcome>, BatchError> {
+        let mut tx = self.db_pool.begin().await.map_err(StoreError::from)?;
+        let mut outcomes = Vec::with_capacity(movies.len());
+        for (index, movie) in movies.into_iter().enumerate() {
+            let outcome = import_movie_in(&mut tx, tenant_id, movie, mode)
+                .await
+                .map_err(BatchError::at(index))?;
+            outcomes.push(outcome);
+        }
+        tx.commit().await.map_err(StoreError::from)?;
+
+        Ok(outcomes)
+    }
+
+    async fn changes(
+        &self,
+        tenant_id: &str,
+        since: Option<ChangeCursor>,
+        limit: u32,
+    ) -> Result<ChangePage, StoreError> {
+        let rows = sqlx::query!(
+            r#"
+            SELECT
+                sequence, kind, movie_id, movie AS "movie: Json<Movie>", changed_at
+            FROM movie_changes
+            WHERE tenant_id = $1 AND sequence >= $2
+            ORDER BY sequence
+            LIMIT $3
+            "#,
+            tenant_id,
+            since.map_or(0, |since| since.sequence),
+            i64::from(limit) + 2
+        )
+        .fetch_all(&self.read_pool)
+        .await?;
+
+        let changes = rows
+            .into_iter()
+            .map(|row| {
+                Ok(MovieChange {
+                    sequence: row.sequence,
+                    kind: row.kind.parse()?,
+                    movie_id: row.movie_id,
+                    movie: row.movie.map(|movie| movie.0),
+                    changed_at: row.changed_at,
+                })
+            })
+            .collect::<Result<_, StoreError>>()?;
+
+        ChangePage::from_rows(changes, since, limit)
+    }
+}
+
+async fn create_movie_in(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant_id: &str,
+    id: Uuid,
+    create_movie: CreateMovieParams,
+) -> Result<Movie, StoreError> {
+    let mut savepoint = tx.begin().await?;
+    let movie = sqlx::query_as!(
+        Movie,
+        r#"
+        INSERT INTO movies (
+            id, tenant_id, title, director, release_date, ticket_price, created_at, updated_at,
+            title_folded, director_folded
+        )
+        VALUES ($1, $7, $2, $3, $4, $5, $6, $6, $8, $9)
+        RETURNING
+            id, tenant_id, title, director, release_date, ticket_price, created_at, updated_at,
+            version, deleted_at
+        "#,
+        id,
+        create_movie.title,
+        create_movie.director,
+        create_movie.release_date,
+        create_movie.ticket_price,
+        Utc::now().naive_utc(),
+        tenant_id,
+        fold_case(&create_movie.title),
+        fold_case(&create_movie.director)
+    )
+    .fetch_one(&mut savepoint)
+    .await;
+    let movie = match movie {
+        Ok(movie) => {
+            savepoint.commit().await?;
+            movie
+        }
+        Err(error) if is_duplicate_movie(&error) => {
+            savepoint.rollback().await?;
+            return Err(duplicate_movie_error(
+                tx,
+                tenant_id,
+                &create_movie.title,
+                &create_movie.director,
+                create_movie.release_date,
+            )
+            .await?);
+        }
+        Err(error) => return Err(error.into()),
+    };
+    insert_revision(tx, &MovieRevision::created(movie.clone())).await?;
+    insert_event(tx, MovieEventKind::Created, &movie).await?;
+
+    Ok(movie)
+}
+
+async fn update_movie_in(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant_id: &str,
+    id: Uuid,
+    movie_to_update: UpdateMovieParams,
+    expected_version: Option<i64>,
+) -> Result<Movie, StoreError> {
+    // one statement locks the row, merges the given fields into it and lists the fields that
+    // changed, so concurrent partial updates apply one after the other
+    let mut savepoint = tx.begin().await?;
+    let row = sqlx::query!(
+        r#"
+        UPDATE movies
+        SET title = COALESCE($3, movies.title),
+            director = COALESCE($4, movies.director),
+            title_folded = COALESCE($9, movies.title_folded),
+            director_folded = COALESCE($10, movies.director_folded),
+            release_date = COALESCE($5, movies.release_date),
+            ticket_price = COALESCE($6, movies.ticket_price),
+            updated_at = CASE
+                WHEN changes.changed_fields = '{}' THEN movies.updated_at
+                ELSE $7
+            END,
+            version = CASE
+                WHEN changes.changed_fields = '{}' THEN movies.version
+                ELSE movies.version + 1
+            END
+        FROM (
+            SELECT
+                id,
+                ARRAY_REMOVE(
+                    ARRAY[
+                        CASE WHEN $3 <> title THEN 'title' END,
+                        CASE WHEN $4 <> director THEN 'director' END,
+                        CASE WHEN $5 <> release_date THEN 'release_date' END,
+                        CASE WHEN $6 <> ticket_price THEN 'ticket_price' END
+                    ],
+                    NULL
+                ) AS changed_fields
+            FROM movies
+            WHERE id = $1
+                AND tenant_id = $2
+                AND deleted_at IS NULL
+                AND ($8::BIGINT IS NULL OR version = $8)
+            FOR UPDATE
+        ) AS changes
+        WHERE movies.id = changes.id
+        RETURNING
+            movies.id, movies.tenant_id, movies.title, movies.director, movies.release_date,
+            movies.ticket_price, movies.created_at, movies.updated_at, movies.version,
+            movies.deleted_at, changes.changed_fields AS "changed_fields!"
+        "#,
+        id,
+        tenant_id,
+        movie_to_update.title,
+        movie_to_update.director,
+        movie_to_update.release_date,
+        movie_to_update.ticket_price,
+        Utc::now().naive_utc(),
+        expected_version,
+        movie_to_update.title.as_deref().map(fold_case),
+        movie_to_update.director.as_deref().map(fold_case)
+    )
+    .fetch_optional(&mut savepoint)
+    .await;
+    let row = match row {
+        Ok(row) => {
+            savepoint.commit().await?;
+            row
+        }
+        Err(error) if is_duplicate_movie(&error) => {
+            savepoint.rollback().await?;
+            let previous = sqlx::query!(
+                "SELECT title, director, release_date FROM movies WHERE id = $1",
+                id
+            )
+            .fetch_one(&mut *tx)
+            .await?;
+            return Err(duplicate_movie_error(
+                tx,
+                tenant_id,
+                movie_to_update.title.as_deref().unwrap_or(&previous.title),
+                movie_to_update
+                    .director
+                    .as_deref()
+                    .unwrap_or(&previous.director),
+                movie_to_update
+                    .release_date
+                    .unwrap_or(previous.release_date),
+            )
+            .await?);
+        }
+        Err(error) => return Err(error.into()),
+    };
+
+    let Some(row) = row else {
+        return Err(missing_movie_error(tx, tenant_id, id, expected_version).await?);
+    };
+    let movie = Movie {
+        id: row.id,
+        tenant_id: row.tenant_id,
+        title: row.title,
+        director: row.director,
+        release_date: row.release_date,
+        ticket_price: row.ticket_price,
+        created_at: row.created_at,
+        updated_at: row.updated_at,
+        version: row.version,
+        deleted_at: row.deleted_at,
+    };
+    if row.changed_fields.is_empty() {
+        return Ok(movie);
+    }
+    insert_revision(tx, &MovieRevision::new(movie.clone(), row.changed_fields)).await?;
+    insert_event(tx, MovieEventKind::Updated, &movie).await?;
+
+    Ok(movie)
+}
+
+async fn import_movie_in(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant_id: &str,
+    movie: ImportMovie,
+    mode: ImportMode,
+) -> Result<ImportOutcome, StoreError> {
+    if !movie_exists(tx, tenant_id, movie.id, true).await? {
+        let movie = create_movie_in(tx, tenant_id, movie.id, movie.movie).await?;
+        return Ok(ImportOutcome::Created(movie));
+    }
+    match mode {
+        ImportMode::SkipExisting => Ok(ImportOutcome::Skipped),
+        ImportMode::Upsert => {
+            let movie = update_movie_in(tx, tenant_id, movie.id, movie.movie.into(), None).await?;
+            Ok(ImportOutcome::Updated(movie))
+        }
+    }
+}
+
+async fn delete_movie_in(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant_id: &str,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<Movie, StoreError> {
+    let movie = sqlx::query_as!(
+        Movie,
+        r#"
+        UPDATE movies
+        SET deleted_at = $3,
+            version = version + 1
+        WHERE id = $1
+            AND tenant_id = $4
+            AND deleted_at IS NULL
+            AND ($2::BIGINT IS NULL OR version = $2)
+        RETURNING
+            id, tenant_id, title, director, release_date, ticket_price, created_at, updated_at,
+            version, deleted_at
+        "#,
+        id,
+        expected_version,
+        Utc::now().naive_utc(),
+        tenant_id
+    )
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(movie) = movie else {
+        return Err(missing_movie_error(tx, tenant_id, id, expected_version).await?);
+    };
+    insert_revision(tx, &MovieRevision::deleted_or_restored(movie.clone())).await?;
+    insert_event(tx, MovieEventKind::Deleted, &movie).await?;
+
+    Ok(movie)
+}
+
+async fn movie_exists(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant_id: &str,
+    id: Uuid,
+    include_deleted: bool,
+) -> Result<bool, StoreError> {
+    let exists = sqlx::query_scalar!(
+        r#"
+        SELECT EXISTS (
+            SELECT 1 FROM movies WHERE id = $1 AND tenant_id = $3 AND ($2 OR deleted_at IS NULL)
+        ) AS "exists!"
+        "#,
+        id,
+        include_deleted,
+        tenant_id
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(exists)
+}
+
+fn is_duplicate_movie(error: &sqlx::Error) -> bool {
+    matches!(
+        error,
+        sqlx::Error::Database(database_error)
+            if database_error.constraint() == Some(UNIQUE_MOVIE_INDEX)
+    )
+}
+
+/// Finds the live movie a write rejected by the unique index would have duplicated, the write
+/// must have been rolled back to a savepoint so `tx` can still be used.
+async fn duplicate_movie_error(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant_id: &str,
+    title: &str,
+    director: &str,
+    release_date: NaiveDateTime,
+) -> Result<StoreError, StoreError> {
+    let id = sqlx::query_scalar!(
+        r#"
+        SELECT id
+        FROM movies
+        WHERE tenant_id = $1
+            AND title = $2
+            AND director = $3
+            AND release_date = $4
+            AND deleted_at IS NULL
+        "#,
+        tenant_id,
+        title,
+        director,
+        release_date
+    )
+    .fetch_one(&mut *tx)
+    .await?;
+
+    Ok(StoreError::Duplicate(id))
+}
+
+/// Tells apart a conditional write that matched no row because of the version from one that
+/// matched no row because the movie is gone.
+async fn missing_movie_error(
+    tx: &mut Transaction<'_, Postgres>,
+    tenant_id: &str,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<StoreError, StoreError> {
+    if expected_version.is_some() && movie_exists(tx, tenant_id, id, true).await? {
+        return Ok(StoreError::VersionMismatch);
+    }
+    Ok(StoreError::NotFound)
+}
+
+async fn insert_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    revision: &MovieRevision,
+) -> Result<(), StoreError> {
+    let movie = &revision.movie;
+    sqlx::query!(
+        r#"
+        INSERT INTO movie_revisions (
+            movie_id, tenant_id, revision, title, director, release_date, ticket_price,
+            created_at, updated_at, deleted_at, changed_fields, recorded_at
+        )
+        VALUES ($1, $12, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+        "#,
+        movie.id,
+        revision.revision,
+        movie.title,
+        movie.director,
+        movie.release_date,
+        movie.ticket_price,
+        movie.created_at,
+        movie.updated_at,
+        movie.deleted_at,
+        &revision.changed_fields,
+        revision.recorded_at,
+        movie.tenant_id
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+/// Records the change in the outbox and in the change feed of the movie's tenant.
+async fn insert_event(
+    tx: &mut Transaction<'_, Postgres>,
+    kind: MovieEventKind,
+    movie: &Movie,
+) -> Result<(), StoreError> {
+    let occurred_at = Utc::now().naive_utc();
+    sqlx::query!(
+        r#"
+        INSERT INTO outbox (kind, movie_id, payload, occurred_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        kind.name(),
+        movie.id,
+        Json(movie) as _,
+        occurred_at
+    )
+    .execute(&mut *tx)
+    .await?;
+    // bumping the tenant's sequence locks its row until the transaction ends, so changes of a
+    // tenant commit in sequence order
+    sqlx::query!(
+        r#"
+        WITH next AS (
+            INSERT INTO movie_change_sequences (tenant_id, last_sequence)
+            VALUES ($1, 1)
+            ON CONFLICT (tenant_id) DO UPDATE
+            SET last_sequence = movie_change_sequences.last_sequence + 1
+            RETURNING last_sequence
+        )
+        INSERT INTO movie_changes (tenant_id, sequence, kind, movie_id, movie, changed_at)
+        SELECT $1, last_sequence, $2, $3, $4, $5
+        FROM next
+        "#,
+        movie.tenant_id,
+        kind.name(),
+        movie.id,
+        (kind != MovieEventKind::Deleted).then_some(Json(movie)) as _,
+        occurred_at
+    )
+    .execute(&mut *tx)
+    .await?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl OutboxStore for SqlMovieStore {
+    /// Events locked by another dispatcher's claim are skipped rather than waited for.
+    async fn claim_pending_events(
+        &self,
+        limit: u32,
+        claimed_until: NaiveDateTime,
+    ) -> Result<Vec<MovieEvent>, StoreError> {
+        let mut rows = sqlx::query!(
+            r#"
+            UPDATE outbox
+            SET claimed_until = $2
+            WHERE id IN (
+                SELECT id
+                FROM outbox
+                WHERE delivered_at IS NULL AND (claimed_until IS NULL OR claimed_until <= $3)
+                ORDER BY id
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING id, kind, movie_id, payload AS "payload: Json<Movie>", occurred_at
+            "#,
+            i64::from(limit),
+            claimed_until,
+            Utc::now().naive_utc()
+        )
+        .fetch_all(&self.db_pool)
+        .await?;
+        rows.sort_by_key(|row| row.id);
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(MovieEvent {
+                    id: row.id,
+                    kind: row.kind.parse()?,
+                    movie_id: row.movie_id,
+                    payload: row.payload.0,
+                    occurred_at: row.occurred_at,
+                })
+            })
+            .collect()
+    }
+
+    async fn mark_delivered(&self, ids: &[i64]) -> Result<(), StoreError> {
+        sqlx::query!(
+            "UPDATE outbox SET delivered_at = $2 WHERE id = ANY($1)",
+            ids,
+            Utc::now().naive_utc()
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn release_events(&self, ids: &[i64]) -> Result<(), StoreError> {
+        sqlx::query!(
+            "UPDATE outbox SET claimed_until = NULL WHERE id = ANY($1) AND delivered_at IS NULL",
+            ids
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for SqlMovieStore {
+    async fn claim(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        request_hash: &str,
+        locked_until: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> Result<Option<IdempotentRequest>, StoreError> {
+        let now = Utc::now().naive_utc();
+        sqlx::query!("DELETE FROM idempotency_keys WHERE expires_at <= $1", now)
+            .execute(&self.db_pool)
+            .await?;
+
+        loop {
+            // a request that never finished, e.g. its instance went away, leaves its key without
+            // a response, a retry takes it over once the lock lapsed
+            let claimed = sqlx::query_scalar!(
+                r#"
+                INSERT INTO idempotency_keys (
+                    tenant_id, idempotency_key, request_hash, locked_until, expires_at
+                )
+                VALUES ($1, $2, $3, $4, $5)
+                ON CONFLICT (tenant_id, idempotency_key) DO UPDATE
+                SET locked_until = EXCLUDED.locked_until, expires_at = EXCLUDED.expires_at
+                WHERE idempotency_keys.status IS NULL
+                    AND idempotency_keys.request_hash = EXCLUDED.request_hash
+                    AND (idempotency_keys.locked_until IS NULL OR idempotency_keys.locked_until <= $6)
+                RETURNING TRUE AS "claimed!"
+                "#,
+                tenant_id,
+                key,
+                request_hash,
+                locked_until,
+                expires_at,
+                now
+            )
+            .fetch_optional(&self.db_pool)
+            .await?;
+            if claimed.is_some() {
+                return Ok(None);
+            }
+
+            let row = sqlx::query!(
+                r#"
+                SELECT request_hash, status, body
+                FROM idempotency_keys
+                WHERE tenant_id = $1 AND idempotency_key = $2
+                "#,
+                tenant_id,
+                key
+            )
+            .fetch_optional(&self.db_pool)
+            .await?;
+            // a key released between the two statements is claimed again
+            if let Some(row) = row {
+                let response = match (row.status, row.body) {
+                    (Some(status), Some(body)) => Some(IdempotentResponse {
+                        status: status as u16,
+                        body,
+                    }),
+                    _ => None,
+                };
+                return Ok(Some(IdempotentRequest {
+                    request_hash: row.request_hash,
+                    response,
+                }));
+            }
+        }
+    }
+
+    async fn complete(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        response: &IdempotentResponse,
+    ) -> Result<(), StoreError> {
+        sqlx::query!(
+            r#"
+            UPDATE idempotency_keys
+            SET status = $3, body = $4
+            WHERE tenant_id = $1 AND idempotency_key = $2
+            "#,
+            tenant_id,
+            key,
+            response.status as i16,
+            &response.body
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+
+    async fn release(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        locked_until: NaiveDateTime,
+    ) -> Result<(), StoreError> {
+        sqlx::query!(
+            r#"
+            DELETE FROM idempotency_keys
+            WHERE tenant_id = $1 AND idempotency_key = $2 AND status IS NULL AND locked_until = $3
+            "#,
+            tenant_id,
+            key,
+            locked_until
+        )
+        .execute(&self.db_pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+fn push_movie_filters(builder: &mut QueryBuilder<Postgres>, query: &MovieQuery) {
+    if !query.include_deleted {
+        builder.push(" AND deleted_at IS NULL");
+    }
+    if let Some(director) = &query.director {
+        builder
+            .push(" AND director_folded = ")
+            .push_bind(fold_case(director));
+    }
+    if let Some(title_contains) = &query.title_contains {
+        builder
+            .push(" AND strpos(title_folded, ")
+            .push_bind(fold_case(title_contains))
+            .push(") > 0");
+    }
+    if let Some(release_date_from) = query.release_date_from {
+        builder
+            .push(" AND release_date >= ")
+            .push_bind(release_date_from);
+    }
+    if let Some(release_date_to) = query.release_date_to {
+        builder
+            .push(" AND release_date <= ")
+            .push_bind(release_date_to);
+    }
+    if let Some(ticket_price_min) = &query.ticket_price_min {
+        builder
+            .push(" AND ticket_price >= ")
+            .push_bind(ticket_price_min.clone());
+    }
+    if let Some(ticket_price_max) = &query.ticket_price_max {
+        builder
+            .push(" AND ticket_price <= ")
+            .push_bind(ticket_price_max.clone());
+    }
+}
+
+/// Column expression to order by, text is compared byte wise to match the in memory ordering.
+fn sort_column(field: MovieSortField) -> &'static str {
+    match field {
+        MovieSortField::Title => r#"title COLLATE "C""#,
+        MovieSortField::Director => r#"director COLLATE "C""#,
+        MovieSortField::ReleaseDate => "release_date",
+        MovieSortField::TicketPrice => "ticket_price",
+        MovieSortField::CreatedAt => "created_at",
+        MovieSortField::UpdatedAt => "updated_at",
+    }
+}
+
+impl From<sqlx::Error> for StoreError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => StoreError::NotFound,
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
+                StoreError::Unavailable(error.to_string())
+            }
+            sqlx::Error::Io(_) | sqlx::Error::Tls(_) => StoreError::Unavailable(error.to_string()),
+            sqlx::Error::Database(ref database_error) => {
+                // https://www.postgresql.org/docs/current/errcodes-appendix.html
+                let code = database_error.code().unwrap_or_default();
+                match code.as_ref() {
+                    "23505" => StoreError::Conflict(database_error.message().to_string()),
+                    "57014" | "55P03" => StoreError::Timeout,
+                    code if code.starts_with("22") || code.starts_with("23") => {
+                        StoreError::Validation(database_error.message().to_string())
+                    }
+                    code if code.starts_with("08") || code.starts_with("57P") => {
+                        StoreError::Unavailable(database_error.message().to_string())
+                    }
+                    _ => StoreError::Unknown(error.to_string()),
+                }
+            }
+            _ => StoreError::Unknown(error.to_string()),
+        }
+    }
 }
@@ -0,0 +1,1468 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::cached_store::CacheStats;
+use super::store::{
+    check_applied_migration, fold_case, spawn_movie_stream, BatchError, BatchOperation,
+    ChangeCursor, ChangePage, CreateMovieParams, DynIdempotencyStore, DynMovieStore,
+    DynOutboxStore, IdempotencyStore, IdempotentRequest, IdempotentResponse, ImportMode,
+    ImportMovie, ImportOutcome, Movie, MovieChange, MovieCursor, MovieEvent, MovieEventKind,
+    MoviePage, MovieQuery, MovieRevision, MovieSearchResult, MovieSortField, MovieStats,
+    MovieStatsQuery, MovieStore, MovieStream, OutboxStore, SortDirection, SortValue, Store,
+    StoreError, TicketPriceStats, UpdateMovieParams,
+};
+use axum::async_trait;
+use bigdecimal::{BigDecimal, ToPrimitive};
+use chrono::{NaiveDateTime, Utc};
+use futures::StreamExt;
+use sqlx::error::DatabaseError;
+use sqlx::migrate::Migrator;
+use sqlx::sqlite::SqliteError;
+use sqlx::types::Json;
+use sqlx::{Acquire, QueryBuilder, Sqlite, SqlitePool, Transaction};
+use uuid::Uuid;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./db/sqlite/migrations");
+
+/// Timestamps are stored as fixed width text, so comparing them as text compares them in time.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.9f";
+
+const MOVIE_COLUMNS: &str = "id, tenant_id, title, director, release_date, ticket_price_cents, \
+    created_at, updated_at, version, deleted_at";
+
+pub struct SqliteStore {
+    db_pool: SqlitePool,
+    movie_store: SqliteMovieStore,
+}
+
+impl SqliteStore {
+    pub fn new(db_pool: SqlitePool) -> SqliteStore {
+        let movie_store = SqliteMovieStore::new(db_pool.clone());
+        Self {
+            db_pool,
+            movie_store,
+        }
+    }
+
+    /// Describes every set of live movies sharing a tenant, title, director and release date.
+    async fn duplicate_movies(&self) -> Result<Vec<String>, StoreError> {
+        let duplicates = sqlx::query_as::<_, (String, String, String, String, String)>(
+            r#"
+            SELECT tenant_id, title, director, release_date, group_concat(id, ', ')
+            FROM (SELECT * FROM movies WHERE deleted_at IS NULL ORDER BY created_at, id)
+            GROUP BY tenant_id, title, director, release_date
+            HAVING count(*) > 1
+            "#,
+        )
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+
+        Ok(duplicates
+            .into_iter()
+            .map(|(tenant_id, title, director, release_date, ids)| {
+                format!(
+                    "{} by {} released {} in tenant {}: {}",
+                    title, director, release_date, tenant_id, ids
+                )
+            })
+            .collect())
+    }
+
+    /// Folds the title and director of the movies stored before they were kept folded, which
+    /// sqlite can't do itself for anything but ASCII.
+    async fn fold_existing_movies(&self) -> Result<(), StoreError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        let movies = sqlx::query_as::<_, (String, String, String)>(
+            "SELECT id, title, director FROM movies \
+            WHERE title_folded IS NULL OR director_folded IS NULL",
+        )
+        .fetch_all(&mut tx)
+        .await
+        .map_err(sqlite_error)?;
+        for (id, title, director) in movies {
+            sqlx::query("UPDATE movies SET title_folded = ?, director_folded = ? WHERE id = ?")
+                .bind(fold_case(&title))
+                .bind(fold_case(&director))
+                .bind(id)
+                .execute(&mut tx)
+                .await
+                .map_err(sqlite_error)?;
+        }
+        tx.commit().await.map_err(sqlite_error)
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn is_connected(&self) -> bool {
+        sqlx::query("SELECT 1").execute(&self.db_pool).await.is_ok()
+    }
+
+    async fn movie_store(&self) -> DynMovieStore {
+        Arc::new(self.movie_store.clone()) as DynMovieStore
+    }
+
+    async fn outbox_store(&self) -> DynOutboxStore {
+        Arc::new(self.movie_store.clone()) as DynOutboxStore
+    }
+
+    async fn idempotency_store(&self) -> DynIdempotencyStore {
+        Arc::new(self.movie_store.clone()) as DynIdempotencyStore
+    }
+
+    async fn replica_movie_store(&self) -> Option<DynMovieStore> {
+        None
+    }
+
+    async fn is_replica_connected(&self) -> Option<bool> {
+        None
+    }
+
+    async fn movie_cache_stats(&self) -> Option<CacheStats> {
+        None
+    }
+
+    async fn migrate(&self) -> Result<(), StoreError> {
+        if let Err(e) = MIGRATOR.run(&self.db_pool).await {
+            // the unique index on movies can't be created over duplicates, sqlite can't name
+            // them from the migration itself
+            let duplicates = self.duplicate_movies().await.unwrap_or_default();
+            if duplicates.is_empty() {
+                return Err(StoreError::Migration(e.to_string()));
+            }
+            return Err(StoreError::Migration(format!(
+                "{}, delete or change the duplicate movies before migrating: {}",
+                e,
+                duplicates.join("; ")
+            )));
+        }
+        self.fold_existing_movies().await
+    }
+
+    async fn check_schema_version(&self) -> Result<(), StoreError> {
+        // the migrations table only exists once the first migration ran
+        let applied_version = sqlx::query_scalar::<_, Option<i64>>(
+            "SELECT MAX(version) FROM _sqlx_migrations WHERE success",
+        )
+        .fetch_one(&self.db_pool)
+        .await;
+        let applied_version = match applied_version {
+            Ok(version) => version,
+            Err(sqlx::Error::Database(e)) if e.message().contains("no such table") => None,
+            Err(e) => return Err(sqlite_error(e)),
+        };
+
+        check_applied_migration(applied_version, MIGRATOR.iter().map(|m| m.version).max())
+    }
+}
+
+#[derive(Clone)]
+pub struct SqliteMovieStore {
+    db_pool: SqlitePool,
+}
+
+impl SqliteMovieStore {
+    fn new(db_pool: SqlitePool) -> Self {
+        SqliteMovieStore { db_pool }
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct MovieRow {
+    id: String,
+    tenant_id: String,
+    title: String,
+    director: String,
+    release_date: String,
+    ticket_price_cents: i64,
+    created_at: String,
+    updated_at: String,
+    version: i64,
+    deleted_at: Option<String>,
+}
+
+impl TryFrom<MovieRow> for Movie {
+    type Error = StoreError;
+
+    fn try_from(row: MovieRow) -> Result<Self, Self::Error> {
+        Ok(Movie {
+            id: Uuid::parse_str(&row.id).map_err(|e| StoreError::Unknown(e.to_string()))?,
+            tenant_id: row.tenant_id,
+            title: row.title,
+            director: row.director,
+            release_date: parse_timestamp(&row.release_date)?,
+            ticket_price: from_cents(row.ticket_price_cents),
+            created_at: parse_timestamp(&row.created_at)?,
+            updated_at: parse_timestamp(&row.updated_at)?,
+            version: row.version,
+            deleted_at: row.deleted_at.as_deref().map(parse_timestamp).transpose()?,
+        })
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct MovieRevisionRow {
+    #[sqlx(flatten)]
+    movie: MovieRow,
+    changed_fields: String,
+    recorded_at: String,
+}
+
+/// One group of the stats, `grouping_set` is 0 for the overall row, 1 for a director and 2
+/// for a release year.
+#[derive(sqlx::FromRow)]
+struct TicketPriceStatsRow {
+    grouping_set: i64,
+    director: Option<String>,
+    release_year: Option<i64>,
+    count: i64,
+    total_cents: i64,
+    min_cents: Option<i64>,
+    max_cents: Option<i64>,
+}
+
+#[derive(sqlx::FromRow)]
+struct MovieSearchRow {
+    #[sqlx(flatten)]
+    movie: MovieRow,
+    score: f64,
+}
+
+#[async_trait]
+impl MovieStore for SqliteMovieStore {
+    async fn get_all(
+        &self,
+        tenant_id: &str,
+        include_deleted: bool,
+    ) -> Result<Vec<Movie>, StoreError> {
+        let rows = sqlx::query_as::<_, MovieRow>(&format!(
+            "SELECT {} FROM movies WHERE tenant_id = ? AND (? OR deleted_at IS NULL) \
+            ORDER BY created_at, id",
+            MOVIE_COLUMNS
+        ))
+        .bind(tenant_id)
+        .bind(include_deleted)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+
+        rows.into_iter().map(Movie::try_from).collect()
+    }
+
+    fn stream_all(&self, tenant_id: &str, include_deleted: bool) -> MovieStream {
+        let db_pool = self.db_pool.clone();
+        let tenant_id = tenant_id.to_string();
+        spawn_movie_stream(|sender| async move {
+            let query = format!(
+                "SELECT {} FROM movies WHERE tenant_id = ? AND (? OR deleted_at IS NULL) \
+                ORDER BY created_at, id",
+                MOVIE_COLUMNS
+            );
+            let mut rows = sqlx::query_as::<_, MovieRow>(&query)
+                .bind(&tenant_id)
+                .bind(include_deleted)
+                .fetch(&db_pool);
+            while let Some(row) = rows.next().await {
+                let movie = row.map_err(sqlite_error).and_then(Movie::try_from);
+                let failed = movie.is_err();
+                if sender.send(movie).await.is_err() || failed {
+                    break;
+                }
+            }
+        })
+    }
+
+    async fn find(
+        &self,
+        tenant_id: &str,
+        query: &MovieQuery,
+        cursor: Option<MovieCursor>,
+        limit: u32,
+    ) -> Result<MoviePage, StoreError> {
+        if let Some(cursor) = &cursor {
+            cursor.check_sort(query.sort)?;
+        }
+
+        let mut builder = QueryBuilder::new(format!(
+            "SELECT {} FROM movies WHERE tenant_id = ",
+            MOVIE_COLUMNS
+        ));
+        builder.push_bind(tenant_id.to_string());
+        push_movie_filters(&mut builder, query);
+
+        let sort_column = sort_column(query.sort.field);
+        let (comparison, direction) = match query.sort.direction {
+            SortDirection::Asc => (">", "ASC"),
+            SortDirection::Desc => ("<", "DESC"),
+        };
+        if let Some(cursor) = cursor {
+            builder.push(format_args!(" AND ({}, id) {} (", sort_column, comparison));
+            match cursor.value {
+                SortValue::Text(value) => builder.push_bind(value),
+                SortValue::Timestamp(value) => builder.push_bind(format_timestamp(&value)),
+                SortValue::Decimal(value) => builder.push_bind(to_cents(&value)?),
+            };
+            builder
+                .push(", ")
+                .push_bind(cursor.id.to_string())
+                .push(")");
+        }
+        builder.push(format_args!(
+            " ORDER BY {} {}, id {}",
+            sort_column, direction, direction
+        ));
+        // fetch one extra row to find out if there is a next page
+        builder.push(" LIMIT ").push_bind(i64::from(limit) + 1);
+
+        let rows = builder
+            .build_query_as::<MovieRow>()
+            .fetch_all(&self.db_pool)
+            .await
+            .map_err(sqlite_error)?;
+        let movies = rows
+            .into_iter()
+            .map(Movie::try_from)
+            .collect::<Result<_, _>>()?;
+
+        Ok(MoviePage::from_rows(movies, limit, query.sort))
+    }
+
+    async fn search(
+        &self,
+        tenant_id: &str,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<MovieSearchResult>, StoreError> {
+        let Some(match_expression) = fts_match_expression(query) else {
+            return Ok(Vec::new());
+        };
+
+        // bm25 is lower for better matches, the column weights favour the title
+        let rows = sqlx::query_as::<_, MovieSearchRow>(
+            r#"
+            SELECT
+                m.id, m.tenant_id, m.title, m.director, m.release_date, m.ticket_price_cents,
+                m.created_at, m.updated_at, m.version, m.deleted_at,
+                -bm25(movies_fts, 1.0, 0.4) AS score
+            FROM movies_fts
+            JOIN movies m ON m.rowid = movies_fts.rowid
+            WHERE movies_fts MATCH ? AND m.tenant_id = ? AND m.deleted_at IS NULL
+            ORDER BY score DESC, m.created_at, m.id
+            LIMIT ?
+            "#,
+        )
+        .bind(match_expression)
+        .bind(tenant_id)
+        .bind(i64::from(limit))
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(MovieSearchResult {
+                    movie: row.movie.try_into()?,
+                    score: row.score as f32,
+                })
+            })
+            .collect()
+    }
+
+    async fn stats(
+        &self,
+        tenant_id: &str,
+        query: &MovieStatsQuery,
+    ) -> Result<MovieStats, StoreError> {
+        // release dates are stored as text starting with the year
+        let rows = sqlx::query_as::<_, TicketPriceStatsRow>(
+            r#"
+            WITH matching AS (
+                SELECT
+                    director, CAST(substr(release_date, 1, 4) AS INTEGER) AS release_year,
+                    ticket_price_cents
+                FROM movies
+                WHERE tenant_id = ?1 AND deleted_at IS NULL
+                    AND (?2 IS NULL OR release_date >= ?2)
+                    AND (?3 IS NULL OR release_date <= ?3)
+            )
+            SELECT
+                0 AS grouping_set, NULL AS director, NULL AS release_year, COUNT(*) AS count,
+                COALESCE(SUM(ticket_price_cents), 0) AS total_cents,
+                MIN(ticket_price_cents) AS min_cents, MAX(ticket_price_cents) AS max_cents
+            FROM matching
+            UNION ALL
+            SELECT
+                1, director, NULL, COUNT(*), SUM(ticket_price_cents), MIN(ticket_price_cents),
+                MAX(ticket_price_cents)
+            FROM matching
+            GROUP BY director
+            UNION ALL
+            SELECT
+                2, NULL, release_year, COUNT(*), SUM(ticket_price_cents),
+                MIN(ticket_price_cents), MAX(ticket_price_cents)
+            FROM matching
+            GROUP BY release_year
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(query.release_date_from.as_ref().map(format_timestamp))
+        .bind(query.release_date_to.as_ref().map(format_timestamp))
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+
+        let mut stats = MovieStats::default();
+        for row in rows {
+            let group = TicketPriceStats {
+                count: row.count,
+                total: from_cents(row.total_cents),
+                min: row.min_cents.map(from_cents),
+                max: row.max_cents.map(from_cents),
+            };
+            match (row.grouping_set, row.director, row.release_year) {
+                (1, Some(director), _) => {
+                    stats.by_director.insert(director, group);
+                }
+                (2, _, Some(release_year)) => {
+                    let release_year = i32::try_from(release_year)
+                        .map_err(|e| StoreError::Unknown(e.to_string()))?;
+                    stats.by_release_year.insert(release_year, group);
+                }
+                _ => stats.overall = group,
+            }
+        }
+
+        Ok(stats)
+    }
+
+    async fn get_by_id(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        include_deleted: bool,
+    ) -> Result<Movie, StoreError> {
+        let row = sqlx::query_as::<_, MovieRow>(&format!(
+            "SELECT {} FROM movies WHERE id = ? AND tenant_id = ? AND (? OR deleted_at IS NULL)",
+            MOVIE_COLUMNS
+        ))
+        .bind(id.to_string())
+        .bind(tenant_id)
+        .bind(include_deleted)
+        .fetch_one(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+
+        row.try_into()
+    }
+
+    async fn create(
+        &self,
+        tenant_id: &str,
+        create_movie: CreateMovieParams,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        let movie = create_movie_in(&mut tx, tenant_id, Uuid::new_v4(), create_movie).await?;
+        tx.commit().await.map_err(sqlite_error)?;
+
+        Ok(movie)
+    }
+
+    async fn update(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        movie_to_update: UpdateMovieParams,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        let movie =
+            update_movie_in(&mut tx, tenant_id, id, movie_to_update, expected_version).await?;
+        tx.commit().await.map_err(sqlite_error)?;
+
+        Ok(movie)
+    }
+
+    async fn delete(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        let movie = delete_movie_in(&mut tx, tenant_id, id, expected_version).await?;
+        tx.commit().await.map_err(sqlite_error)?;
+
+        Ok(movie)
+    }
+
+    async fn restore(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        let row = sqlx::query_as::<_, MovieRow>(&format!(
+            r#"
+            UPDATE movies
+            SET deleted_at = NULL,
+                version = version + 1
+            WHERE id = ?
+                AND tenant_id = ?
+                AND deleted_at IS NOT NULL
+                AND (? IS NULL OR version = ?)
+            RETURNING {}
+            "#,
+            MOVIE_COLUMNS
+        ))
+        .bind(id.to_string())
+        .bind(tenant_id)
+        .bind(expected_version)
+        .bind(expected_version)
+        .fetch_optional(&mut tx)
+        .await;
+        let row = match row {
+            Err(error) if is_duplicate_movie(&error) => {
+                let (title, director, release_date) =
+                    sqlx::query_as::<_, (String, String, String)>(
+                        "SELECT title, director, release_date FROM movies WHERE id = ?",
+                    )
+                    .bind(id.to_string())
+                    .fetch_one(&mut tx)
+                    .await
+                    .map_err(sqlite_error)?;
+                return Err(duplicate_movie_error(
+                    &mut tx,
+                    tenant_id,
+                    &title,
+                    &director,
+                    &release_date,
+                )
+                .await?);
+            }
+            row => row.map_err(sqlite_error)?,
+        };
+
+        let Some(row) = row else {
+            if movie_exists(&mut tx, tenant_id, id, false).await? {
+                return Err(StoreError::Conflict("movie is not deleted".to_string()));
+            }
+            return Err(missing_movie_error(&mut tx, tenant_id, id, expected_version).await?);
+        };
+        let movie = Movie::try_from(row)?;
+        insert_revision(&mut tx, &MovieRevision::deleted_or_restored(movie.clone())).await?;
+        insert_event(&mut tx, MovieEventKind::Updated, &movie).await?;
+        tx.commit().await.map_err(sqlite_error)?;
+
+        Ok(movie)
+    }
+
+    async fn purge(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        // revisions go with the movie through the foreign key
+        let row = sqlx::query_as::<_, MovieRow>(&format!(
+            "DELETE FROM movies WHERE id = ? AND tenant_id = ? AND (? IS NULL OR version = ?) \
+            RETURNING {}",
+            MOVIE_COLUMNS
+        ))
+        .bind(id.to_string())
+        .bind(tenant_id)
+        .bind(expected_version)
+        .bind(expected_version)
+        .fetch_optional(&mut tx)
+        .await
+        .map_err(sqlite_error)?;
+
+        let Some(row) = row else {
+            return Err(missing_movie_error(&mut tx, tenant_id, id, expected_version).await?);
+        };
+        let movie = Movie::try_from(row)?;
+        insert_event(&mut tx, MovieEventKind::Deleted, &movie).await?;
+        tx.commit().await.map_err(sqlite_error)?;
+
+        Ok(movie)
+    }
+
+    async fn history(&self, tenant_id: &str, id: Uuid) -> Result<Vec<MovieRevision>, StoreError> {
+        let rows = sqlx::query_as::<_, MovieRevisionRow>(
+            r#"
+            SELECT
+                movie_id AS id, tenant_id, title, director, release_date, ticket_price_cents,
+                created_at, updated_at, revision AS version, deleted_at, changed_fields,
+                recorded_at
+            FROM movie_revisions
+            WHERE movie_id = ? AND tenant_id = ?
+            ORDER BY revision
+            "#,
+        )
+        .bind(id.to_string())
+        .bind(tenant_id)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+        if rows.is_empty() {
+            return Err(StoreError::NotFound);
+        }
+
+        rows.into_iter()
+            .map(|row| {
+                let movie = Movie::try_from(row.movie)?;
+                Ok(MovieRevision {
+                    revision: movie.version,
+                    movie,
+                    changed_fields: row
+                        .changed_fields
+                        .split(',')
+                        .filter(|field| !field.is_empty())
+                        .map(str::to_string)
+                        .collect(),
+                    recorded_at: parse_timestamp(&row.recorded_at)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn batch(
+        &self,
+        tenant_id: &str,
+        operations: Vec<BatchOperation>,
+    ) -> Result<Vec<Movie>, BatchError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        let mut movies = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.into_iter().enumerate() {
+            let movie = match operation {
+                BatchOperation::Create(movie) => {
+                    create_movie_in(&mut tx, tenant_id, Uuid::new_v4(), movie).await
+                }
+                BatchOperation::Update {
+                    id,
+                    movie,
+                    expected_version,
+                } => update_movie_in(&mut tx, tenant_id, id, movie, expected_version).await,
+                BatchOperation::Delete {
+                    id,
+                    expected_version,
+                } => delete_movie_in(&mut tx, tenant_id, id, expected_version).await,
+            }
+            .map_err(BatchError::at(index))?;
+            movies.push(movie);
+        }
+        tx.commit().await.map_err(sqlite_error)?;
+
+        Ok(movies)
+    }
+
+    async fn import(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<Result<ImportOutcome, StoreError>>, StoreError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        let mut outcomes = Vec::with_capacity(movies.len());
+        for movie in movies {
+            // a failed movie only rolls back to its savepoint, the others are kept
+            let mut savepoint = tx.begin().await.map_err(sqlite_error)?;
+            let outcome = import_movie_in(&mut savepoint, tenant_id, movie, mode).await;
+            match outcome {
+                Ok(_) => savepoint.commit().await,
+                Err(_) => savepoint.rollback().await,
+            }
+            .map_err(sqlite_error)?;
+            outcomes.push(outcome);
+        }
+        tx.commit().await.map_err(sqlite_error)?;
+
+        Ok(outcomes)
+    }
+
+    async fn import_all(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<ImportOutcome>, BatchError> {
+        let mut tx = self.db_pool.begin().await.map_err(sqlite_error)?;
+        let mut outcomes = Vec::with_capacity(movies.len());
+        for (index, movie) in movies.into_iter().enumerate() {
+            let outcome = import_movie_in(&mut tx, tenant_id, movie, mode)
+                .await
+                .map_err(BatchError::at(index))?;
+            outcomes.push(outcome);
+        }
+        tx.commit().await.map_err(sqlite_error)?;
+
+        Ok(outcomes)
+    }
+
+    async fn changes(
+        &self,
+        tenant_id: &str,
+        since: Option<ChangeCursor>,
+        limit: u32,
+    ) -> Result<ChangePage, StoreError> {
+        let rows = sqlx::query_as::<_, MovieChangeRow>(
+            r#"
+            SELECT sequence, kind, movie_id, movie, changed_at
+            FROM movie_changes
+            WHERE tenant_id = ? AND sequence >= ?
+            ORDER BY sequence
+            LIMIT ?
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(since.map_or(0, |since| since.sequence))
+        .bind(i64::from(limit) + 2)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+
+        let changes = rows
+            .into_iter()
+            .map(|row| {
+                Ok(MovieChange {
+                    sequence: row.sequence,
+                    kind: row.kind.parse()?,
+                    movie_id: Uuid::parse_str(&row.movie_id)
+                        .map_err(|e| StoreError::Unknown(e.to_string()))?,
+                    movie: row.movie.map(|movie| movie.0),
+                    changed_at: parse_timestamp(&row.changed_at)?,
+                })
+            })
+            .collect::<Result<_, StoreError>>()?;
+
+        ChangePage::from_rows(changes, since, limit)
+    }
+}
+
+async fn create_movie_in(
+    tx: &mut Transaction<'_, Sqlite>,
+    tenant_id: &str,
+    id: Uuid,
+    create_movie: CreateMovieParams,
+) -> Result<Movie, StoreError> {
+    let now = format_timestamp(&Utc::now().naive_utc());
+    let row = sqlx::query_as::<_, MovieRow>(&format!(
+        r#"
+        INSERT INTO movies ({}, title_folded, director_folded)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, 1, NULL, ?, ?)
+        RETURNING {}
+        "#,
+        MOVIE_COLUMNS, MOVIE_COLUMNS
+    ))
+    .bind(id.to_string())
+    .bind(tenant_id)
+    .bind(&create_movie.title)
+    .bind(&create_movie.director)
+    .bind(format_timestamp(&create_movie.release_date))
+    .bind(to_cents(&create_movie.ticket_price)?)
+    .bind(&now)
+    .bind(&now)
+    .bind(fold_case(&create_movie.title))
+    .bind(fold_case(&create_movie.director))
+    .fetch_one(&mut *tx)
+    .await;
+    let row = match row {
+        Err(error) if is_duplicate_movie(&error) => {
+            return Err(duplicate_movie_error(
+                tx,
+                tenant_id,
+                &create_movie.title,
+                &create_movie.director,
+                &format_timestamp(&create_movie.release_date),
+            )
+            .await?);
+        }
+        row => row.map_err(sqlite_error)?,
+    };
+    let movie = Movie::try_from(row)?;
+    insert_revision(tx, &MovieRevision::created(movie.clone())).await?;
+    insert_event(tx, MovieEventKind::Created, &movie).await?;
+
+    Ok(movie)
+}
+
+async fn update_movie_in(
+    tx: &mut Transaction<'_, Sqlite>,
+    tenant_id: &str,
+    id: Uuid,
+    movie_to_update: UpdateMovieParams,
+    expected_version: Option<i64>,
+) -> Result<Movie, StoreError> {
+    let release_date = movie_to_update.release_date.as_ref().map(format_timestamp);
+    let ticket_price_cents = movie_to_update
+        .ticket_price
+        .as_ref()
+        .map(to_cents)
+        .transpose()?;
+    // writing before reading anything makes the transaction wait for the write lock, one that
+    // read first fails outright when another connection is writing. Only the version and
+    // updated_at are written here, which keeps the search index out of it, and the returned
+    // row still has the fields as they were for the revision.
+    let previous = sqlx::query_as::<_, MovieRow>(&format!(
+        r#"
+        UPDATE movies
+        SET updated_at = ?,
+            version = version + 1
+        WHERE id = ? AND tenant_id = ? AND deleted_at IS NULL AND (? IS NULL OR version = ?)
+            AND NOT (
+                title IS COALESCE(?, title)
+                AND director IS COALESCE(?, director)
+                AND release_date IS COALESCE(?, release_date)
+                AND ticket_price_cents IS COALESCE(?, ticket_price_cents)
+            )
+        RETURNING {}
+        "#,
+        MOVIE_COLUMNS
+    ))
+    .bind(format_timestamp(&Utc::now().naive_utc()))
+    .bind(id.to_string())
+    .bind(tenant_id)
+    .bind(expected_version)
+    .bind(expected_version)
+    .bind(&movie_to_update.title)
+    .bind(&movie_to_update.director)
+    .bind(&release_date)
+    .bind(ticket_price_cents)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(sqlite_error)?;
+    let Some(previous) = previous else {
+        return unchanged_movie(tx, tenant_id, id, expected_version).await;
+    };
+    let previous = Movie::try_from(previous)?;
+
+    let title = movie_to_update.title.unwrap_or(previous.title.clone());
+    let director = movie_to_update
+        .director
+        .unwrap_or(previous.director.clone());
+    let release_date = release_date.unwrap_or(format_timestamp(&previous.release_date));
+    let row = sqlx::query_as::<_, MovieRow>(&format!(
+        r#"
+        UPDATE movies
+        SET title = ?,
+            director = ?,
+            title_folded = ?,
+            director_folded = ?,
+            release_date = ?,
+            ticket_price_cents = COALESCE(?, ticket_price_cents)
+        WHERE id = ?
+        RETURNING {}
+        "#,
+        MOVIE_COLUMNS
+    ))
+    .bind(&title)
+    .bind(&director)
+    .bind(fold_case(&title))
+    .bind(fold_case(&director))
+    .bind(&release_date)
+    .bind(ticket_price_cents)
+    .bind(id.to_string())
+    .fetch_one(&mut *tx)
+    .await;
+    let row = match row {
+        Err(error) if is_duplicate_movie(&error) => {
+            return Err(
+                duplicate_movie_error(tx, tenant_id, &title, &director, &release_date).await?,
+            );
+        }
+        row => row.map_err(sqlite_error)?,
+    };
+    let movie = Movie::try_from(row)?;
+    insert_revision(tx, &MovieRevision::updated(&previous, movie.clone())).await?;
+    insert_event(tx, MovieEventKind::Updated, &movie).await?;
+
+    Ok(movie)
+}
+
+async fn import_movie_in(
+    tx: &mut Transaction<'_, Sqlite>,
+    tenant_id: &str,
+    movie: ImportMovie,
+    mode: ImportMode,
+) -> Result<ImportOutcome, StoreError> {
+    if !movie_exists(tx, tenant_id, movie.id, true).await? {
+        let movie = create_movie_in(tx, tenant_id, movie.id, movie.movie).await?;
+        return Ok(ImportOutcome::Created(movie));
+    }
+    match mode {
+        ImportMode::SkipExisting => Ok(ImportOutcome::Skipped),
+        ImportMode::Upsert => {
+            let movie = update_movie_in(tx, tenant_id, movie.id, movie.movie.into(), None).await?;
+            Ok(ImportOutcome::Updated(movie))
+        }
+    }
+}
+
+async fn delete_movie_in(
+    tx: &mut Transaction<'_, Sqlite>,
+    tenant_id: &str,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<Movie, StoreError> {
+    let row = sqlx::query_as::<_, MovieRow>(&format!(
+        r#"
+        UPDATE movies
+        SET deleted_at = ?,
+            version = version + 1
+        WHERE id = ? AND tenant_id = ? AND deleted_at IS NULL AND (? IS NULL OR version = ?)
+        RETURNING {}
+        "#,
+        MOVIE_COLUMNS
+    ))
+    .bind(format_timestamp(&Utc::now().naive_utc()))
+    .bind(id.to_string())
+    .bind(tenant_id)
+    .bind(expected_version)
+    .bind(expected_version)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(sqlite_error)?;
+
+    let Some(row) = row else {
+        return Err(missing_movie_error(tx, tenant_id, id, expected_version).await?);
+    };
+    let movie = Movie::try_from(row)?;
+    insert_revision(tx, &MovieRevision::deleted_or_restored(movie.clone())).await?;
+    insert_event(tx, MovieEventKind::Deleted, &movie).await?;
+
+    Ok(movie)
+}
+
+async fn movie_exists(
+    tx: &mut Transaction<'_, Sqlite>,
+    tenant_id: &str,
+    id: Uuid,
+    include_deleted: bool,
+) -> Result<bool, StoreError> {
+    let exists = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (\
+            SELECT 1 FROM movies WHERE id = ? AND tenant_id = ? AND (? OR deleted_at IS NULL)\
+        )",
+    )
+    .bind(id.to_string())
+    .bind(tenant_id)
+    .bind(include_deleted)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(sqlite_error)?;
+
+    Ok(exists)
+}
+
+/// Whether a write was rejected by the unique index on the live movies of a tenant, sqlite
+/// names the indexed columns in the message.
+fn is_duplicate_movie(error: &sqlx::Error) -> bool {
+    let sqlx::Error::Database(database_error) = error else {
+        return false;
+    };
+    database_error.code().as_deref() == Some("2067")
+        && database_error
+            .message()
+            .contains("movies.tenant_id, movies.title, movies.director, movies.release_date")
+}
+
+/// Finds the live movie a write rejected by the unique index would have duplicated, sqlite
+/// only undoes the failed statement so `tx` can still be used.
+async fn duplicate_movie_error(
+    tx: &mut Transaction<'_, Sqlite>,
+    tenant_id: &str,
+    title: &str,
+    director: &str,
+    release_date: &str,
+) -> Result<StoreError, StoreError> {
+    let id = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT id
+        FROM movies
+        WHERE tenant_id = ?
+            AND title = ?
+            AND director = ?
+            AND release_date = ?
+            AND deleted_at IS NULL
+        "#,
+    )
+    .bind(tenant_id)
+    .bind(title)
+    .bind(director)
+    .bind(release_date)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(sqlite_error)?;
+
+    Uuid::parse_str(&id)
+        .map(StoreError::Duplicate)
+        .map_err(|e| StoreError::Unknown(e.to_string()))
+}
+
+/// Explains an update that wrote nothing, either the movie is missing, is at another version or
+/// the update leaves it as it is.
+async fn unchanged_movie(
+    tx: &mut Transaction<'_, Sqlite>,
+    tenant_id: &str,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<Movie, StoreError> {
+    let movie = sqlx::query_as::<_, MovieRow>(&format!(
+        "SELECT {} FROM movies WHERE id = ? AND tenant_id = ? AND deleted_at IS NULL",
+        MOVIE_COLUMNS
+    ))
+    .bind(id.to_string())
+    .bind(tenant_id)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(sqlite_error)?;
+    match movie {
+        None => Err(StoreError::NotFound),
+        Some(movie) if expected_version.is_some_and(|version| version != movie.version) => {
+            Err(StoreError::VersionMismatch)
+        }
+        // nothing changes, so neither do updated_at and the version
+        Some(movie) => movie.try_into(),
+    }
+}
+
+/// Tells apart a conditional write that matched no row because of the version from one that
+/// matched no row because the movie is gone.
+async fn missing_movie_error(
+    tx: &mut Transaction<'_, Sqlite>,
+    tenant_id: &str,
+    id: Uuid,
+    expected_version: Option<i64>,
+) -> Result<StoreError, StoreError> {
+    if expected_version.is_some() && movie_exists(tx, tenant_id, id, true).await? {
+        return Ok(StoreError::VersionMismatch);
+    }
+    Ok(StoreError::NotFound)
+}
+
+async fn insert_revision(
+    tx: &mut Transaction<'_, Sqlite>,
+    revision: &MovieRevision,
+) -> Result<(), StoreError> {
+    let movie = &revision.movie;
+    sqlx::query(
+        r#"
+        INSERT INTO movie_revisions (
+            movie_id, tenant_id, revision, title, director, release_date, ticket_price_cents,
+            created_at, updated_at, deleted_at, changed_fields, recorded_at
+        )
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(movie.id.to_string())
+    .bind(&movie.tenant_id)
+    .bind(revision.revision)
+    .bind(&movie.title)
+    .bind(&movie.director)
+    .bind(format_timestamp(&movie.release_date))
+    .bind(to_cents(&movie.ticket_price)?)
+    .bind(format_timestamp(&movie.created_at))
+    .bind(format_timestamp(&movie.updated_at))
+    .bind(movie.deleted_at.as_ref().map(format_timestamp))
+    .bind(revision.changed_fields.join(","))
+    .bind(format_timestamp(&revision.recorded_at))
+    .execute(&mut *tx)
+    .await
+    .map_err(sqlite_error)?;
+
+    Ok(())
+}
+
+/// Records the change in the outbox and in the change feed of the movie's tenant.
+async fn insert_event(
+    tx: &mut Transaction<'_, Sqlite>,
+    kind: MovieEventKind,
+    movie: &Movie,
+) -> Result<(), StoreError> {
+    let occurred_at = format_timestamp(&Utc::now().naive_utc());
+    sqlx::query("INSERT INTO outbox (kind, movie_id, payload, occurred_at) VALUES (?, ?, ?, ?)")
+        .bind(kind.name())
+        .bind(movie.id.to_string())
+        .bind(Json(movie))
+        .bind(&occurred_at)
+        .execute(&mut *tx)
+        .await
+        .map_err(sqlite_error)?;
+    // sqlite has a single writer, so changes commit in sequence order
+    let sequence: i64 = sqlx::query_scalar(
+        r#"
+        INSERT INTO movie_change_sequences (tenant_id, last_sequence)
+        VALUES (?, 1)
+        ON CONFLICT (tenant_id) DO UPDATE SET last_sequence = last_sequence + 1
+        RETURNING last_sequence
+        "#,
+    )
+    .bind(&movie.tenant_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(sqlite_error)?;
+    sqlx::query(
+        r#"
+        INSERT INTO movie_changes (tenant_id, sequence, kind, movie_id, movie, changed_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        "#,
+    )
+    .bind(&movie.tenant_id)
+    .bind(sequence)
+    .bind(kind.name())
+    .bind(movie.id.to_string())
+    .bind((kind != MovieEventKind::Deleted).then_some(Json(movie)))
+    .bind(&occurred_at)
+    .execute(&mut *tx)
+    .await
+    .map_err(sqlite_error)?;
+
+    Ok(())
+}
+
+#[derive(sqlx::FromRow)]
+struct MovieChangeRow {
+    sequence: i64,
+    kind: String,
+    movie_id: String,
+    movie: Option<Json<Movie>>,
+    changed_at: String,
+}
+
+#[derive(sqlx::FromRow)]
+struct MovieEventRow {
+    id: i64,
+    kind: String,
+    payload: Json<Movie>,
+    occurred_at: String,
+}
+
+#[async_trait]
+impl OutboxStore for SqliteMovieStore {
+    /// Claiming is a single write, sqlite runs it while no other connection writes.
+    async fn claim_pending_events(
+        &self,
+        limit: u32,
+        claimed_until: NaiveDateTime,
+    ) -> Result<Vec<MovieEvent>, StoreError> {
+        let mut rows = sqlx::query_as::<_, MovieEventRow>(
+            r#"
+            UPDATE outbox
+            SET claimed_until = ?
+            WHERE id IN (
+                SELECT id
+                FROM outbox
+                WHERE delivered_at IS NULL AND (claimed_until IS NULL OR claimed_until <= ?)
+                ORDER BY id
+                LIMIT ?
+            )
+            RETURNING id, kind, payload, occurred_at
+            "#,
+        )
+        .bind(format_timestamp(&claimed_until))
+        .bind(format_timestamp(&Utc::now().naive_utc()))
+        .bind(limit)
+        .fetch_all(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+        rows.sort_by_key(|row| row.id);
+
+        rows.into_iter()
+            .map(|row| {
+                let movie = row.payload.0;
+                Ok(MovieEvent {
+                    id: row.id,
+                    kind: row.kind.parse()?,
+                    movie_id: movie.id,
+                    payload: movie,
+                    occurred_at: parse_timestamp(&row.occurred_at)?,
+                })
+            })
+            .collect()
+    }
+
+    async fn mark_delivered(&self, ids: &[i64]) -> Result<(), StoreError> {
+        let mut builder = QueryBuilder::new("UPDATE outbox SET delivered_at = ");
+        builder.push_bind(format_timestamp(&Utc::now().naive_utc()));
+        self.update_events(builder, ids).await
+    }
+
+    async fn release_events(&self, ids: &[i64]) -> Result<(), StoreError> {
+        let builder = QueryBuilder::new("UPDATE outbox SET claimed_until = NULL");
+        self.update_events(builder, ids).await
+    }
+}
+
+impl SqliteMovieStore {
+    /// Runs `update`, an `UPDATE outbox SET ...` without a condition, on the events with `ids`.
+    async fn update_events(
+        &self,
+        mut update: QueryBuilder<'_, Sqlite>,
+        ids: &[i64],
+    ) -> Result<(), StoreError> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        update.push(" WHERE delivered_at IS NULL AND id IN (");
+        let mut separated = update.separated(", ");
+        for id in ids {
+            separated.push_bind(*id);
+        }
+        separated.push_unseparated(")");
+        update
+            .build()
+            .execute(&self.db_pool)
+            .await
+            .map_err(sqlite_error)?;
+
+        Ok(())
+    }
+}
+
+#[derive(sqlx::FromRow)]
+struct IdempotentRequestRow {
+    request_hash: String,
+    status: Option<i64>,
+    body: Option<Vec<u8>>,
+}
+
+#[async_trait]
+impl IdempotencyStore for SqliteMovieStore {
+    async fn claim(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        request_hash: &str,
+        locked_until: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> Result<Option<IdempotentRequest>, StoreError> {
+        let now = format_timestamp(&Utc::now().naive_utc());
+        sqlx::query("DELETE FROM idempotency_keys WHERE expires_at <= ?")
+            .bind(&now)
+            .execute(&self.db_pool)
+            .await
+            .map_err(sqlite_error)?;
+
+        loop {
+            // a request that never finished leaves its key without a response, a retry takes it
+            // over once the lock lapsed
+            let claimed = sqlx::query(
+                r#"
+                INSERT INTO idempotency_keys (
+                    tenant_id, idempotency_key, request_hash, locked_until, expires_at
+                )
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT (tenant_id, idempotency_key) DO UPDATE
+                SET locked_until = excluded.locked_until, expires_at = excluded.expires_at
+                WHERE idempotency_keys.status IS NULL
+                    AND idempotency_keys.request_hash = excluded.request_hash
+                    AND (idempotency_keys.locked_until IS NULL OR idempotency_keys.locked_until <= ?)
+                "#,
+            )
+            .bind(tenant_id)
+            .bind(key)
+            .bind(request_hash)
+            .bind(format_timestamp(&locked_until))
+            .bind(format_timestamp(&expires_at))
+            .bind(&now)
+            .execute(&self.db_pool)
+            .await
+            .map_err(sqlite_error)?;
+            if claimed.rows_affected() > 0 {
+                return Ok(None);
+            }
+
+            let row = sqlx::query_as::<_, IdempotentRequestRow>(
+                r#"
+                SELECT request_hash, status, body
+                FROM idempotency_keys
+                WHERE tenant_id = ? AND idempotency_key = ?
+                "#,
+            )
+            .bind(tenant_id)
+            .bind(key)
+            .fetch_optional(&self.db_pool)
+            .await
+            .map_err(sqlite_error)?;
+            // a key released between the two statements is claimed again
+            if let Some(row) = row {
+                let response = match (row.status, row.body) {
+                    (Some(status), Some(body)) => Some(IdempotentResponse {
+                        status: status as u16,
+                        body,
+                    }),
+                    _ => None,
+                };
+                return Ok(Some(IdempotentRequest {
+                    request_hash: row.request_hash,
+                    response,
+                }));
+            }
+        }
+    }
+
+    async fn complete(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        response: &IdempotentResponse,
+    ) -> Result<(), StoreError> {
+        sqlx::query(
+            r#"
+            UPDATE idempotency_keys
+            SET status = ?, body = ?
+            WHERE tenant_id = ? AND idempotency_key = ?
+            "#,
+        )
+        .bind(i64::from(response.status))
+        .bind(&response.body)
+        .bind(tenant_id)
+        .bind(key)
+        .execute(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+
+        Ok(())
+    }
+
+    async fn release(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        locked_until: NaiveDateTime,
+    ) -> Result<(), StoreError> {
+        sqlx::query(
+            r#"
+            DELETE FROM idempotency_keys
+            WHERE tenant_id = ? AND idempotency_key = ? AND status IS NULL AND locked_until = ?
+            "#,
+        )
+        .bind(tenant_id)
+        .bind(key)
+        .bind(format_timestamp(&locked_until))
+        .execute(&self.db_pool)
+        .await
+        .map_err(sqlite_error)?;
+
+        Ok(())
+    }
+}
+
+fn push_movie_filters(builder: &mut QueryBuilder<Sqlite>, query: &MovieQuery) {
+    if !query.include_deleted {
+        builder.push(" AND deleted_at IS NULL");
+    }
+    if let Some(director) = &query.director {
+        builder
+            .push(" AND director_folded = ")
+            .push_bind(fold_case(director));
+    }
+    if let Some(title_contains) = &query.title_contains {
+        builder
+            .push(" AND instr(title_folded, ")
+            .push_bind(fold_case(title_contains))
+            .push(") > 0");
+    }
+    if let Some(release_date_from) = &query.release_date_from {
+        builder
+            .push(" AND release_date >= ")
+            .push_bind(format_timestamp(release_date_from));
+    }
+    if let Some(release_date_to) = &query.release_date_to {
+        builder
+            .push(" AND release_date <= ")
+            .push_bind(format_timestamp(release_date_to));
+    }
+    // prices are whole cents, so the bounds are rounded inwards
+    if let Some(ticket_price_min) = &query.ticket_price_min {
+        builder
+            .push(" AND ticket_price_cents >= ")
+            .push_bind(cents_bound(ticket_price_min, true));
+    }
+    if let Some(ticket_price_max) = &query.ticket_price_max {
+        builder
+            .push(" AND ticket_price_cents <= ")
+            .push_bind(cents_bound(ticket_price_max, false));
+    }
+}
+
+fn sort_column(field: MovieSortField) -> &'static str {
+    match field {
+        MovieSortField::Title => "title",
+        MovieSortField::Director => "director",
+        MovieSortField::ReleaseDate => "release_date",
+        MovieSortField::TicketPrice => "ticket_price_cents",
+        MovieSortField::CreatedAt => "created_at",
+        MovieSortField::UpdatedAt => "updated_at",
+    }
+}
+
+/// Quotes every word so user input can't be read as fts5 query syntax, all words must match.
+fn fts_match_expression(query: &str) -> Option<String> {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    if words.is_empty() {
+        None
+    } else {
+        Some(words.join(" "))
+    }
+}
+
+fn format_timestamp(timestamp: &NaiveDateTime) -> String {
+    timestamp.format(TIMESTAMP_FORMAT).to_string()
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<NaiveDateTime, StoreError> {
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .map_err(|e| StoreError::Unknown(e.to_string()))
+}
+
+/// Converts a price to whole cents, rounding half away from zero like a Postgres `DECIMAL(12, 2)`.
+fn to_cents(price: &BigDecimal) -> Result<i64, StoreError> {
+    (price.round(2) * BigDecimal::from(100))
+        .to_i64()
+        .ok_or_else(|| StoreError::Validation("ticket price is out of range".to_string()))
+}
+
+/// Rounds a price range bound to whole cents towards the inside of the range.
+fn cents_bound(price: &BigDecimal, lower: bool) -> i64 {
+    let cents = price * BigDecimal::from(100);
+    // with_scale truncates towards zero
+    let mut bound = cents.with_scale(0);
+    if lower && bound < cents {
+        bound += BigDecimal::from(1);
+    } else if !lower && bound > cents {
+        bound -= BigDecimal::from(1);
+    }
+    bound.to_i64().unwrap_or(if cents > BigDecimal::from(0) {
+        i64::MAX
+    } else {
+        i64::MIN
+    })
+}
+
+fn from_cents(cents: i64) -> BigDecimal {
+    BigDecimal::new(cents.into(), 2)
+}
+
+/// Maps sqlite specific database errors, everything else is handled like any other sqlx error.
+fn sqlite_error(error: sqlx::Error) -> StoreError {
+    if let sqlx::Error::Database(database_error) = &error {
+        if let Some(sqlite_error) = database_error.try_downcast_ref::<SqliteError>() {
+            // https://www.sqlite.org/rescode.html
+            let code = sqlite_error.code().unwrap_or_default();
+            let message = sqlite_error.message().to_string();
+            return match i32::from_str(&code).unwrap_or_default() {
+                1555 | 2067 => StoreError::Conflict(message),
+                19 | 275 | 787 | 1299 => StoreError::Validation(message),
+                5 | 6 | 261 | 262 | 517 => StoreError::Timeout,
+                _ => StoreError::Unknown(message),
+            };
+        }
+    }
+    StoreError::from(error)
+}
@@ -1,38 +1,988 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
 use std::sync::Arc;
 
 use axum::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
 use bigdecimal::BigDecimal;
-use chrono::NaiveDateTime;
+use chrono::{Datelike, NaiveDateTime, Utc};
+use futures::stream::{self, BoxStream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
 use uuid::Uuid;
 
+use super::cached_store::CacheStats;
+
 pub type DynStore = Arc<dyn Store + Send + Sync>;
 
 #[async_trait]
 pub trait Store {
     async fn is_connected(&self) -> bool;
     async fn movie_store(&self) -> DynMovieStore;
+    /// The change events written by the movie store, for the dispatcher to deliver.
+    async fn outbox_store(&self) -> DynOutboxStore;
+    /// The responses kept for requests sent with an idempotency key.
+    async fn idempotency_store(&self) -> DynIdempotencyStore;
+    /// A movie store that reads from the read replica and writes to the primary, `None` when
+    /// there is no replica.
+    async fn replica_movie_store(&self) -> Option<DynMovieStore>;
+    /// Whether the read replica answers, `None` when there is no replica.
+    async fn is_replica_connected(&self) -> Option<bool>;
+    /// The counters of the movie cache, `None` when movies are not cached.
+    async fn movie_cache_stats(&self) -> Option<CacheStats>;
+    /// Applies pending schema migrations, stores without a schema do nothing.
+    async fn migrate(&self) -> Result<(), StoreError>;
+    /// Fails if the schema was migrated by a newer binary than this one.
+    async fn check_schema_version(&self) -> Result<(), StoreError>;
 }
 
 pub type DynMovieStore = Arc<dyn MovieStore + Send + Sync>;
 
+/// Movies belong to a tenant and every method only sees the movies of the `tenant_id` it is
+/// given, a movie of another tenant is reported as `NotFound`.
 #[async_trait]
 pub trait MovieStore {
-    async fn get_all(&self) -> Vec<Movie>;
-    async fn get_by_id(&self, id: Uuid) -> Option<Movie>;
-    async fn create(&self, movie: CreateMovieParams) -> Result<Movie, String>;
-    async fn update(&self, id: Uuid, movie: UpdateMovieParams) -> Result<Movie, String>;
-    async fn delete(&self, id: Uuid) -> Result<Movie, String>;
+    async fn get_all(
+        &self,
+        tenant_id: &str,
+        include_deleted: bool,
+    ) -> Result<Vec<Movie>, StoreError>;
+    /// Like `get_all`, but the movies are read as the stream is polled instead of all at once.
+    fn stream_all(&self, tenant_id: &str, include_deleted: bool) -> MovieStream;
+    /// Returns up to `limit` movies matching `query` in its sort order, starting after `cursor`.
+    async fn find(
+        &self,
+        tenant_id: &str,
+        query: &MovieQuery,
+        cursor: Option<MovieCursor>,
+        limit: u32,
+    ) -> Result<MoviePage, StoreError>;
+    /// Returns up to `limit` movies matching every word of `query` in the title or director,
+    /// most relevant first. Deleted movies are never returned.
+    async fn search(
+        &self,
+        tenant_id: &str,
+        query: &str,
+        limit: u32,
+    ) -> Result<Vec<MovieSearchResult>, StoreError>;
+    /// Counts and ticket price aggregates of the movies released within `query`, deleted movies
+    /// are left out.
+    async fn stats(
+        &self,
+        tenant_id: &str,
+        query: &MovieStatsQuery,
+    ) -> Result<MovieStats, StoreError>;
+    async fn get_by_id(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        include_deleted: bool,
+    ) -> Result<Movie, StoreError>;
+    async fn create(&self, tenant_id: &str, movie: CreateMovieParams) -> Result<Movie, StoreError>;
+    /// Applies `movie` and bumps the version, only if the stored version is `expected_version`
+    /// when one is given.
+    async fn update(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        movie: UpdateMovieParams,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError>;
+    /// Marks the movie as deleted, only if the stored version is `expected_version` when one is
+    /// given. Deleted movies are hidden from reads unless asked for and can be restored.
+    async fn delete(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError>;
+    /// Brings back a deleted movie, fails with `Conflict` if it is not deleted.
+    async fn restore(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError>;
+    /// Removes the movie for good, whether it is deleted or not, along with its history.
+    async fn purge(
+        &self,
+        tenant_id: &str,
+        id: Uuid,
+        expected_version: Option<i64>,
+    ) -> Result<Movie, StoreError>;
+    /// Returns every revision recorded for the movie, oldest first. Fails with `NotFound` when
+    /// there are none.
+    async fn history(&self, tenant_id: &str, id: Uuid) -> Result<Vec<MovieRevision>, StoreError>;
+    /// Applies the operations in order, all of them or none. Returns the movie each operation
+    /// left behind.
+    async fn batch(
+        &self,
+        tenant_id: &str,
+        operations: Vec<BatchOperation>,
+    ) -> Result<Vec<Movie>, BatchError>;
+    /// Writes each movie on its own, so a movie that fails doesn't fail the others. A movie
+    /// whose id the tenant already has is overwritten or left as it is depending on `mode`,
+    /// any other is created with its id. Returns what happened to each movie, in order, with
+    /// the movie written.
+    async fn import(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<Result<ImportOutcome, StoreError>>, StoreError>;
+    /// Like `import`, but in one transaction: the first movie that fails rolls back the others
+    /// and is reported by its index.
+    async fn import_all(
+        &self,
+        tenant_id: &str,
+        movies: Vec<ImportMovie>,
+        mode: ImportMode,
+    ) -> Result<Vec<ImportOutcome>, BatchError>;
+    /// Returns up to `limit` changes to the movies made after the change `since` points at,
+    /// oldest first, or from the first change without one. Fails with `CursorExpired` when the
+    /// store doesn't know that change.
+    async fn changes(
+        &self,
+        tenant_id: &str,
+        since: Option<ChangeCursor>,
+        limit: u32,
+    ) -> Result<ChangePage, StoreError>;
+}
+
+/// Movies read one at a time, in `(created_at, id)` order.
+pub type MovieStream = BoxStream<'static, Result<Movie, StoreError>>;
+
+/// How many movies a streaming read may run ahead of its reader.
+pub const MOVIE_STREAM_BUFFER: usize = 64;
+
+/// Streams the movies `produce` sends from a task of its own, which lets a store stream the
+/// rows of a query that borrows its pool. `produce` should stop once a send fails, the stream
+/// was dropped then.
+pub fn spawn_movie_stream<F, Fut>(produce: F) -> MovieStream
+where
+    F: FnOnce(mpsc::Sender<Result<Movie, StoreError>>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(MOVIE_STREAM_BUFFER);
+    tokio::spawn(produce(sender));
+    stream::unfold(receiver, |mut receiver| async move {
+        let movie = receiver.recv().await?;
+        Some((movie, receiver))
+    })
+    .boxed()
 }
 
+pub type DynOutboxStore = Arc<dyn OutboxStore + Send + Sync>;
+
+/// Change events recorded with each successful write, in the same transaction as the write.
+#[async_trait]
+pub trait OutboxStore {
+    /// Claims up to `limit` of the oldest events that are neither delivered nor claimed, until
+    /// `claimed_until`. Other dispatchers skip claimed events, a claim that runs out before the
+    /// events are delivered or released lets them be claimed again.
+    async fn claim_pending_events(
+        &self,
+        limit: u32,
+        claimed_until: NaiveDateTime,
+    ) -> Result<Vec<MovieEvent>, StoreError>;
+    /// Marks the events as delivered, they are not claimed again.
+    async fn mark_delivered(&self, ids: &[i64]) -> Result<(), StoreError>;
+    /// Gives up the claim on events that were not delivered, so they can be claimed again.
+    async fn release_events(&self, ids: &[i64]) -> Result<(), StoreError>;
+}
+
+pub type DynIdempotencyStore = Arc<dyn IdempotencyStore + Send + Sync>;
+
+/// Responses to requests sent with an idempotency key, so a retried request gets the first
+/// response instead of being applied again. Keys belong to a tenant and are forgotten once they
+/// expire.
+#[async_trait]
+pub trait IdempotencyStore {
+    /// Claims `key` for a request until `expires_at`, while it has no response the request only
+    /// holds it until `locked_until`. Returns `None` when the key was free, or held by the same
+    /// request past its lock, and the request should go ahead, or the request that holds the key
+    /// otherwise.
+    async fn claim(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        request_hash: &str,
+        locked_until: NaiveDateTime,
+        expires_at: NaiveDateTime,
+    ) -> Result<Option<IdempotentRequest>, StoreError>;
+    /// Keeps the response to a claimed key, retries get it from now on.
+    async fn complete(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        response: &IdempotentResponse,
+    ) -> Result<(), StoreError>;
+    /// Frees a key claimed until `locked_until` that has no response yet, so the request can be
+    /// sent again. A key another request took over since is left alone.
+    async fn release(
+        &self,
+        tenant_id: &str,
+        key: &str,
+        locked_until: NaiveDateTime,
+    ) -> Result<(), StoreError>;
+}
+
+/// The request holding an idempotency key.
 #[derive(Clone, Debug)]
+pub struct IdempotentRequest {
+    pub request_hash: String,
+    /// `None` while the first request is still running.
+    pub response: Option<IdempotentResponse>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdempotentResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Errors returned by store implementations, independent of the backend in use.
+#[derive(Debug)]
+pub enum StoreError {
+    /// The requested record does not exist.
+    NotFound,
+    /// The write clashes with existing data, e.g. a unique constraint.
+    Conflict(String),
+    /// A tenant's live movies must differ in title, director or release date, this is the id of
+    /// the movie the write would have duplicated.
+    Duplicate(Uuid),
+    /// The record was changed since the version the caller expected.
+    VersionMismatch,
+    /// The data was rejected by the store, e.g. a check constraint or an out of range value.
+    Validation(String),
+    /// The store could not be reached.
+    Unavailable(String),
+    /// The store did not answer in time.
+    Timeout,
+    /// The change feed no longer has the change a cursor points at, the reader has to start
+    /// over.
+    CursorExpired,
+    /// The schema could not be migrated or does not match this binary.
+    Migration(String),
+    /// Anything else.
+    Unknown(String),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StoreError::NotFound => write!(f, "not found"),
+            StoreError::Conflict(message) => write!(f, "conflict: {}", message),
+            StoreError::Duplicate(id) => write!(f, "duplicate of movie {}", id),
+            StoreError::VersionMismatch => write!(f, "version mismatch"),
+            StoreError::Validation(message) => write!(f, "validation error: {}", message),
+            StoreError::Unavailable(message) => write!(f, "store unavailable: {}", message),
+            StoreError::Timeout => write!(f, "store timed out"),
+            StoreError::CursorExpired => write!(f, "change cursor expired"),
+            StoreError::Migration(message) => write!(f, "migration error: {}", message),
+            StoreError::Unknown(message) => write!(f, "unknown error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+#[derive(Clone, Debug, sqlx::FromRow, Serialize, Deserialize)]
 pub struct Movie {
     pub id: Uuid,
+    /// The catalogue the movie belongs to.
+    #[serde(default = "default_tenant_id")]
+    pub tenant_id: String,
     pub title: String,
     pub director: String,
     pub release_date: NaiveDateTime,
     pub ticket_price: BigDecimal,
     pub created_at: NaiveDateTime,
     pub updated_at: NaiveDateTime,
+    /// Starts at 1 and goes up by one on every change, including delete and restore.
+    #[serde(default = "initial_version")]
+    pub version: i64,
+    /// Set while the movie is soft deleted.
+    #[serde(default)]
+    pub deleted_at: Option<NaiveDateTime>,
+}
+
+/// Tenant of requests that don't name one, and of movies stored before there were tenants.
+pub const DEFAULT_TENANT_ID: &str = "default";
+
+fn default_tenant_id() -> String {
+    DEFAULT_TENANT_ID.to_string()
+}
+
+// movies persisted before versions were introduced
+fn initial_version() -> i64 {
+    1
+}
+
+/// Folds text for the case insensitive filters of `MovieQuery`. The sql stores keep the title
+/// and director folded by this, so every store matches the same way whatever the database folds.
+pub fn fold_case(text: &str) -> String {
+    text.to_lowercase()
+}
+
+/// Filters and ordering applied when listing movies. Every filter is optional and inclusive.
+#[derive(Clone, Debug, Default)]
+pub struct MovieQuery {
+    /// Case insensitive match on the whole director name.
+    pub director: Option<String>,
+    /// Case insensitive match on part of the title.
+    pub title_contains: Option<String>,
+    pub release_date_from: Option<NaiveDateTime>,
+    pub release_date_to: Option<NaiveDateTime>,
+    pub ticket_price_min: Option<BigDecimal>,
+    pub ticket_price_max: Option<BigDecimal>,
+    /// Also returns soft deleted movies.
+    pub include_deleted: bool,
+    pub sort: MovieSort,
+}
+
+impl MovieQuery {
+    /// Evaluates the filters against a movie, stores that can't push them down use this.
+    pub fn matches(&self, movie: &Movie) -> bool {
+        if !self.include_deleted && movie.deleted_at.is_some() {
+            return false;
+        }
+        if let Some(director) = &self.director {
+            if fold_case(&movie.director) != fold_case(director) {
+                return false;
+            }
+        }
+        if let Some(title_contains) = &self.title_contains {
+            if !fold_case(&movie.title).contains(&fold_case(title_contains)) {
+                return false;
+            }
+        }
+        if let Some(release_date_from) = &self.release_date_from {
+            if movie.release_date < *release_date_from {
+                return false;
+            }
+        }
+        if let Some(release_date_to) = &self.release_date_to {
+            if movie.release_date > *release_date_to {
+                return false;
+            }
+        }
+        if let Some(ticket_price_min) = &self.ticket_price_min {
+            if movie.ticket_price < *ticket_price_min {
+                return false;
+            }
+        }
+        if let Some(ticket_price_max) = &self.ticket_price_max {
+            if movie.ticket_price > *ticket_price_max {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovieSortField {
+    Title,
+    Director,
+    ReleaseDate,
+    TicketPrice,
+    CreatedAt,
+    UpdatedAt,
+}
+
+impl MovieSortField {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MovieSortField::Title => "title",
+            MovieSortField::Director => "director",
+            MovieSortField::ReleaseDate => "release_date",
+            MovieSortField::TicketPrice => "ticket_price",
+            MovieSortField::CreatedAt => "created_at",
+            MovieSortField::UpdatedAt => "updated_at",
+        }
+    }
+
+    pub fn value(&self, movie: &Movie) -> SortValue {
+        match self {
+            MovieSortField::Title => SortValue::Text(movie.title.clone()),
+            MovieSortField::Director => SortValue::Text(movie.director.clone()),
+            MovieSortField::ReleaseDate => SortValue::Timestamp(movie.release_date),
+            MovieSortField::TicketPrice => SortValue::Decimal(movie.ticket_price.clone()),
+            MovieSortField::CreatedAt => SortValue::Timestamp(movie.created_at),
+            MovieSortField::UpdatedAt => SortValue::Timestamp(movie.updated_at),
+        }
+    }
+
+    fn parse_value(&self, value: &str) -> Option<SortValue> {
+        match self {
+            MovieSortField::Title | MovieSortField::Director => {
+                Some(SortValue::Text(value.to_string()))
+            }
+            MovieSortField::TicketPrice => BigDecimal::from_str(value).ok().map(SortValue::Decimal),
+            MovieSortField::ReleaseDate | MovieSortField::CreatedAt | MovieSortField::UpdatedAt => {
+                NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT)
+                    .ok()
+                    .map(SortValue::Timestamp)
+            }
+        }
+    }
+}
+
+impl FromStr for MovieSortField {
+    type Err = StoreError;
+
+    fn from_str(field: &str) -> Result<Self, Self::Err> {
+        match field {
+            "title" => Ok(MovieSortField::Title),
+            "director" => Ok(MovieSortField::Director),
+            "release_date" => Ok(MovieSortField::ReleaseDate),
+            "ticket_price" => Ok(MovieSortField::TicketPrice),
+            "created_at" => Ok(MovieSortField::CreatedAt),
+            "updated_at" => Ok(MovieSortField::UpdatedAt),
+            _ => Err(StoreError::Validation(format!(
+                "cannot sort by '{}'",
+                field
+            ))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+/// Ordering of a movie list, ties are broken by id in the same direction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MovieSort {
+    pub field: MovieSortField,
+    pub direction: SortDirection,
+}
+
+impl MovieSort {
+    pub fn compare(&self, a: &Movie, b: &Movie) -> Ordering {
+        let ordering = self
+            .field
+            .value(a)
+            .cmp(&self.field.value(b))
+            .then_with(|| a.id.cmp(&b.id));
+        match self.direction {
+            SortDirection::Asc => ordering,
+            SortDirection::Desc => ordering.reverse(),
+        }
+    }
+}
+
+impl Default for MovieSort {
+    fn default() -> Self {
+        MovieSort {
+            field: MovieSortField::CreatedAt,
+            direction: SortDirection::Asc,
+        }
+    }
+}
+
+impl fmt::Display for MovieSort {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let direction = match self.direction {
+            SortDirection::Asc => "asc",
+            SortDirection::Desc => "desc",
+        };
+        write!(f, "{}:{}", self.field.name(), direction)
+    }
+}
+
+/// Parses `field` or `field:asc|desc`.
+impl FromStr for MovieSort {
+    type Err = StoreError;
+
+    fn from_str(sort: &str) -> Result<Self, Self::Err> {
+        let (field, direction) = sort.split_once(':').unwrap_or((sort, "asc"));
+        let direction = match direction {
+            "asc" => SortDirection::Asc,
+            "desc" => SortDirection::Desc,
+            _ => {
+                return Err(StoreError::Validation(format!(
+                    "invalid sort direction '{}'",
+                    direction
+                )))
+            }
+        };
+
+        Ok(MovieSort {
+            field: field.parse()?,
+            direction,
+        })
+    }
+}
+
+/// Value of the field a movie list is sorted by.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortValue {
+    Text(String),
+    Timestamp(NaiveDateTime),
+    Decimal(BigDecimal),
+}
+
+impl fmt::Display for SortValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SortValue::Text(value) => write!(f, "{}", value),
+            SortValue::Timestamp(value) => write!(f, "{}", value.format(TIMESTAMP_FORMAT)),
+            SortValue::Decimal(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.f";
+
+/// Position of the last movie of a page, used for keyset pagination.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MovieCursor {
+    pub sort: MovieSort,
+    pub value: SortValue,
+    pub id: Uuid,
+}
+
+impl MovieCursor {
+    pub fn new(movie: &Movie, sort: MovieSort) -> Self {
+        MovieCursor {
+            sort,
+            value: sort.field.value(movie),
+            id: movie.id,
+        }
+    }
+
+    /// Checks the cursor was issued for the same ordering it is used with.
+    pub fn check_sort(&self, sort: MovieSort) -> Result<(), StoreError> {
+        if self.sort != sort {
+            return Err(StoreError::Validation(
+                "cursor does not match the requested sort".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Returns true if the movie comes after the cursor in the cursor's sort order.
+    pub fn is_before(&self, movie: &Movie) -> bool {
+        let ordering = self
+            .value
+            .cmp(&self.sort.field.value(movie))
+            .then_with(|| self.id.cmp(&movie.id));
+        match self.sort.direction {
+            SortDirection::Asc => ordering == Ordering::Less,
+            SortDirection::Desc => ordering == Ordering::Greater,
+        }
+    }
+
+    /// Encodes the cursor as an opaque, url safe token.
+    pub fn encode(&self) -> String {
+        let raw = format!("{}|{}|{}", self.sort, self.value, self.id);
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(token: &str) -> Result<Self, StoreError> {
+        let invalid = || StoreError::Validation("invalid cursor".to_string());
+
+        let raw = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        // values may contain the separator, the sort and the id never do
+        let (sort, rest) = raw.split_once('|').ok_or_else(invalid)?;
+        let (value, id) = rest.rsplit_once('|').ok_or_else(invalid)?;
+        let sort = MovieSort::from_str(sort).map_err(|_| invalid())?;
+
+        Ok(MovieCursor {
+            sort,
+            value: sort.field.parse_value(value).ok_or_else(invalid)?,
+            id: Uuid::parse_str(id).map_err(|_| invalid())?,
+        })
+    }
+}
+
+pub struct MoviePage {
+    pub movies: Vec<Movie>,
+    /// Cursor to pass to the next call, `None` when this is the last page.
+    pub next_cursor: Option<MovieCursor>,
+}
+
+impl MoviePage {
+    /// Builds a page from up to `limit + 1` rows, using the extra row only to detect a next page.
+    pub fn from_rows(mut movies: Vec<Movie>, limit: u32, sort: MovieSort) -> Self {
+        let next_cursor = if movies.len() > limit as usize {
+            movies.truncate(limit as usize);
+            movies.last().map(|movie| MovieCursor::new(movie, sort))
+        } else {
+            None
+        };
+
+        MoviePage {
+            movies,
+            next_cursor,
+        }
+    }
+}
+
+pub struct MovieSearchResult {
+    pub movie: Movie,
+    /// Relevance of the match, higher is better. Only comparable within one search.
+    pub score: f32,
+}
+
+/// Release date range the stats are computed over, both ends are optional and inclusive.
+#[derive(Clone, Debug, Default)]
+pub struct MovieStatsQuery {
+    pub release_date_from: Option<NaiveDateTime>,
+    pub release_date_to: Option<NaiveDateTime>,
+}
+
+impl MovieStatsQuery {
+    pub fn matches(&self, movie: &Movie) -> bool {
+        movie.deleted_at.is_none()
+            && self
+                .release_date_from
+                .is_none_or(|from| movie.release_date >= from)
+            && self
+                .release_date_to
+                .is_none_or(|to| movie.release_date <= to)
+    }
+}
+
+/// Decimal places the average ticket price is rounded to.
+pub const AVERAGE_TICKET_PRICE_SCALE: i64 = 4;
+
+/// Count and ticket price aggregates of a group of movies, exact whatever the store.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct TicketPriceStats {
+    pub count: i64,
+    pub total: BigDecimal,
+    pub min: Option<BigDecimal>,
+    pub max: Option<BigDecimal>,
+}
+
+impl TicketPriceStats {
+    pub fn add(&mut self, ticket_price: &BigDecimal) {
+        self.count += 1;
+        self.total += ticket_price;
+        if self.min.as_ref().is_none_or(|min| ticket_price < min) {
+            self.min = Some(ticket_price.clone());
+        }
+        if self.max.as_ref().is_none_or(|max| ticket_price > max) {
+            self.max = Some(ticket_price.clone());
+        }
+    }
+
+    /// Rounded to `AVERAGE_TICKET_PRICE_SCALE` decimal places, `None` for an empty group.
+    pub fn average(&self) -> Option<BigDecimal> {
+        (self.count > 0)
+            .then(|| (&self.total / BigDecimal::from(self.count)).round(AVERAGE_TICKET_PRICE_SCALE))
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MovieStats {
+    pub overall: TicketPriceStats,
+    pub by_director: BTreeMap<String, TicketPriceStats>,
+    pub by_release_year: BTreeMap<i32, TicketPriceStats>,
+}
+
+impl MovieStats {
+    pub fn add(&mut self, movie: &Movie) {
+        self.overall.add(&movie.ticket_price);
+        self.by_director
+            .entry(movie.director.clone())
+            .or_default()
+            .add(&movie.ticket_price);
+        self.by_release_year
+            .entry(movie.release_date.year())
+            .or_default()
+            .add(&movie.ticket_price);
+    }
+}
+
+/// Fields a revision can list as changed, besides `deleted_at`.
+const MOVIE_FIELDS: [&str; 4] = ["title", "director", "release_date", "ticket_price"];
+
+/// The state of a movie right after a change, recorded by every write.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MovieRevision {
+    /// Numbered like the movie version it captures.
+    pub revision: i64,
+    pub movie: Movie,
+    pub changed_fields: Vec<String>,
+    pub recorded_at: NaiveDateTime,
+}
+
+impl MovieRevision {
+    pub fn new(movie: Movie, changed_fields: Vec<String>) -> Self {
+        MovieRevision {
+            revision: movie.version,
+            movie,
+            changed_fields,
+            recorded_at: Utc::now().naive_utc(),
+        }
+    }
+
+    /// Revision for a newly created movie.
+    pub fn created(movie: Movie) -> Self {
+        let changed_fields = MOVIE_FIELDS.iter().map(|field| field.to_string()).collect();
+        Self::new(movie, changed_fields)
+    }
+
+    /// Revision for a movie that was deleted or restored.
+    pub fn deleted_or_restored(movie: Movie) -> Self {
+        Self::new(movie, vec!["deleted_at".to_string()])
+    }
+
+    /// Revision for an update, listing the fields that differ from `previous`.
+    pub fn updated(previous: &Movie, movie: Movie) -> Self {
+        let mut changed_fields = Vec::new();
+        if previous.title != movie.title {
+            changed_fields.push("title".to_string());
+        }
+        if previous.director != movie.director {
+            changed_fields.push("director".to_string());
+        }
+        if previous.release_date != movie.release_date {
+            changed_fields.push("release_date".to_string());
+        }
+        if previous.ticket_price != movie.ticket_price {
+            changed_fields.push("ticket_price".to_string());
+        }
+        Self::new(movie, changed_fields)
+    }
+}
+
+/// What happened to a movie. Restoring a movie is an update, soft deleting and purging it are
+/// both deletes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MovieEventKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl MovieEventKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MovieEventKind::Created => "created",
+            MovieEventKind::Updated => "updated",
+            MovieEventKind::Deleted => "deleted",
+        }
+    }
+}
+
+impl FromStr for MovieEventKind {
+    type Err = StoreError;
+
+    fn from_str(kind: &str) -> Result<Self, Self::Err> {
+        match kind {
+            "created" => Ok(MovieEventKind::Created),
+            "updated" => Ok(MovieEventKind::Updated),
+            "deleted" => Ok(MovieEventKind::Deleted),
+            _ => Err(StoreError::Unknown(format!(
+                "unknown event kind '{}'",
+                kind
+            ))),
+        }
+    }
+}
+
+/// A change to a movie waiting in the outbox, with the movie as the change left it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MovieEvent {
+    /// Handed out in the order events are written. Concurrent writes may commit in a different
+    /// order, so an event can be delivered after events with a higher id.
+    pub id: i64,
+    pub kind: MovieEventKind,
+    pub movie_id: Uuid,
+    pub payload: Movie,
+    pub occurred_at: NaiveDateTime,
+}
+
+/// A movie created, updated or deleted, numbered in the order the changes were made.
+#[derive(Clone, Debug)]
+pub struct MovieChange {
+    /// Goes up with every change to the movies of a tenant.
+    pub sequence: i64,
+    pub kind: MovieEventKind,
+    pub movie_id: Uuid,
+    /// The movie as the change left it, `None` for deletes.
+    pub movie: Option<Movie>,
+    pub changed_at: NaiveDateTime,
+}
+
+impl MovieChange {
+    pub fn new(
+        sequence: i64,
+        kind: MovieEventKind,
+        movie: Movie,
+        changed_at: NaiveDateTime,
+    ) -> Self {
+        MovieChange {
+            sequence,
+            kind,
+            movie_id: movie.id,
+            movie: (kind != MovieEventKind::Deleted).then_some(movie),
+            changed_at,
+        }
+    }
+
+    pub fn cursor(&self) -> ChangeCursor {
+        ChangeCursor {
+            changed_at: self.changed_at,
+            sequence: self.sequence,
+        }
+    }
+}
+
+/// Points at the last change a reader of the change feed has seen. The time of the change is
+/// checked along with its sequence, so a cursor from a store that started over is not mistaken
+/// for a position in the new feed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChangeCursor {
+    pub changed_at: NaiveDateTime,
+    pub sequence: i64,
+}
+
+impl ChangeCursor {
+    /// Encodes the cursor as an opaque, url safe token.
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}|{}",
+            self.changed_at.format("%Y-%m-%dT%H:%M:%S%.f"),
+            self.sequence
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(token: &str) -> Result<Self, StoreError> {
+        let invalid = || StoreError::Validation("invalid change cursor".to_string());
+
+        let raw = URL_SAFE_NO_PAD.decode(token).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let (changed_at, sequence) = raw.split_once('|').ok_or_else(invalid)?;
+
+        Ok(ChangeCursor {
+            changed_at: NaiveDateTime::from_str(changed_at).map_err(|_| invalid())?,
+            sequence: sequence.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+pub struct ChangePage {
+    pub changes: Vec<MovieChange>,
+    /// Whether there are changes after this page already.
+    pub has_more: bool,
+}
+
+impl ChangePage {
+    /// Builds a page from up to `limit + 2` changes read from the sequence of `since` on. The
+    /// change `since` points at has to come first, the extra change after the page is only used
+    /// to detect more changes.
+    pub fn from_rows(
+        mut changes: Vec<MovieChange>,
+        since: Option<ChangeCursor>,
+        limit: u32,
+    ) -> Result<Self, StoreError> {
+        if let Some(since) = since {
+            match changes.first() {
+                Some(change) if change.cursor() == since => {
+                    changes.remove(0);
+                }
+                _ => return Err(StoreError::CursorExpired),
+            }
+        }
+        let has_more = changes.len() > limit as usize;
+        changes.truncate(limit as usize);
+
+        Ok(ChangePage { changes, has_more })
+    }
+}
+
+/// Checks the latest migration applied to a database against the latest one known to this
+/// binary, a database that is ahead was migrated by a newer release.
+pub fn check_applied_migration(
+    applied_version: Option<i64>,
+    known_version: Option<i64>,
+) -> Result<(), StoreError> {
+    match (applied_version, known_version) {
+        (Some(applied), Some(known)) if applied > known => Err(StoreError::Migration(format!(
+            "database schema version {} is newer than the latest migration {} known to this binary",
+            applied, known
+        ))),
+        (Some(applied), None) => Err(StoreError::Migration(format!(
+            "database schema version {} is newer than this binary, which has no migrations",
+            applied
+        ))),
+        _ => Ok(()),
+    }
+}
+
+pub enum BatchOperation {
+    Create(CreateMovieParams),
+    Update {
+        id: Uuid,
+        movie: UpdateMovieParams,
+        expected_version: Option<i64>,
+    },
+    Delete {
+        id: Uuid,
+        expected_version: Option<i64>,
+    },
+}
+
+/// Why a batch was rolled back.
+#[derive(Debug)]
+pub struct BatchError {
+    /// The operation that failed, `None` when the batch as a whole could not be applied.
+    pub index: Option<usize>,
+    pub error: StoreError,
+}
+
+impl BatchError {
+    pub fn at(index: usize) -> impl FnOnce(StoreError) -> BatchError {
+        move |error| BatchError {
+            index: Some(index),
+            error,
+        }
+    }
+}
+
+impl From<StoreError> for BatchError {
+    fn from(error: StoreError) -> Self {
+        BatchError { index: None, error }
+    }
+}
+
+/// What an import does with a movie whose id the tenant already has.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportMode {
+    /// Overwrites the movie with the imported fields.
+    Upsert,
+    /// Leaves the movie as it is.
+    #[default]
+    SkipExisting,
+}
+
+pub struct ImportMovie {
+    pub id: Uuid,
+    pub movie: CreateMovieParams,
+}
+
+/// What an import did with a movie, with the movie as it left it.
+#[derive(Clone, Debug)]
+pub enum ImportOutcome {
+    Created(Movie),
+    Updated(Movie),
+    Skipped,
 }
 
 pub struct CreateMovieParams {
@@ -48,3 +998,14 @@ pub struct UpdateMovieParams {
     pub release_date: Option<NaiveDateTime>,
     pub ticket_price: Option<BigDecimal>,
 }
+
+impl From<CreateMovieParams> for UpdateMovieParams {
+    fn from(movie: CreateMovieParams) -> Self {
+        UpdateMovieParams {
+            title: Some(movie.title),
+            director: Some(movie.director),
+            release_date: Some(movie.release_date),
+            ticket_price: Some(movie.ticket_price),
+        }
+    }
+}
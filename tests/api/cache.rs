@@ -0,0 +1,136 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use movie_api::store::cached_store::{CacheStats, CachedStore};
+use movie_api::store::memory_store::MemoryStore;
+use movie_api::store::store::{DynStore, Store};
+use serde_json::json;
+
+use crate::helpers::TestApp;
+
+async fn cached(capacity: usize, ttl: Duration) -> (Arc<CachedStore>, TestApp) {
+    let store = Arc::new(MemoryStore::new()) as DynStore;
+    let cached_store = Arc::new(CachedStore::new(store, capacity, ttl).await);
+    let app = TestApp::new(cached_store.clone() as DynStore).await;
+    (cached_store, app)
+}
+
+async fn create(app: &TestApp, title: &str) -> String {
+    let (status, movie) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(json!({
+                "title": title,
+                "director": "Sergio Leone",
+                "release_date": "1966-12-23T00:00:00",
+                "ticket_price": 7.5,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    format!("/movies/{}", movie["id"].as_str().unwrap())
+}
+
+fn stats(hits: u64, misses: u64, evictions: u64) -> CacheStats {
+    CacheStats {
+        hits,
+        misses,
+        evictions,
+    }
+}
+
+#[tokio::test]
+async fn reads_are_cached_until_a_write() {
+    let (cached_store, app) = cached(10, Duration::from_secs(60)).await;
+    let uri = create(&app, "The Good, the Bad and the Ugly").await;
+
+    app.request("GET", &uri, None).await;
+    let (status, body) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["title"], "The Good, the Bad and the Ugly");
+    assert_eq!(
+        cached_store.movie_cache_stats().await.unwrap(),
+        stats(1, 1, 0)
+    );
+
+    let (status, _) = app
+        .request(
+            "PUT",
+            &uri,
+            Some(json!({ "title": "Il buono, il brutto, il cattivo" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, body) = app.request("GET", &uri, None).await;
+    assert_eq!(body["title"], "Il buono, il brutto, il cattivo");
+    assert_eq!(
+        cached_store.movie_cache_stats().await.unwrap(),
+        stats(1, 2, 0)
+    );
+
+    let (status, _) = app.request("DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn least_recently_used_movie_is_evicted() {
+    let (cached_store, app) = cached(2, Duration::from_secs(60)).await;
+    let first = create(&app, "A Fistful of Dollars").await;
+    let second = create(&app, "For a Few Dollars More").await;
+    let third = create(&app, "Once Upon a Time in the West").await;
+
+    for uri in [&first, &second, &first, &third] {
+        app.request("GET", uri, None).await;
+    }
+    assert_eq!(
+        cached_store.movie_cache_stats().await.unwrap(),
+        stats(1, 3, 1)
+    );
+
+    app.request("GET", &first, None).await;
+    app.request("GET", &second, None).await;
+    assert_eq!(
+        cached_store.movie_cache_stats().await.unwrap(),
+        stats(2, 4, 2)
+    );
+}
+
+#[tokio::test]
+async fn expired_movies_are_read_again() {
+    let (cached_store, app) = cached(10, Duration::ZERO).await;
+    let uri = create(&app, "Duck, You Sucker!").await;
+
+    app.request("GET", &uri, None).await;
+    app.request("GET", &uri, None).await;
+    assert_eq!(
+        cached_store.movie_cache_stats().await.unwrap(),
+        stats(0, 2, 0)
+    );
+}
+
+#[tokio::test]
+async fn counters_are_reported_by_health() {
+    let (_, app) = cached(1, Duration::from_secs(60)).await;
+    let first = create(&app, "My Name Is Nobody").await;
+    let second = create(&app, "A Genius, Two Partners and a Dupe").await;
+
+    for uri in [&first, &first, &second] {
+        app.request("GET", uri, None).await;
+    }
+    let (status, health) = app.request("GET", "/health", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        health["movie_cache"],
+        json!({ "hits": 1, "misses": 2, "evictions": 1 })
+    );
+
+    let (_, health) = TestApp::memory()
+        .await
+        .request("GET", "/health", None)
+        .await;
+    assert!(health.get("movie_cache").is_none());
+}
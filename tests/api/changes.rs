@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use movie_api::store::memory_store::MemoryStore;
+use movie_api::store::store::DynStore;
+
+use crate::helpers::TestApp;
+
+fn movie(title: &str) -> Value {
+    json!({
+        "title": title,
+        "director": "Kathryn Bigelow",
+        "release_date": "1991-07-12T00:00:00",
+        "ticket_price": 8.5,
+    })
+}
+
+/// Applies the change feed from `since` to `replica` a page at a time, returning where to
+/// carry on from.
+async fn sync(app: &TestApp, replica: &mut HashMap<String, Value>, since: Option<Value>) -> Value {
+    let mut since = since.unwrap_or(Value::Null);
+    loop {
+        let uri = match since.as_str() {
+            None => "/movies/changes?limit=2".to_string(),
+            Some(since) => format!("/movies/changes?limit=2&since={}", since),
+        };
+        let (status, page) = app.request("GET", &uri, None).await;
+        assert_eq!(status, StatusCode::OK, "{}", page);
+        for change in page["changes"].as_array().unwrap() {
+            let id = change["movie_id"].as_str().unwrap().to_string();
+            if change["kind"] == "deleted" {
+                assert!(change["movie"].is_null());
+                replica.remove(&id);
+            } else {
+                replica.insert(id, change["movie"].clone());
+            }
+        }
+        since = page["next_since"].clone();
+        if page["has_more"] == false {
+            return since;
+        }
+    }
+}
+
+async fn live_movies(app: &TestApp) -> HashMap<String, Value> {
+    let (_, page) = app.request("GET", "/movies", None).await;
+    page["movies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|movie| (movie["id"].as_str().unwrap().to_string(), movie.clone()))
+        .collect()
+}
+
+async fn assert_change_feed_replicates_the_catalogue(app: TestApp) {
+    let mut ids = Vec::new();
+    for title in ["Point Break", "Strange Days", "The Hurt Locker", "Detroit"] {
+        let (_, created) = app.request("POST", "/movies", Some(movie(title))).await;
+        ids.push(created["id"].as_str().unwrap().to_string());
+    }
+    app.request(
+        "PUT",
+        &format!("/movies/{}", ids[0]),
+        Some(json!({ "ticket_price": 9.0 })),
+    )
+    .await;
+    app.request("DELETE", &format!("/movies/{}", ids[1]), None)
+        .await;
+    app.request("DELETE", &format!("/movies/{}/purge", ids[2]), None)
+        .await;
+
+    let mut replica = HashMap::new();
+    let since = sync(&app, &mut replica, None).await;
+    assert_eq!(replica, live_movies(&app).await);
+    assert_eq!(replica[&ids[0]]["ticket_price"], 9.0);
+
+    // nothing new, the cursor stays where it was
+    let uri = format!("/movies/changes?since={}", since.as_str().unwrap());
+    let (_, page) = app.request("GET", &uri, None).await;
+    assert_eq!(page["changes"], json!([]));
+    assert_eq!(page["next_since"], since);
+
+    app.request("POST", &format!("/movies/{}/restore", ids[1]), None)
+        .await;
+    app.request("DELETE", &format!("/movies/{}", ids[3]), None)
+        .await;
+    sync(&app, &mut replica, Some(since)).await;
+    assert_eq!(replica, live_movies(&app).await);
+    assert!(replica.contains_key(&ids[1]) && !replica.contains_key(&ids[3]));
+}
+
+#[tokio::test]
+async fn change_feed_replicates_the_catalogue_for_memory_store() {
+    assert_change_feed_replicates_the_catalogue(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn change_feed_replicates_the_catalogue_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_change_feed_replicates_the_catalogue(app).await;
+    }
+}
+
+#[tokio::test]
+async fn change_feed_replicates_the_catalogue_for_sqlite_store() {
+    assert_change_feed_replicates_the_catalogue(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn memory_store_compacts_changes_older_than_the_retention() {
+    let store = MemoryStore::new().with_change_retention(2);
+    let app = TestApp::new(Arc::new(store) as DynStore).await;
+    let mut ids = Vec::new();
+    for title in ["Point Break", "Strange Days", "The Hurt Locker"] {
+        let (_, created) = app.request("POST", "/movies", Some(movie(title))).await;
+        ids.push(created["id"].as_str().unwrap().to_string());
+    }
+    app.request("DELETE", &format!("/movies/{}", ids[1]), None)
+        .await;
+    let mut since = None;
+    for price in 1..=10 {
+        app.request(
+            "PUT",
+            &format!("/movies/{}", ids[0]),
+            Some(json!({ "ticket_price": price })),
+        )
+        .await;
+        if price == 1 {
+            since = Some(sync(&app, &mut HashMap::new(), None).await);
+        }
+    }
+    let since = since.unwrap();
+
+    // the change the cursor points at was compacted away
+    let uri = format!("/movies/changes?since={}", since.as_str().unwrap());
+    let (status, body) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::GONE, "{}", body);
+
+    // the latest change of each movie is left, the deleted one as a tombstone
+    let (_, page) = app.request("GET", "/movies/changes?limit=100", None).await;
+    assert!(page["changes"].as_array().unwrap().len() <= 3 + 2 * 2);
+    let mut replica = HashMap::new();
+    sync(&app, &mut replica, None).await;
+    assert_eq!(replica, live_movies(&app).await);
+    assert_eq!(replica[&ids[0]]["ticket_price"], 10.0);
+}
+
+#[tokio::test]
+async fn invalid_change_cursor_returns_400() {
+    let app = TestApp::memory().await;
+
+    let (status, body) = app
+        .request("GET", "/movies/changes?since=not-a-cursor", None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error_message"], "validation error");
+}
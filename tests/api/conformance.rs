@@ -0,0 +1,774 @@
+//! Behaviour every `MovieStore` must share, run against each backend through a factory that
+//! returns a fresh `DynStore`.
+
+use std::future::Future;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bigdecimal::BigDecimal;
+use chrono::{NaiveDate, NaiveDateTime, Utc};
+use futures::TryStreamExt;
+use movie_api::store::cached_store::CachedStore;
+use movie_api::store::store::{
+    BatchError, BatchOperation, ChangeCursor, CreateMovieParams, DynMovieStore, DynStore,
+    ImportMode, ImportMovie, ImportOutcome, MovieEventKind, MovieQuery, MovieStatsQuery,
+    StoreError, TicketPriceStats, UpdateMovieParams,
+};
+use uuid::Uuid;
+
+use crate::helpers::{memory_store, sql_store, sqlite_store};
+
+const TENANT: &str = "conformance";
+const OTHER_TENANT: &str = "conformance-other";
+
+async fn run_conformance_suite<F, Fut>(new_store: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = DynStore>,
+{
+    let movie_store = || async { new_store().await.movie_store().await };
+
+    create_returns_the_stored_movie(movie_store().await).await;
+    get_by_id_of_missing_movie_is_not_found(movie_store().await).await;
+    update_changes_only_given_fields(movie_store().await).await;
+    update_bumps_updated_at_and_version(movie_store().await).await;
+    update_without_changes_leaves_the_movie_as_it_is(movie_store().await).await;
+    writes_to_missing_movie_are_not_found(movie_store().await).await;
+    stale_version_is_rejected(movie_store().await).await;
+    deleted_movie_is_hidden_until_restored(movie_store().await).await;
+    purged_movie_is_gone(movie_store().await).await;
+    other_tenants_cannot_see_or_change_the_movie(movie_store().await).await;
+    duplicate_movie_is_rejected(movie_store().await).await;
+    stream_all_reads_what_get_all_does(movie_store().await).await;
+    import_creates_skips_or_overwrites(movie_store().await).await;
+    filters_ignore_case_beyond_ascii(movie_store().await).await;
+    stats_aggregate_live_movies_exactly(movie_store().await).await;
+    changes_are_read_in_order_with_tombstones(movie_store().await).await;
+}
+
+fn release_date() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(1979, 5, 25)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+}
+
+/// Each movie gets a director of its own so that movies left behind by earlier runs against a
+/// shared database are never duplicates of it.
+fn alien() -> CreateMovieParams {
+    CreateMovieParams {
+        title: "Alien".to_string(),
+        director: format!("Ridley Scott {}", Uuid::new_v4().simple()),
+        release_date: release_date(),
+        ticket_price: BigDecimal::from_str("12.34").unwrap(),
+    }
+}
+
+async fn create(movie_store: &DynMovieStore) -> movie_api::store::store::Movie {
+    movie_store
+        .create(TENANT, alien())
+        .await
+        .expect("create failed")
+}
+
+fn rename(title: &str) -> UpdateMovieParams {
+    UpdateMovieParams {
+        title: Some(title.to_string()),
+        director: None,
+        release_date: None,
+        ticket_price: None,
+    }
+}
+
+async fn create_returns_the_stored_movie(movie_store: DynMovieStore) {
+    let params = alien();
+    let director = params.director.clone();
+    let before = Utc::now().naive_utc() - chrono::Duration::seconds(1);
+    let created = movie_store.create(TENANT, params).await.unwrap();
+    let after = Utc::now().naive_utc() + chrono::Duration::seconds(1);
+
+    assert_eq!(created.tenant_id, TENANT);
+    assert_eq!(created.title, "Alien");
+    assert_eq!(created.director, director);
+    assert_eq!(created.release_date, release_date());
+    assert_eq!(created.ticket_price, BigDecimal::from_str("12.34").unwrap());
+    assert_eq!(created.version, 1);
+    assert!(created.deleted_at.is_none());
+    assert!(before <= created.created_at && created.created_at <= after);
+    assert_eq!(created.created_at, created.updated_at);
+
+    let stored = movie_store
+        .get_by_id(TENANT, created.id, false)
+        .await
+        .unwrap();
+    assert_eq!(stored.id, created.id);
+    assert_eq!(stored.title, created.title);
+    assert_eq!(stored.release_date, created.release_date);
+    assert_eq!(stored.ticket_price, created.ticket_price);
+    assert_eq!(stored.created_at, created.created_at);
+    assert_eq!(stored.updated_at, created.updated_at);
+    assert_eq!(stored.version, created.version);
+
+    let all = movie_store.get_all(TENANT, false).await.unwrap();
+    assert!(all.iter().any(|movie| movie.id == created.id));
+}
+
+async fn get_by_id_of_missing_movie_is_not_found(movie_store: DynMovieStore) {
+    let result = movie_store.get_by_id(TENANT, Uuid::new_v4(), false).await;
+    assert!(matches!(result, Err(StoreError::NotFound)));
+    let result = movie_store.get_by_id(TENANT, Uuid::new_v4(), true).await;
+    assert!(matches!(result, Err(StoreError::NotFound)));
+}
+
+async fn update_changes_only_given_fields(movie_store: DynMovieStore) {
+    let created = create(&movie_store).await;
+
+    let updated = movie_store
+        .update(TENANT, created.id, rename("Aliens"), None)
+        .await
+        .unwrap();
+    assert_eq!(updated.title, "Aliens");
+    assert_eq!(updated.director, created.director);
+    assert_eq!(updated.release_date, created.release_date);
+    assert_eq!(updated.ticket_price, created.ticket_price);
+
+    let stored = movie_store
+        .get_by_id(TENANT, created.id, false)
+        .await
+        .unwrap();
+    assert_eq!(stored.title, "Aliens");
+    assert_eq!(stored.director, created.director);
+}
+
+async fn update_bumps_updated_at_and_version(movie_store: DynMovieStore) {
+    let created = create(&movie_store).await;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let updated = movie_store
+        .update(TENANT, created.id, rename("Alien 3"), None)
+        .await
+        .unwrap();
+    assert_eq!(updated.created_at, created.created_at);
+    assert!(updated.updated_at > created.updated_at);
+    assert_eq!(updated.version, created.version + 1);
+
+    let stored = movie_store
+        .get_by_id(TENANT, created.id, false)
+        .await
+        .unwrap();
+    assert_eq!(stored.updated_at, updated.updated_at);
+    assert_eq!(stored.version, updated.version);
+}
+
+async fn update_without_changes_leaves_the_movie_as_it_is(movie_store: DynMovieStore) {
+    let created = create(&movie_store).await;
+    tokio::time::sleep(Duration::from_millis(10)).await;
+
+    let updated = movie_store
+        .update(
+            TENANT,
+            created.id,
+            UpdateMovieParams {
+                title: Some(created.title.clone()),
+                director: None,
+                release_date: Some(created.release_date),
+                ticket_price: Some(BigDecimal::from_str("12.340").unwrap()),
+            },
+            Some(created.version),
+        )
+        .await
+        .unwrap();
+    assert_eq!(updated.updated_at, created.updated_at);
+    assert_eq!(updated.version, created.version);
+    let history = movie_store.history(TENANT, created.id).await.unwrap();
+    assert_eq!(history.len(), 1);
+
+    // the version is still checked
+    let result = movie_store
+        .update(
+            TENANT,
+            created.id,
+            rename("Alien"),
+            Some(created.version + 1),
+        )
+        .await;
+    assert!(matches!(result, Err(StoreError::VersionMismatch)));
+}
+
+async fn writes_to_missing_movie_are_not_found(movie_store: DynMovieStore) {
+    let id = Uuid::new_v4();
+    let result = movie_store
+        .update(TENANT, id, rename("Prometheus"), None)
+        .await;
+    assert!(matches!(result, Err(StoreError::NotFound)));
+    let result = movie_store
+        .update(TENANT, id, rename("Prometheus"), Some(1))
+        .await;
+    assert!(matches!(result, Err(StoreError::NotFound)));
+    assert!(matches!(
+        movie_store.delete(TENANT, id, None).await,
+        Err(StoreError::NotFound)
+    ));
+    assert!(matches!(
+        movie_store.restore(TENANT, id, None).await,
+        Err(StoreError::NotFound)
+    ));
+    assert!(matches!(
+        movie_store.purge(TENANT, id, None).await,
+        Err(StoreError::NotFound)
+    ));
+    assert!(matches!(
+        movie_store.history(TENANT, id).await,
+        Err(StoreError::NotFound)
+    ));
+}
+
+async fn stale_version_is_rejected(movie_store: DynMovieStore) {
+    let created = create(&movie_store).await;
+    movie_store
+        .update(TENANT, created.id, rename("Alien: Resurrection"), Some(1))
+        .await
+        .unwrap();
+
+    let result = movie_store
+        .update(TENANT, created.id, rename("Alien: Covenant"), Some(1))
+        .await;
+    assert!(matches!(result, Err(StoreError::VersionMismatch)));
+    let result = movie_store.delete(TENANT, created.id, Some(1)).await;
+    assert!(matches!(result, Err(StoreError::VersionMismatch)));
+
+    let stored = movie_store
+        .get_by_id(TENANT, created.id, false)
+        .await
+        .unwrap();
+    assert_eq!(stored.title, "Alien: Resurrection");
+    assert_eq!(stored.version, 2);
+}
+
+async fn deleted_movie_is_hidden_until_restored(movie_store: DynMovieStore) {
+    let created = create(&movie_store).await;
+
+    let deleted = movie_store.delete(TENANT, created.id, None).await.unwrap();
+    assert!(deleted.deleted_at.is_some());
+    assert!(matches!(
+        movie_store.get_by_id(TENANT, created.id, false).await,
+        Err(StoreError::NotFound)
+    ));
+    assert!(movie_store
+        .get_by_id(TENANT, created.id, true)
+        .await
+        .is_ok());
+    let all = movie_store.get_all(TENANT, false).await.unwrap();
+    assert!(all.iter().all(|movie| movie.id != created.id));
+    assert!(matches!(
+        movie_store.delete(TENANT, created.id, None).await,
+        Err(StoreError::NotFound)
+    ));
+
+    let restored = movie_store.restore(TENANT, created.id, None).await.unwrap();
+    assert!(restored.deleted_at.is_none());
+    assert!(movie_store
+        .get_by_id(TENANT, created.id, false)
+        .await
+        .is_ok());
+    assert!(matches!(
+        movie_store.restore(TENANT, created.id, None).await,
+        Err(StoreError::Conflict(_))
+    ));
+}
+
+async fn purged_movie_is_gone(movie_store: DynMovieStore) {
+    let created = create(&movie_store).await;
+
+    movie_store.purge(TENANT, created.id, None).await.unwrap();
+    assert!(matches!(
+        movie_store.get_by_id(TENANT, created.id, true).await,
+        Err(StoreError::NotFound)
+    ));
+    assert!(matches!(
+        movie_store.history(TENANT, created.id).await,
+        Err(StoreError::NotFound)
+    ));
+}
+
+async fn other_tenants_cannot_see_or_change_the_movie(movie_store: DynMovieStore) {
+    let created = create(&movie_store).await;
+    let id = created.id;
+
+    assert!(matches!(
+        movie_store.get_by_id(OTHER_TENANT, id, true).await,
+        Err(StoreError::NotFound)
+    ));
+    let all = movie_store.get_all(OTHER_TENANT, true).await.unwrap();
+    assert!(all.iter().all(|movie| movie.id != id));
+    let page = movie_store
+        .find(OTHER_TENANT, &MovieQuery::default(), None, 100)
+        .await
+        .unwrap();
+    assert!(page.movies.iter().all(|movie| movie.id != id));
+    let results = movie_store
+        .search(OTHER_TENANT, "Alien", 100)
+        .await
+        .unwrap();
+    assert!(results.iter().all(|result| result.movie.id != id));
+    assert!(matches!(
+        movie_store.history(OTHER_TENANT, id).await,
+        Err(StoreError::NotFound)
+    ));
+
+    let result = movie_store
+        .update(OTHER_TENANT, id, rename("Predator"), None)
+        .await;
+    assert!(matches!(result, Err(StoreError::NotFound)));
+    let result = movie_store
+        .update(OTHER_TENANT, id, rename("Predator"), Some(1))
+        .await;
+    assert!(matches!(result, Err(StoreError::NotFound)));
+    assert!(matches!(
+        movie_store.delete(OTHER_TENANT, id, Some(1)).await,
+        Err(StoreError::NotFound)
+    ));
+    assert!(matches!(
+        movie_store.purge(OTHER_TENANT, id, None).await,
+        Err(StoreError::NotFound)
+    ));
+    let result = movie_store
+        .batch(
+            OTHER_TENANT,
+            vec![BatchOperation::Delete {
+                id,
+                expected_version: None,
+            }],
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(BatchError {
+            index: Some(0),
+            error: StoreError::NotFound
+        })
+    ));
+
+    movie_store.delete(TENANT, id, None).await.unwrap();
+    assert!(matches!(
+        movie_store.restore(OTHER_TENANT, id, None).await,
+        Err(StoreError::NotFound)
+    ));
+
+    let stored = movie_store.get_by_id(TENANT, id, true).await.unwrap();
+    assert_eq!(stored.title, created.title);
+    assert_eq!(stored.version, created.version + 1);
+}
+
+async fn duplicate_movie_is_rejected(movie_store: DynMovieStore) {
+    let first = alien();
+    let params = || CreateMovieParams {
+        director: first.director.clone(),
+        ..alien()
+    };
+    let created = movie_store.create(TENANT, params()).await.unwrap();
+
+    let result = movie_store.create(TENANT, params()).await;
+    assert!(matches!(result, Err(StoreError::Duplicate(id)) if id == created.id));
+    let other = movie_store
+        .create(
+            TENANT,
+            CreateMovieParams {
+                title: "Aliens".to_string(),
+                ..params()
+            },
+        )
+        .await
+        .unwrap();
+    let result = movie_store
+        .update(TENANT, other.id, rename("Alien"), None)
+        .await;
+    assert!(matches!(result, Err(StoreError::Duplicate(id)) if id == created.id));
+    let result = movie_store
+        .batch(
+            TENANT,
+            vec![
+                BatchOperation::Update {
+                    id: other.id,
+                    movie: rename("Alien 3"),
+                    expected_version: None,
+                },
+                BatchOperation::Create(params()),
+            ],
+        )
+        .await;
+    assert!(matches!(
+        result,
+        Err(BatchError {
+            index: Some(1),
+            error: StoreError::Duplicate(id)
+        }) if id == created.id
+    ));
+    let stored = movie_store
+        .get_by_id(TENANT, other.id, false)
+        .await
+        .unwrap();
+    assert_eq!(stored.title, "Aliens");
+
+    // other tenants may have the same movie
+    movie_store.create(OTHER_TENANT, params()).await.unwrap();
+
+    // deleted movies don't count, until they are restored
+    movie_store.delete(TENANT, created.id, None).await.unwrap();
+    let replacement = movie_store.create(TENANT, params()).await.unwrap();
+    let result = movie_store.restore(TENANT, created.id, None).await;
+    assert!(matches!(result, Err(StoreError::Duplicate(id)) if id == replacement.id));
+}
+
+async fn stream_all_reads_what_get_all_does(movie_store: DynMovieStore) {
+    let deleted = create(&movie_store).await;
+    movie_store.delete(TENANT, deleted.id, None).await.unwrap();
+    create(&movie_store).await;
+
+    for include_deleted in [false, true] {
+        let streamed: Vec<_> = movie_store
+            .stream_all(TENANT, include_deleted)
+            .try_collect()
+            .await
+            .unwrap();
+        let all = movie_store.get_all(TENANT, include_deleted).await.unwrap();
+        let ids = |movies: &[movie_api::store::store::Movie]| {
+            movies.iter().map(|movie| movie.id).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&streamed), ids(&all));
+        assert_eq!(
+            streamed.iter().any(|movie| movie.id == deleted.id),
+            include_deleted
+        );
+    }
+}
+
+async fn import_creates_skips_or_overwrites(movie_store: DynMovieStore) {
+    let existing = create(&movie_store).await;
+    let id = Uuid::new_v4();
+    let import = |id, title: &str| ImportMovie {
+        id,
+        movie: CreateMovieParams {
+            title: title.to_string(),
+            director: existing.director.clone(),
+            ..alien()
+        },
+    };
+
+    let outcomes = movie_store
+        .import(
+            TENANT,
+            vec![import(id, "Prometheus"), import(existing.id, "Alien 3")],
+            ImportMode::SkipExisting,
+        )
+        .await
+        .unwrap();
+    assert!(matches!(
+        outcomes[..],
+        [Ok(ImportOutcome::Created(_)), Ok(ImportOutcome::Skipped)]
+    ));
+    let created = movie_store.get_by_id(TENANT, id, false).await.unwrap();
+    assert_eq!(created.title, "Prometheus");
+    let stored = movie_store
+        .get_by_id(TENANT, existing.id, false)
+        .await
+        .unwrap();
+    assert_eq!(stored.title, existing.title);
+
+    let outcomes = movie_store
+        .import(
+            TENANT,
+            vec![
+                import(existing.id, "Alien 3"),
+                import(Uuid::new_v4(), "Prometheus"),
+            ],
+            ImportMode::Upsert,
+        )
+        .await
+        .unwrap();
+    assert!(matches!(&outcomes[0], Ok(ImportOutcome::Updated(movie)) if movie.title == "Alien 3"));
+    // a failed movie doesn't undo the others
+    assert!(matches!(outcomes[1], Err(StoreError::Duplicate(duplicate)) if duplicate == id));
+    let stored = movie_store
+        .get_by_id(TENANT, existing.id, false)
+        .await
+        .unwrap();
+    assert_eq!(stored.title, "Alien 3");
+    assert_eq!(stored.version, existing.version + 1);
+
+    // ids are unique across tenants
+    let outcomes = movie_store
+        .import(
+            OTHER_TENANT,
+            vec![import(id, "Prometheus")],
+            ImportMode::Upsert,
+        )
+        .await
+        .unwrap();
+    assert!(outcomes[0].is_err());
+    let stored = movie_store.get_by_id(TENANT, id, false).await.unwrap();
+    assert_eq!(stored.tenant_id, TENANT);
+}
+
+async fn filters_ignore_case_beyond_ascii(movie_store: DynMovieStore) {
+    // a tenant of its own, so movies of earlier runs don't match
+    let tenant_id = format!("case-{}", Uuid::new_v4().simple());
+    let created = movie_store
+        .create(
+            &tenant_id,
+            CreateMovieParams {
+                title: "Le Fabuleux Destin d'Amélie Poulain".to_string(),
+                director: "Jean-Pierre Jeunet".to_string(),
+                ..alien()
+            },
+        )
+        .await
+        .unwrap();
+    movie_store
+        .create(
+            &tenant_id,
+            CreateMovieParams {
+                title: "Война и мир".to_string(),
+                director: "Сергей Бондарчук".to_string(),
+                ..alien()
+            },
+        )
+        .await
+        .unwrap();
+
+    let find = |query: MovieQuery| {
+        let movie_store = movie_store.clone();
+        let tenant_id = tenant_id.clone();
+        async move {
+            let page = movie_store
+                .find(&tenant_id, &query, None, 100)
+                .await
+                .unwrap();
+            page.movies
+                .into_iter()
+                .map(|movie| movie.title)
+                .collect::<Vec<_>>()
+        }
+    };
+    let title_contains = |title_contains: &str| MovieQuery {
+        title_contains: Some(title_contains.to_string()),
+        ..MovieQuery::default()
+    };
+    let director = |director: &str| MovieQuery {
+        director: Some(director.to_string()),
+        ..MovieQuery::default()
+    };
+    assert_eq!(
+        find(title_contains("AMÉLIE")).await,
+        ["Le Fabuleux Destin d'Amélie Poulain"]
+    );
+    assert_eq!(find(title_contains("ВОЙНА")).await, ["Война и мир"]);
+    assert!(find(title_contains("amelie")).await.is_empty());
+    assert_eq!(find(director("СЕРГЕЙ БОНДАРЧУК")).await, ["Война и мир"]);
+
+    // the folded title follows an update
+    movie_store
+        .update(&tenant_id, created.id, rename("Amélie ÉTÉ"), None)
+        .await
+        .unwrap();
+    assert_eq!(find(title_contains("amélie été")).await, ["Amélie ÉTÉ"]);
+}
+
+async fn stats_aggregate_live_movies_exactly(movie_store: DynMovieStore) {
+    // a tenant of its own, so movies of earlier runs don't count
+    let tenant_id = format!("stats-{}", Uuid::new_v4().simple());
+    let movie = |director: &str, year: i32, ticket_price: &str| CreateMovieParams {
+        title: format!("Alien {}", ticket_price),
+        director: director.to_string(),
+        release_date: NaiveDate::from_ymd_opt(year, 5, 25)
+            .unwrap()
+            .and_hms_opt(0, 0, 0)
+            .unwrap(),
+        ticket_price: BigDecimal::from_str(ticket_price).unwrap(),
+    };
+    let price = |ticket_price: &str| Some(BigDecimal::from_str(ticket_price).unwrap());
+
+    for params in [
+        movie("Ridley Scott", 1979, "10.00"),
+        movie("Ridley Scott", 1982, "10.50"),
+        movie("James Cameron", 1986, "12.25"),
+        movie("James Cameron", 1979, "0.10"),
+    ] {
+        movie_store.create(&tenant_id, params).await.unwrap();
+    }
+    let deleted = movie_store
+        .create(&tenant_id, movie("James Cameron", 1986, "99.99"))
+        .await
+        .unwrap();
+    movie_store
+        .delete(&tenant_id, deleted.id, None)
+        .await
+        .unwrap();
+
+    let stats = movie_store
+        .stats(&tenant_id, &MovieStatsQuery::default())
+        .await
+        .unwrap();
+    assert_eq!(stats.overall.count, 4);
+    assert_eq!(stats.overall.total, BigDecimal::from_str("32.85").unwrap());
+    assert_eq!(stats.overall.min, price("0.10"));
+    assert_eq!(stats.overall.max, price("12.25"));
+    // 32.85 / 4 is exact, no binary floating point rounding on the way
+    assert_eq!(stats.overall.average(), price("8.2125"));
+    assert_eq!(
+        stats.by_director.keys().collect::<Vec<_>>(),
+        ["James Cameron", "Ridley Scott"]
+    );
+    assert_eq!(
+        stats.by_director["Ridley Scott"],
+        TicketPriceStats {
+            count: 2,
+            total: BigDecimal::from_str("20.50").unwrap(),
+            min: price("10.00"),
+            max: price("10.50"),
+        }
+    );
+    assert_eq!(
+        stats.by_release_year.keys().copied().collect::<Vec<_>>(),
+        [1979, 1982, 1986]
+    );
+    assert_eq!(stats.by_release_year[&1979].count, 2);
+    assert_eq!(stats.by_release_year[&1979].average(), price("5.05"));
+    assert_eq!(stats.by_release_year[&1986].count, 1);
+
+    let in_the_eighties = movie_store
+        .stats(
+            &tenant_id,
+            &MovieStatsQuery {
+                release_date_from: NaiveDate::from_ymd_opt(1980, 1, 1)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0),
+                release_date_to: NaiveDate::from_ymd_opt(1986, 5, 25)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0),
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(in_the_eighties.overall.count, 2);
+    assert_eq!(in_the_eighties.overall.average(), price("11.375"));
+    assert_eq!(
+        in_the_eighties
+            .by_release_year
+            .keys()
+            .copied()
+            .collect::<Vec<_>>(),
+        [1982, 1986]
+    );
+
+    let empty = movie_store
+        .stats(
+            OTHER_TENANT,
+            &MovieStatsQuery {
+                release_date_from: NaiveDate::from_ymd_opt(3000, 1, 1)
+                    .unwrap()
+                    .and_hms_opt(0, 0, 0),
+                release_date_to: None,
+            },
+        )
+        .await
+        .unwrap();
+    assert_eq!(empty.overall, TicketPriceStats::default());
+    assert_eq!(empty.overall.average(), None);
+    assert!(empty.by_director.is_empty() && empty.by_release_year.is_empty());
+}
+
+async fn changes_are_read_in_order_with_tombstones(movie_store: DynMovieStore) {
+    // a tenant of its own, so the feed starts at the first change
+    let tenant_id = format!("changes-{}", Uuid::new_v4().simple());
+    let first = movie_store.create(&tenant_id, alien()).await.unwrap();
+    movie_store
+        .update(&tenant_id, first.id, rename("Aliens"), None)
+        .await
+        .unwrap();
+    let second = movie_store.create(&tenant_id, alien()).await.unwrap();
+    movie_store
+        .delete(&tenant_id, first.id, None)
+        .await
+        .unwrap();
+    movie_store
+        .purge(&tenant_id, second.id, None)
+        .await
+        .unwrap();
+    movie_store.create(OTHER_TENANT, alien()).await.unwrap();
+
+    let page = movie_store.changes(&tenant_id, None, 10).await.unwrap();
+    assert!(!page.has_more);
+    let changes = page.changes;
+    assert_eq!(
+        changes
+            .iter()
+            .map(|change| (change.sequence, change.kind, change.movie_id))
+            .collect::<Vec<_>>(),
+        [
+            (1, MovieEventKind::Created, first.id),
+            (2, MovieEventKind::Updated, first.id),
+            (3, MovieEventKind::Created, second.id),
+            (4, MovieEventKind::Deleted, first.id),
+            (5, MovieEventKind::Deleted, second.id),
+        ]
+    );
+    assert_eq!(changes[1].movie.as_ref().unwrap().title, "Aliens");
+    assert!(changes[3].movie.is_none() && changes[4].movie.is_none());
+
+    let mut since = None;
+    let mut paged = Vec::new();
+    loop {
+        let page = movie_store.changes(&tenant_id, since, 2).await.unwrap();
+        paged.extend(page.changes.iter().map(|change| change.sequence));
+        since = page.changes.last().map(|change| change.cursor()).or(since);
+        if !page.has_more {
+            break;
+        }
+    }
+    assert_eq!(paged, [1, 2, 3, 4, 5]);
+    let page = movie_store.changes(&tenant_id, since, 2).await.unwrap();
+    assert!(page.changes.is_empty() && !page.has_more);
+
+    for cursor in [
+        ChangeCursor {
+            changed_at: release_date(),
+            ..changes[2].cursor()
+        },
+        ChangeCursor {
+            sequence: 6,
+            ..changes[4].cursor()
+        },
+    ] {
+        let result = movie_store.changes(&tenant_id, Some(cursor), 10).await;
+        assert!(matches!(result, Err(StoreError::CursorExpired)));
+    }
+}
+
+#[tokio::test]
+async fn memory_store_conforms() {
+    run_conformance_suite(memory_store).await;
+}
+
+#[tokio::test]
+async fn sqlite_store_conforms() {
+    run_conformance_suite(sqlite_store).await;
+}
+
+#[tokio::test]
+async fn cached_store_conforms() {
+    run_conformance_suite(|| async {
+        let store = CachedStore::new(memory_store().await, 10, Duration::from_secs(60)).await;
+        Arc::new(store) as DynStore
+    })
+    .await;
+}
+
+#[tokio::test]
+async fn sql_store_conforms() {
+    if sql_store().await.is_none() {
+        return;
+    }
+    run_conformance_suite(|| async { sql_store().await.unwrap() }).await;
+}
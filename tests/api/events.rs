@@ -0,0 +1,271 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::body::{Body, BoxBody};
+use axum::http::{Request, StatusCode};
+use hyper::body::HttpBody;
+use movie_api::store::memory_store::MemoryStore;
+use movie_api::store::store::DynStore;
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use crate::helpers::{memory_store, sqlite_store, TestApp};
+
+fn movie(title: &str, director: &str) -> Value {
+    json!({
+        "title": title,
+        "director": director,
+        "release_date": "1958-05-09T00:00:00",
+        "ticket_price": 6.5,
+    })
+}
+
+async fn app_with_heartbeats(store: DynStore, buffer_size: usize) -> TestApp {
+    TestApp::with_configuration(store, |configuration| {
+        configuration.events.buffer_size = buffer_size;
+        configuration.events.heartbeat_interval_seconds = 1;
+    })
+    .await
+}
+
+/// A server-sent event, or a comment when it has neither a name nor data.
+#[derive(Debug)]
+struct Message {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    comment: Option<String>,
+}
+
+struct EventStream {
+    body: BoxBody,
+    buffer: String,
+}
+
+impl EventStream {
+    async fn open(app: &TestApp, uri: &str, headers: &[(&str, &str)]) -> (StatusCode, Self) {
+        let mut builder = Request::builder().uri(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        let request = builder.body(Body::empty()).unwrap();
+        let response = app.router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let stream = EventStream {
+            body: response.into_body(),
+            buffer: String::new(),
+        };
+        (status, stream)
+    }
+
+    async fn next(&mut self) -> Message {
+        loop {
+            if let Some(end) = self.buffer.find("\n\n") {
+                let block: String = self.buffer.drain(..end + 2).collect();
+                return parse_message(&block);
+            }
+
+            let chunk = tokio::time::timeout(Duration::from_secs(5), self.body.data())
+                .await
+                .expect("no event within 5 seconds")
+                .expect("event stream ended")
+                .unwrap();
+            self.buffer.push_str(std::str::from_utf8(&chunk).unwrap());
+        }
+    }
+
+    /// Reads the next event, skipping heartbeats.
+    async fn next_event(&mut self) -> Message {
+        loop {
+            let message = self.next().await;
+            if message.comment.is_none() {
+                return message;
+            }
+        }
+    }
+}
+
+fn parse_message(block: &str) -> Message {
+    let mut message = Message {
+        id: None,
+        event: None,
+        data: String::new(),
+        comment: None,
+    };
+    for line in block.lines().filter(|line| !line.is_empty()) {
+        let (field, value) = line.split_once(':').unwrap_or((line, ""));
+        let value = value.strip_prefix(' ').unwrap_or(value).to_string();
+        match field {
+            "" => message.comment = Some(value),
+            "id" => message.id = Some(value),
+            "event" => message.event = Some(value),
+            "data" => message.data.push_str(&value),
+            _ => {}
+        }
+    }
+    message
+}
+
+async fn assert_events_are_pushed_as_they_happen(store: DynStore) {
+    let app = app_with_heartbeats(store, 100).await;
+    let (status, mut all) = EventStream::open(&app, "/movies/events", &[]).await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, mut hitchcock) =
+        EventStream::open(&app, "/movies/events?director=alfred%20hitchcock", &[]).await;
+    let (_, mut other_tenant) =
+        EventStream::open(&app, "/movies/events", &[("X-Tenant-Id", "other")]).await;
+
+    let (_, vertigo) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(movie("Vertigo", "Alfred Hitchcock")),
+        )
+        .await;
+    let uri = format!("/movies/{}", vertigo["id"].as_str().unwrap());
+    app.request(
+        "POST",
+        "/movies",
+        Some(movie("Spartacus", "Stanley Kubrick")),
+    )
+    .await;
+    app.request("PUT", &uri, Some(json!({ "ticket_price": 7.0 })))
+        .await;
+    app.request("DELETE", &uri, None).await;
+
+    let mut ids = Vec::new();
+    for (kind, title) in [
+        ("created", "Vertigo"),
+        ("created", "Spartacus"),
+        ("updated", "Vertigo"),
+        ("deleted", "Vertigo"),
+    ] {
+        let message = all.next_event().await;
+        assert_eq!(message.event.as_deref(), Some(kind));
+        let data: Value = serde_json::from_str(&message.data).unwrap();
+        assert_eq!(data["title"], title);
+        ids.push(message.id.unwrap().parse::<i64>().unwrap());
+    }
+    assert!(ids.windows(2).all(|pair| pair[0] < pair[1]));
+
+    let (_, mut movie_only) = EventStream::open(
+        &app,
+        &format!(
+            "/movies/events?movie_id={}",
+            vertigo["id"].as_str().unwrap()
+        ),
+        &[("Last-Event-ID", &ids[0].to_string())],
+    )
+    .await;
+    for kind in ["created", "updated", "deleted"] {
+        let message = hitchcock.next_event().await;
+        assert_eq!(message.event.as_deref(), Some(kind));
+    }
+    // resumed after the first event, only the changes to the movie that came after it
+    for kind in ["updated", "deleted"] {
+        let message = movie_only.next_event().await;
+        assert_eq!(message.event.as_deref(), Some(kind));
+    }
+
+    // nothing for another tenant, only heartbeats
+    let message = other_tenant.next().await;
+    assert_eq!(message.comment.as_deref(), Some("heartbeat"));
+    let message = hitchcock.next().await;
+    assert_eq!(message.comment.as_deref(), Some("heartbeat"));
+}
+
+#[tokio::test]
+async fn events_are_pushed_as_they_happen_for_memory_store() {
+    assert_events_are_pushed_as_they_happen(memory_store().await).await;
+}
+
+#[tokio::test]
+async fn events_are_pushed_as_they_happen_for_sqlite_store() {
+    assert_events_are_pushed_as_they_happen(sqlite_store().await).await;
+}
+
+#[tokio::test]
+async fn resuming_past_the_buffer_is_reported_as_lagged() {
+    let app = app_with_heartbeats(memory_store().await, 2).await;
+    for title in ["Rope", "Psycho", "The Birds"] {
+        app.request("POST", "/movies", Some(movie(title, "Alfred Hitchcock")))
+            .await;
+    }
+
+    // the buffer holds the last two events, 2 and 3
+    let (_, mut stream) =
+        EventStream::open(&app, "/movies/events", &[("Last-Event-ID", "1")]).await;
+    assert_eq!(stream.next_event().await.id.as_deref(), Some("2"));
+    assert_eq!(stream.next_event().await.id.as_deref(), Some("3"));
+
+    let (_, mut stream) =
+        EventStream::open(&app, "/movies/events", &[("Last-Event-ID", "0")]).await;
+    let message = stream.next_event().await;
+    assert_eq!(message.event.as_deref(), Some("lagged"));
+    assert!(message.id.is_none());
+    assert_eq!(stream.next_event().await.id.as_deref(), Some("2"));
+
+    let (status, _) =
+        EventStream::open(&app, "/movies/events", &[("Last-Event-ID", "not-a-number")]).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn events_are_numbered_by_the_stream_without_an_outbox() {
+    let store = Arc::new(MemoryStore::new().with_outbox(false)) as DynStore;
+    let app = TestApp::with_configuration(store, |configuration| {
+        configuration.outbox.enabled = false;
+        configuration.events.heartbeat_interval_seconds = 0;
+    })
+    .await;
+    let (_, mut stream) = EventStream::open(&app, "/movies/events", &[]).await;
+
+    let (_, rope) = app
+        .request("POST", "/movies", Some(movie("Rope", "Alfred Hitchcock")))
+        .await;
+    let uri = format!("/movies/{}", rope["id"].as_str().unwrap());
+    // changes nothing, so nothing is published
+    app.request("PUT", &uri, Some(json!({ "title": "Rope" })))
+        .await;
+    app.request("PUT", &uri, Some(json!({ "title": "Rope (1948)" })))
+        .await;
+    // hands back the version that was just published
+    app.request("PUT", &uri, Some(json!({ "title": "Rope (1948)" })))
+        .await;
+    // keeps the version but is still news
+    app.request("DELETE", &format!("{}/purge", uri), None).await;
+
+    for (id, kind) in [("1", "created"), ("2", "updated"), ("3", "deleted")] {
+        let message = stream.next_event().await;
+        assert_eq!(message.id.as_deref(), Some(id));
+        assert_eq!(message.event.as_deref(), Some(kind));
+    }
+    // a heartbeat interval of 0 is taken as a second
+    let message = stream.next().await;
+    assert_eq!(message.comment.as_deref(), Some("heartbeat"));
+}
+
+#[tokio::test]
+async fn imported_movies_are_pushed() {
+    let app = app_with_heartbeats(memory_store().await, 100).await;
+    let (_, mut stream) = EventStream::open(&app, "/movies/events", &[]).await;
+
+    let (status, _, body) = app
+        .raw_request(
+            "POST",
+            "/movies/import/csv",
+            &[("Content-Type", "text/csv")],
+            Some(
+                "title,director,release_date,ticket_price\n\
+                 Rope,Alfred Hitchcock,1948-08-26T00:00:00,6.5\n"
+                    .to_string(),
+            ),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let message = stream.next_event().await;
+    assert_eq!(message.event.as_deref(), Some("created"));
+    let data: Value = serde_json::from_str(&message.data).unwrap();
+    assert_eq!(data["title"], "Rope");
+}
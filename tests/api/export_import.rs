@@ -0,0 +1,496 @@
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::helpers::TestApp;
+
+fn movie(title: &str, director: &str) -> Value {
+    json!({
+        "title": title,
+        "director": director,
+        "release_date": "1982-06-25T00:00:00",
+        "ticket_price": 11.5,
+    })
+}
+
+async fn export(app: &TestApp, query: &str) -> Vec<Value> {
+    let (status, headers, body) = app
+        .raw_request("GET", &format!("/movies/export{}", query), &[], None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "application/x-ndjson");
+    assert!(body.is_empty() || body.ends_with('\n'));
+    body.lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+async fn import(app: &TestApp, query: &str, lines: &[String]) -> Value {
+    let (status, _, body) = app
+        .raw_request(
+            "POST",
+            &format!("/movies/import{}", query),
+            &[("Content-Type", "application/x-ndjson")],
+            Some(lines.join("\n")),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    serde_json::from_str(&body).unwrap()
+}
+
+async fn assert_export_streams_every_movie(app: TestApp) {
+    // more movies than a streaming read holds at a time
+    let operations: Vec<Value> = (0..70)
+        .map(|i| json!({ "op": "create", "movie": movie(&format!("Blade Runner {}", i), "Scott") }))
+        .collect();
+    let (status, batch) = app
+        .request(
+            "POST",
+            "/movies/batch",
+            Some(json!({ "operations": operations })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let ids: Vec<Value> = batch["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["movie"]["id"].clone())
+        .collect();
+    let uri = format!("/movies/{}", ids[0].as_str().unwrap());
+    let (status, _) = app.request("DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let movies = export(&app, "").await;
+    assert_eq!(movies.len(), 69);
+    let exported: Vec<Value> = movies.iter().map(|movie| movie["id"].clone()).collect();
+    assert_eq!(exported, ids[1..]);
+    assert_eq!(movies[0]["title"], "Blade Runner 1");
+
+    let movies = export(&app, "?include_deleted=true").await;
+    assert_eq!(movies.len(), 70);
+    assert_eq!(movies[0]["id"], ids[0]);
+    assert!(movies[0]["deleted_at"].is_string());
+
+    // other tenants export nothing of it
+    let (_, _, body) = app
+        .raw_request(
+            "GET",
+            "/movies/export",
+            &[("X-Tenant-Id", &format!("t{}", Uuid::new_v4().simple()))],
+            None,
+        )
+        .await;
+    assert_eq!(body, "");
+}
+
+#[tokio::test]
+async fn export_streams_every_movie_for_memory_store() {
+    assert_export_streams_every_movie(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn export_streams_every_movie_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_export_streams_every_movie(app).await;
+    }
+}
+
+#[tokio::test]
+async fn export_streams_every_movie_for_sqlite_store() {
+    assert_export_streams_every_movie(TestApp::sqlite().await).await;
+}
+
+async fn assert_import_reports_every_line(app: TestApp) {
+    let (_, first) = app
+        .request("POST", "/movies", Some(movie("E.T.", "Spielberg")))
+        .await;
+    let (_, second) = app
+        .request("POST", "/movies", Some(movie("The Thing", "Carpenter")))
+        .await;
+    let exported: Vec<String> = export(&app, "")
+        .await
+        .iter()
+        .map(Value::to_string)
+        .collect();
+
+    // existing movies are skipped unless asked to upsert them
+    let summary = import(&app, "", &exported).await;
+    assert_eq!(
+        summary,
+        json!({ "created": 0, "updated": 0, "skipped": 2, "failed": [] })
+    );
+
+    // purged movies come back with their ids
+    let uri = format!("/movies/{}", first["id"].as_str().unwrap());
+    let (status, _) = app.request("DELETE", &format!("{}/purge", uri), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let summary = import(&app, "?mode=skip_existing", &exported).await;
+    assert_eq!(
+        summary,
+        json!({ "created": 1, "updated": 0, "skipped": 1, "failed": [] })
+    );
+    let (status, restored) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(restored["title"], "E.T.");
+
+    let mut renamed = second.clone();
+    renamed["title"] = json!("They Live");
+    let mut invalid = movie("Halloween", "Carpenter");
+    invalid["release_date"] = json!("not a date");
+    let lines = [
+        renamed.to_string(),
+        "{ not json".to_string(),
+        String::new(),
+        movie("Starman", "Carpenter").to_string(),
+        invalid.to_string(),
+    ];
+    let summary = import(&app, "?mode=upsert", &lines).await;
+    assert_eq!(summary["created"], 1);
+    assert_eq!(summary["updated"], 1);
+    assert_eq!(summary["skipped"], 0);
+    let failed = summary["failed"].as_array().unwrap();
+    assert_eq!(failed.len(), 2);
+    assert_eq!(failed[0]["line"], 2);
+    assert!(failed[0]["error_message"]
+        .as_str()
+        .unwrap()
+        .starts_with("invalid movie"));
+    assert_eq!(failed[1]["line"], 5);
+    assert_eq!(failed[1]["error_message"], "Invalid release date");
+    let uri = format!("/movies/{}", second["id"].as_str().unwrap());
+    let (_, updated) = app.request("GET", &uri, None).await;
+    assert_eq!(updated["title"], "They Live");
+
+    // ids are taken in every tenant
+    let (status, _, body) = app
+        .raw_request(
+            "POST",
+            "/movies/import",
+            &[("X-Tenant-Id", &format!("t{}", Uuid::new_v4().simple()))],
+            Some(exported[1].clone()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let summary: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(summary["created"], 0);
+    assert_eq!(summary["failed"][0]["line"], 1);
+}
+
+#[tokio::test]
+async fn import_reports_every_line_for_memory_store() {
+    assert_import_reports_every_line(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn import_reports_every_line_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_import_reports_every_line(app).await;
+    }
+}
+
+#[tokio::test]
+async fn import_reports_every_line_for_sqlite_store() {
+    assert_import_reports_every_line(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn import_writes_large_files_in_chunks() {
+    let app = TestApp::memory().await;
+    let mut lines: Vec<String> = (0..250)
+        .map(|i| movie(&format!("Take {}", i), "Kubrick").to_string())
+        .collect();
+    lines[100] = format!("{{\"title\": \"{}\"}}", "x".repeat(70 * 1024));
+
+    let summary = import(&app, "", &lines).await;
+    assert_eq!(summary["created"], 249);
+    assert_eq!(
+        summary["failed"],
+        json!([{ "line": 101, "error_message": "line is too long" }])
+    );
+    assert_eq!(export(&app, "").await.len(), 249);
+}
+
+#[tokio::test]
+async fn import_with_unknown_mode_returns_400() {
+    let app = TestApp::memory().await;
+
+    let (status, _, _) = app
+        .raw_request(
+            "POST",
+            "/movies/import?mode=replace",
+            &[],
+            Some(String::new()),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+async fn import_csv(app: &TestApp, query: &str, body: &str) -> (StatusCode, Value) {
+    let (status, _, body) = app
+        .raw_request(
+            "POST",
+            &format!("/movies/import/csv{}", query),
+            &[("Content-Type", "text/csv")],
+            Some(body.to_string()),
+        )
+        .await;
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+async fn assert_csv_export_can_be_imported(app: TestApp) {
+    let (_, first) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(movie("Crouching Tiger, \"Hidden\" Dragon", "Lee")),
+        )
+        .await;
+    app.request("POST", "/movies", Some(movie("Hulk", "Lee")))
+        .await;
+
+    let (status, headers, csv) = app.raw_request("GET", "/movies.csv", &[], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["content-type"], "text/csv");
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+        lines[0],
+        "id,title,director,release_date,ticket_price,created_at,updated_at,deleted_at"
+    );
+    assert!(lines[1].starts_with(&format!(
+        "{},\"Crouching Tiger, \"\"Hidden\"\" Dragon\",Lee,1982-06-25 00:00:00,11.50,",
+        first["id"].as_str().unwrap()
+    )));
+    let (_, _, accepted) = app
+        .raw_request("GET", "/movies", &[("Accept", "text/csv")], None)
+        .await;
+    assert_eq!(accepted, csv);
+
+    let uri = format!("/movies/{}", first["id"].as_str().unwrap());
+    app.request("DELETE", &format!("{}/purge", uri), None).await;
+    let (status, summary) = import_csv(&app, "", &csv).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        summary,
+        json!({ "created": 1, "updated": 0, "skipped": 1, "failed": [] })
+    );
+    let (_, restored) = app.request("GET", &uri, None).await;
+    assert_eq!(restored["title"], "Crouching Tiger, \"Hidden\" Dragon");
+}
+
+#[tokio::test]
+async fn csv_export_can_be_imported_for_memory_store() {
+    assert_csv_export_can_be_imported(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn csv_export_can_be_imported_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_csv_export_can_be_imported(app).await;
+    }
+}
+
+#[tokio::test]
+async fn csv_export_can_be_imported_for_sqlite_store() {
+    assert_csv_export_can_be_imported(TestApp::sqlite().await).await;
+}
+
+const SPREADSHEET: &str = "\
+Film,Regisseur,Released,Price,Notes
+Vertigo,Hitchcock,1958-05-09T00:00:00,9.5,classic
+Psycho,Hitchcock,1960-06-16T00:00:00,nine,
+The Birds,Hitchcock,sometime in 1963,9.5,
+Rope,Hitchcock,1948-08-26 00:00:00,7,
+";
+
+const SPREADSHEET_COLUMNS: &str = "?title_column=Film&director_column=Regisseur\
+    &release_date_column=Released&ticket_price_column=Price";
+
+#[tokio::test]
+async fn csv_import_maps_columns_and_reports_invalid_rows() {
+    let app = TestApp::memory().await;
+
+    let (status, summary) = import_csv(&app, SPREADSHEET_COLUMNS, SPREADSHEET).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["created"], 2);
+    assert_eq!(
+        summary["failed"],
+        json!([
+            { "line": 3, "error_message": "Invalid ticket price" },
+            { "line": 4, "error_message": "Invalid release date" },
+        ])
+    );
+    let (_, page) = app.request("GET", "/movies?director=Hitchcock", None).await;
+    let titles: Vec<&str> = page["movies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|movie| movie["title"].as_str().unwrap())
+        .collect();
+    assert_eq!(titles, ["Vertigo", "Rope"]);
+}
+
+#[tokio::test]
+async fn strict_csv_import_rejects_the_file_with_an_invalid_row() {
+    let app = TestApp::memory().await;
+
+    let query = format!("{}&strict=true", SPREADSHEET_COLUMNS);
+    let (status, summary) = import_csv(&app, &query, SPREADSHEET).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(summary["created"], 0);
+    assert_eq!(summary["failed"].as_array().unwrap().len(), 2);
+    let (_, page) = app.request("GET", "/movies", None).await;
+    assert_eq!(page["movies"], json!([]));
+}
+
+async fn assert_strict_csv_import_writes_nothing_when_a_row_fails(app: TestApp) {
+    let mut csv = String::from("title,director,release_date,ticket_price\n");
+    for i in 0..150 {
+        csv.push_str(&format!("Take {},Kubrick,1968-04-02T00:00:00,9.5\n", i));
+    }
+    // the same movie as the first row, past the first chunk of movies written
+    csv.push_str("Take 0,Kubrick,1968-04-02T00:00:00,9.5\n");
+
+    let (status, summary) = import_csv(&app, "?strict=true", &csv).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(summary["created"], 0);
+    assert_eq!(
+        summary["failed"],
+        json!([{ "line": 152, "error_message": "movie already exists" }])
+    );
+    let (_, page) = app.request("GET", "/movies", None).await;
+    assert_eq!(page["movies"], json!([]));
+
+    let (status, summary) = import_csv(&app, "?strict=true", SPREADSHEET_ROWS).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(summary["created"], 1);
+}
+
+const SPREADSHEET_ROWS: &str = "\
+title,director,release_date,ticket_price
+Vertigo,Hitchcock,1958-05-09T00:00:00,9.5
+";
+
+#[tokio::test]
+async fn strict_csv_import_writes_nothing_when_a_row_fails_for_memory_store() {
+    assert_strict_csv_import_writes_nothing_when_a_row_fails(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn strict_csv_import_writes_nothing_when_a_row_fails_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_strict_csv_import_writes_nothing_when_a_row_fails(app).await;
+    }
+}
+
+#[tokio::test]
+async fn strict_csv_import_writes_nothing_when_a_row_fails_for_sqlite_store() {
+    assert_strict_csv_import_writes_nothing_when_a_row_fails(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn strict_csv_import_refuses_too_many_rows() {
+    let app = TestApp::memory().await;
+    let mut csv = String::from("title,director,release_date,ticket_price\n");
+    for i in 0..10_001 {
+        csv.push_str(&format!("Take {},Kubrick,1968-04-02T00:00:00,9.5\n", i));
+    }
+
+    let (status, body) = import_csv(&app, "?strict=true", &csv).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+    assert_eq!(body["error_message"], "payload too large");
+    let (_, page) = app.request("GET", "/movies", None).await;
+    assert_eq!(page["movies"], json!([]));
+}
+
+#[tokio::test]
+async fn csv_import_without_a_column_returns_400() {
+    let app = TestApp::memory().await;
+
+    let (status, body) = import_csv(&app, "", SPREADSHEET).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error_message"], "validation error");
+}
+
+#[tokio::test]
+async fn csv_import_streams_files_past_the_body_limit() {
+    let app = TestApp::memory().await;
+    let notes = "n".repeat(100);
+    let mut csv = String::from("title,director,release_date,ticket_price,notes\n");
+    for i in 0..20_000 {
+        // a newline between quotes belongs to the title, wherever the chunks are cut
+        let title = if i % 1000 == 0 {
+            format!("\"Take {}\nreprise\"", i)
+        } else {
+            format!("Take {}", i)
+        };
+        csv.push_str(&format!(
+            "{},Kubrick,1968-04-02T00:00:00,9.5,{}\n",
+            title, notes
+        ));
+    }
+    let broken_line = csv.matches('\n').count() + 1;
+    csv.push_str("Broken,Kubrick,1968-04-02T00:00:00,free,\n");
+    assert!(csv.len() > 2 * 1024 * 1024);
+    let chunks = csv
+        .as_bytes()
+        .chunks(64 * 1024 + 7)
+        .map(|chunk| String::from_utf8(chunk.to_vec()).unwrap())
+        .collect();
+
+    let (status, _, summary) = app
+        .chunked_request(
+            "POST",
+            "/movies/import/csv",
+            &[("Content-Type", "text/csv")],
+            chunks,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let summary: Value = serde_json::from_str(&summary).unwrap();
+    assert_eq!(summary["created"], 20_000);
+    assert_eq!(
+        summary["failed"],
+        json!([{ "line": broken_line, "error_message": "Invalid ticket price" }])
+    );
+    let (_, page) = app
+        .request("GET", "/movies?title_contains=reprise&limit=100", None)
+        .await;
+    assert_eq!(page["movies"].as_array().unwrap().len(), 20);
+    assert_eq!(page["movies"][0]["title"], "Take 0\nreprise");
+}
+
+#[tokio::test]
+async fn csv_list_applies_the_filters_and_rejects_paging() {
+    let app = TestApp::memory().await;
+    app.request("POST", "/movies", Some(movie("Hulk", "Lee")))
+        .await;
+    app.request("POST", "/movies", Some(movie("Alien", "Scott")))
+        .await;
+
+    let (status, _, csv) = app
+        .raw_request(
+            "GET",
+            "/movies?director=lee",
+            &[("Accept", "text/csv")],
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(",Hulk,Lee,"));
+
+    for query in ["limit=1", "cursor=abc", "sort=title"] {
+        let (status, _, _) = app
+            .raw_request(
+                "GET",
+                &format!("/movies?{}", query),
+                &[("Accept", "text/csv")],
+                None,
+            )
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -0,0 +1,287 @@
+use std::time::Duration;
+
+use axum::http::StatusCode;
+use chrono::{SubsecRound, Utc};
+use movie_api::startup::get_connection_pool;
+use movie_api::store::store::DynStore;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::helpers::{database_configuration, memory_store, sql_store, sqlite_store, TestApp};
+
+fn movie(title: &str, director: &str, ticket_price: f64) -> Value {
+    json!({
+        "title": title,
+        "director": director,
+        "release_date": "1993-02-12T00:00:00",
+        "ticket_price": ticket_price,
+    })
+}
+
+async fn count_movies(app: &TestApp, director: &str) -> usize {
+    let (_, page) = app
+        .request("GET", &format!("/movies?director={}", director), None)
+        .await;
+    page["movies"].as_array().unwrap().len()
+}
+
+async fn assert_retries_replay_the_first_response(app: TestApp) {
+    let word = format!("w{}", Uuid::new_v4().simple());
+    let key = Uuid::new_v4().to_string();
+    let headers = [("Idempotency-Key", key.as_str())];
+
+    let (status, first_headers, first) = app
+        .request_with_headers(
+            "POST",
+            "/movies",
+            &headers,
+            Some(movie("Groundhog Day", &word, 7.0)),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(first_headers.get("idempotent-replayed").is_none());
+    let (status, retry_headers, retry) = app
+        .request_with_headers(
+            "POST",
+            "/movies",
+            &headers,
+            Some(movie("Groundhog Day", &word, 7.0)),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(retry_headers["idempotent-replayed"], "true");
+    assert_eq!(retry, first);
+    assert_eq!(count_movies(&app, &word).await, 1);
+
+    let (status, _, body) = app
+        .request_with_headers(
+            "POST",
+            "/movies",
+            &headers,
+            Some(movie("Groundhog Day", &word, 8.0)),
+        )
+        .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+        body["error_message"],
+        "idempotency key was used for a different request"
+    );
+
+    // keys belong to a tenant
+    let (status, _, other) = app
+        .request_with_headers(
+            "POST",
+            "/movies",
+            &[("Idempotency-Key", key.as_str()), ("X-Tenant-Id", "other")],
+            Some(movie("Groundhog Day", &word, 8.0)),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(other["id"], first["id"]);
+
+    // client errors are replayed as well
+    let key = Uuid::new_v4().to_string();
+    let headers = [("Idempotency-Key", key.as_str())];
+    let mut invalid = movie("Groundhog Day", &word, 7.0);
+    invalid["release_date"] = json!("not a date");
+    for _ in 0..2 {
+        let (status, _, body) = app
+            .request_with_headers("POST", "/movies", &headers, Some(invalid.clone()))
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error_message"], "validation error");
+    }
+    assert_eq!(count_movies(&app, &word).await, 1);
+}
+
+#[tokio::test]
+async fn retries_replay_the_first_response_for_memory_store() {
+    assert_retries_replay_the_first_response(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn retries_replay_the_first_response_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_retries_replay_the_first_response(app).await;
+    }
+}
+
+#[tokio::test]
+async fn retries_replay_the_first_response_for_sqlite_store() {
+    assert_retries_replay_the_first_response(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn expired_keys_can_be_used_again() {
+    let app = TestApp::with_configuration(memory_store().await, |configuration| {
+        configuration.idempotency.ttl_seconds = 0;
+    })
+    .await;
+    let headers = [("Idempotency-Key", "expires-right-away")];
+
+    let (_, _, first) = app
+        .request_with_headers(
+            "POST",
+            "/movies",
+            &headers,
+            Some(movie("Stripes", "Ramis", 7.0)),
+        )
+        .await;
+    let (status, retry_headers, retry) = app
+        .request_with_headers(
+            "POST",
+            "/movies",
+            &headers,
+            Some(movie("Caddyshack", "Ramis", 8.0)),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(retry_headers.get("idempotent-replayed").is_none());
+    assert_ne!(retry["id"], first["id"]);
+}
+
+#[tokio::test]
+async fn retries_without_a_key_are_rejected_as_duplicates() {
+    let app = TestApp::memory().await;
+
+    let (status, first) = app
+        .request("POST", "/movies", Some(movie("Stripes", "Ramis", 7.0)))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, retry) = app
+        .request("POST", "/movies", Some(movie("Stripes", "Ramis", 7.0)))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(retry["existing_id"], first["id"]);
+    assert_eq!(count_movies(&app, "Ramis").await, 1);
+}
+
+async fn assert_abandoned_keys_are_taken_over_once_the_lock_lapses(store: DynStore) {
+    let idempotency_store = store.idempotency_store().await;
+    let tenant_id = format!("t{}", Uuid::new_v4().simple());
+    let key = Uuid::new_v4().to_string();
+    let now = Utc::now().naive_utc().trunc_subsecs(6);
+    let expires_at = now + chrono::Duration::days(1);
+    let lapsed = now - chrono::Duration::seconds(1);
+    let locked_until = now + chrono::Duration::minutes(1);
+
+    // the first request never finished and its lock lapsed right away
+    let claimed = idempotency_store
+        .claim(&tenant_id, &key, "first", lapsed, expires_at)
+        .await
+        .unwrap();
+    assert!(claimed.is_none());
+    let held = idempotency_store
+        .claim(&tenant_id, &key, "second", locked_until, expires_at)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(held.request_hash, "first");
+
+    let taken_over = idempotency_store
+        .claim(&tenant_id, &key, "first", locked_until, expires_at)
+        .await
+        .unwrap();
+    assert!(taken_over.is_none());
+    let held = idempotency_store
+        .claim(&tenant_id, &key, "first", locked_until, expires_at)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(held.response.is_none());
+
+    // the request that lost the key can't release it
+    idempotency_store
+        .release(&tenant_id, &key, lapsed)
+        .await
+        .unwrap();
+    assert!(idempotency_store
+        .claim(&tenant_id, &key, "first", locked_until, expires_at)
+        .await
+        .unwrap()
+        .is_some());
+    idempotency_store
+        .release(&tenant_id, &key, locked_until)
+        .await
+        .unwrap();
+    assert!(idempotency_store
+        .claim(&tenant_id, &key, "third", locked_until, expires_at)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn abandoned_keys_are_taken_over_once_the_lock_lapses_for_memory_store() {
+    assert_abandoned_keys_are_taken_over_once_the_lock_lapses(memory_store().await).await;
+}
+
+#[tokio::test]
+async fn abandoned_keys_are_taken_over_once_the_lock_lapses_for_sql_store() {
+    if let Some(store) = sql_store().await {
+        assert_abandoned_keys_are_taken_over_once_the_lock_lapses(store).await;
+    }
+}
+
+#[tokio::test]
+async fn abandoned_keys_are_taken_over_once_the_lock_lapses_for_sqlite_store() {
+    assert_abandoned_keys_are_taken_over_once_the_lock_lapses(sqlite_store().await).await;
+}
+
+#[tokio::test]
+async fn cancelled_requests_release_their_key() {
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        return;
+    };
+    let app = TestApp::new(sql_store().await.unwrap()).await;
+    let tenant_id = format!("t{}", Uuid::new_v4().simple());
+    let key = Uuid::new_v4().to_string();
+    let headers = [
+        ("Idempotency-Key", key.as_str()),
+        ("X-Tenant-Id", tenant_id.as_str()),
+    ];
+
+    // an uncommitted movie with the same title makes the insert wait until the request is
+    // cancelled
+    let pool = get_connection_pool(&database_configuration("sql", &database_url));
+    let mut transaction = pool.begin().await.unwrap();
+    sqlx::query(
+        r#"
+        INSERT INTO movies (
+            tenant_id, title, director, release_date, ticket_price, title_folded, director_folded
+        )
+        VALUES ($1, 'Tootsie', 'Pollack', '1993-02-12', 7.0, 'tootsie', 'pollack')
+        "#,
+    )
+    .bind(&tenant_id)
+    .execute(&mut transaction)
+    .await
+    .unwrap();
+    let request = app.request_with_headers(
+        "POST",
+        "/movies",
+        &headers,
+        Some(movie("Tootsie", "Pollack", 7.0)),
+    );
+    assert!(tokio::time::timeout(Duration::from_millis(500), request)
+        .await
+        .is_err());
+    transaction.rollback().await.unwrap();
+
+    let mut status = StatusCode::CONFLICT;
+    for _ in 0..20 {
+        (status, _, _) = app
+            .request_with_headers(
+                "POST",
+                "/movies",
+                &headers,
+                Some(movie("Tootsie", "Pollack", 7.0)),
+            )
+            .await;
+        if status != StatusCode::CONFLICT {
+            break;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    assert_eq!(status, StatusCode::OK);
+}
@@ -0,0 +1,14 @@
+mod cache;
+mod changes;
+mod conformance;
+mod events;
+mod export_import;
+mod helpers;
+mod idempotency;
+mod memory_persistence;
+mod migrations;
+mod movies;
+mod outbox;
+mod read_replica;
+mod stats;
+mod tenants;
@@ -0,0 +1,200 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use movie_api::store::memory_store::MemoryStore;
+use movie_api::store::store::DynStore;
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::helpers::TestApp;
+
+fn temporary_directory() -> PathBuf {
+    std::env::temp_dir().join(format!("movie-api-{}", Uuid::new_v4()))
+}
+
+async fn open(directory: &Path) -> (MemoryStore, TestApp) {
+    let store = MemoryStore::open(directory).expect("Failed to open memory store.");
+    let app = TestApp::new(Arc::new(store.clone()) as DynStore).await;
+    (store, app)
+}
+
+async fn create(app: &TestApp, title: &str) -> String {
+    let (status, movie) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(json!({
+                "title": title,
+                "director": "Ridley Scott",
+                "release_date": "1982-06-25T00:00:00",
+                "ticket_price": 9.99,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    movie["id"].as_str().unwrap().to_string()
+}
+
+async fn titles(app: &TestApp) -> Vec<String> {
+    let (status, page) = app.request("GET", "/movies", None).await;
+    assert_eq!(status, StatusCode::OK);
+    page["movies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|movie: &Value| movie["title"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn changes_survive_a_restart() {
+    let directory = temporary_directory();
+
+    let (_, app) = open(&directory).await;
+    let blade_runner = create(&app, "Blade Runner").await;
+    let alien = create(&app, "Alien").await;
+    create(&app, "Gladiator").await;
+    app.request(
+        "PUT",
+        &format!("/movies/{}", blade_runner),
+        Some(json!({ "title": "Blade Runner: The Final Cut" })),
+    )
+    .await;
+    app.request("DELETE", &format!("/movies/{}", alien), None)
+        .await;
+    drop(app);
+
+    let (_, app) = open(&directory).await;
+    assert_eq!(
+        titles(&app).await,
+        vec!["Blade Runner: The Final Cut", "Gladiator"]
+    );
+
+    let (status, results) = app.request("GET", "/movies/search?q=final", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(results.as_array().unwrap().len(), 1);
+
+    // the first reopen replayed the log, this one reads everything back from the snapshot
+    drop(app);
+    let (_, app) = open(&directory).await;
+    let (status, history) = app
+        .request("GET", &format!("/movies/{}/history", blade_runner), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(history.as_array().unwrap().len(), 2);
+    assert_eq!(history[1]["changed_fields"], json!(["title"]));
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn truncated_last_record_is_dropped() {
+    let directory = temporary_directory();
+
+    let (_, app) = open(&directory).await;
+    create(&app, "The Martian").await;
+    drop(app);
+
+    // simulate a crash halfway through appending a record
+    let mut log = OpenOptions::new()
+        .append(true)
+        .open(directory.join("movies.log"))
+        .unwrap();
+    log.write_all(br#"{"op":"put","movie":{"id":"#).unwrap();
+    drop(log);
+
+    let (_, app) = open(&directory).await;
+    assert_eq!(titles(&app).await, vec!["The Martian"]);
+    create(&app, "Prometheus").await;
+    drop(app);
+
+    let (_, app) = open(&directory).await;
+    assert_eq!(titles(&app).await, vec!["The Martian", "Prometheus"]);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn snapshot_compacts_the_log() {
+    let directory = temporary_directory();
+
+    let (store, app) = open(&directory).await;
+    create(&app, "Thelma & Louise").await;
+    assert!(fs::metadata(directory.join("movies.log")).unwrap().len() > 0);
+
+    store.snapshot().unwrap();
+    assert_eq!(fs::metadata(directory.join("movies.log")).unwrap().len(), 0);
+    drop(app);
+
+    let (_, app) = open(&directory).await;
+    assert_eq!(titles(&app).await, vec!["Thelma & Louise"]);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn log_left_behind_by_a_snapshot_is_not_replayed_twice() {
+    let directory = temporary_directory();
+
+    let (store, app) = open(&directory).await;
+    let id = create(&app, "Thelma & Louise").await;
+    let uri = format!("/movies/{}", id);
+    app.request("PUT", &uri, Some(json!({ "title": "Thelma and Louise" })))
+        .await;
+    // a crash after the snapshot is in place but before the log is truncated
+    let log = fs::read(directory.join("movies.log")).unwrap();
+    store.snapshot().unwrap();
+    fs::write(directory.join("movies.log"), log).unwrap();
+    drop(app);
+
+    let (_, app) = open(&directory).await;
+    let (_, movie) = app.request("GET", &uri, None).await;
+    assert_eq!(movie["title"], "Thelma and Louise");
+    let (_, history) = app.request("GET", &format!("{}/history", uri), None).await;
+    let revisions: Vec<&Value> = history
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|revision| &revision["revision"])
+        .collect();
+    assert_eq!(revisions, [&json!(1), &json!(2)]);
+
+    fs::remove_dir_all(&directory).unwrap();
+}
+
+#[tokio::test]
+async fn change_feed_starts_over_after_a_restart() {
+    let directory = temporary_directory();
+
+    let (_, app) = open(&directory).await;
+    let alien = create(&app, "Alien").await;
+    create(&app, "Legend").await;
+    app.request("DELETE", &format!("/movies/{}", alien), None)
+        .await;
+    let (_, feed) = app.request("GET", "/movies/changes", None).await;
+    assert_eq!(feed["changes"].as_array().unwrap().len(), 3);
+    drop(app);
+
+    let (_, app) = open(&directory).await;
+    let uri = format!(
+        "/movies/changes?since={}",
+        feed["next_since"].as_str().unwrap()
+    );
+    let (status, _) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::GONE);
+
+    // read from the start, the feed has every movie as it is now
+    let (status, feed) = app.request("GET", "/movies/changes", None).await;
+    assert_eq!(status, StatusCode::OK);
+    let changes = feed["changes"].as_array().unwrap();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0]["kind"], "deleted");
+    assert_eq!(changes[0]["movie_id"], alien.as_str());
+    assert_eq!(changes[1]["kind"], "created");
+    assert_eq!(changes[1]["movie"]["title"], "Legend");
+
+    fs::remove_dir_all(&directory).unwrap();
+}
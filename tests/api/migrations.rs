@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use movie_api::configuration::get_configuration;
+use movie_api::startup::{get_sqlite_connection_pool, Application};
+use movie_api::store::sql_store::SqlStore;
+use movie_api::store::sqlite_store::SqliteStore;
+use movie_api::store::store::{Store, StoreError};
+use sqlx::migrate::Migrator;
+
+use crate::helpers::database_configuration;
+
+/// The sqlite migration adding the unique index on the title, director and release date.
+const UNIQUE_MOVIES_MIGRATION: i64 = 20261018180100;
+
+#[tokio::test]
+async fn memory_store_ignores_run_migrations() {
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.database.store_type = "memory".to_string();
+    configuration.database.run_migrations = true;
+
+    assert!(Application::build(configuration).await.is_ok());
+}
+
+#[tokio::test]
+async fn sqlite_store_is_migrated_on_startup() {
+    let mut configuration = get_configuration().expect("Failed to read configuration.");
+    configuration.database.store_type = "sqlite".to_string();
+    configuration.database.database_url = "sqlite::memory:".to_string();
+    configuration.database.run_migrations = false;
+
+    assert!(Application::build(configuration).await.is_ok());
+}
+
+#[tokio::test]
+async fn sqlite_store_refuses_a_schema_ahead_of_the_binary() {
+    let pool = get_sqlite_connection_pool(&database_configuration("sqlite", "sqlite::memory:"))
+        .await
+        .unwrap();
+    let store = SqliteStore::new(pool.clone());
+    store.migrate().await.unwrap();
+    assert!(store.check_schema_version().await.is_ok());
+
+    sqlx::query(
+        r#"
+        INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+        VALUES (99991231235959, 'from the future', TRUE, x'00', 0)
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    assert!(matches!(
+        store.check_schema_version().await,
+        Err(StoreError::Migration(_))
+    ));
+}
+
+#[tokio::test]
+async fn sqlite_store_folds_movies_stored_before_they_were_kept_folded() {
+    let pool = get_sqlite_connection_pool(&database_configuration("sqlite", "sqlite::memory:"))
+        .await
+        .unwrap();
+    let store = SqliteStore::new(pool.clone());
+    store.migrate().await.unwrap();
+    sqlx::query(
+        r#"
+        INSERT INTO movies (
+            id, tenant_id, title, director, release_date, ticket_price_cents, created_at,
+            updated_at, version
+        )
+        VALUES (
+            '9b1deb4d-3b7d-4bad-9bdd-2b0d7b3dcb6d', 'default', 'Æon Flux', 'Karyn Kusama',
+            '2005-12-02 00:00:00.000000000', 950, '2005-12-02 00:00:00.000000000',
+            '2005-12-02 00:00:00.000000000', 1
+        )
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    store.migrate().await.unwrap();
+
+    let folded =
+        sqlx::query_as::<_, (String, String)>("SELECT title_folded, director_folded FROM movies")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+    assert_eq!(folded, ("æon flux".to_string(), "karyn kusama".to_string()));
+}
+
+#[tokio::test]
+async fn sqlite_store_lists_duplicate_movies_instead_of_deleting_them() {
+    let pool = get_sqlite_connection_pool(&database_configuration("sqlite", "sqlite::memory:"))
+        .await
+        .unwrap();
+    let mut migrator = Migrator::new(Path::new("db/sqlite/migrations"))
+        .await
+        .unwrap();
+    migrator.migrations = migrator
+        .migrations
+        .iter()
+        .filter(|migration| migration.version < UNIQUE_MOVIES_MIGRATION)
+        .cloned()
+        .collect();
+    migrator.run(&pool).await.unwrap();
+    for id in [
+        "2c5ea4c0-4067-11e9-8bad-9b1deb4d3b7d",
+        "3c5ea4c0-4067-11e9-8bad-9b1deb4d3b7d",
+    ] {
+        sqlx::query(
+            r#"
+            INSERT INTO movies (
+                id, tenant_id, title, director, release_date, ticket_price_cents, created_at,
+                updated_at, version
+            )
+            VALUES (
+                ?, 'default', 'Heat', 'Michael Mann', '1995-12-15 00:00:00.000000000', 950,
+                '1995-12-15 00:00:00.000000000', '1995-12-15 00:00:00.000000000', 1
+            )
+            "#,
+        )
+        .bind(id)
+        .execute(&pool)
+        .await
+        .unwrap();
+    }
+
+    let store = SqliteStore::new(pool.clone());
+    let Err(StoreError::Migration(message)) = store.migrate().await else {
+        panic!("migrated over duplicate movies");
+    };
+    assert!(message.contains(
+        "Heat by Michael Mann released 1995-12-15 00:00:00.000000000 in tenant default: \
+        2c5ea4c0-4067-11e9-8bad-9b1deb4d3b7d, 3c5ea4c0-4067-11e9-8bad-9b1deb4d3b7d"
+    ));
+    let live = sqlx::query_scalar::<_, i64>("SELECT count(*) FROM movies WHERE deleted_at IS NULL")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(live, 2);
+}
+
+#[tokio::test]
+async fn sql_store_schema_matches_the_binary_after_migrating() {
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        return;
+    };
+    let pool =
+        movie_api::startup::get_connection_pool(&database_configuration("sql", &database_url));
+    let store = SqlStore::new(pool);
+
+    store.migrate().await.unwrap();
+
+    assert!(store.check_schema_version().await.is_ok());
+}
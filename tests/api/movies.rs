@@ -0,0 +1,1021 @@
+use std::str::FromStr;
+use std::sync::Arc;
+
+use axum::http::StatusCode;
+use bigdecimal::BigDecimal;
+use chrono::NaiveDateTime;
+use futures::future::join_all;
+use movie_api::controllers::movies::CreateMovieRequest;
+use movie_api::startup::get_sqlite_connection_pool;
+use movie_api::store::sqlite_store::SqliteStore;
+use movie_api::store::store::{CreateMovieParams, DynStore, Store, DEFAULT_TENANT_ID};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::helpers::{database_configuration, memory_store, TestApp};
+
+#[test]
+fn create_params_reject_nan_ticket_price() {
+    let request = CreateMovieRequest {
+        title: "Star Wars: Episode IV – A New Hope".to_string(),
+        director: "George Lucas".to_string(),
+        release_date: "1977-05-25T00:00:00".to_string(),
+        ticket_price: f64::NAN,
+    };
+
+    assert!(CreateMovieParams::try_from(request).is_err());
+}
+
+#[tokio::test]
+async fn ticket_price_out_of_range_fails_the_response_without_panicking() {
+    let store = memory_store().await;
+    let movie = store
+        .movie_store()
+        .await
+        .create(
+            DEFAULT_TENANT_ID,
+            CreateMovieParams {
+                title: "Superman".to_string(),
+                director: "Richard Donner".to_string(),
+                release_date: NaiveDateTime::from_str("1978-12-15T00:00:00").unwrap(),
+                ticket_price: BigDecimal::from_str("1e400").unwrap(),
+            },
+        )
+        .await
+        .unwrap();
+    let app = TestApp::new(store).await;
+
+    let uri = format!("/movies/{}", movie.id);
+    let (status, _, body) = app.raw_request("GET", &uri, &[], None).await;
+    assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(body.contains("ticket price"), "{}", body);
+}
+
+#[tokio::test]
+async fn update_with_invalid_release_date_returns_400() {
+    let app = TestApp::memory().await;
+    let (_, movie) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(json!({
+                "title": "Jaws",
+                "director": "Steven Spielberg",
+                "release_date": "1975-06-20T00:00:00",
+                "ticket_price": 10.5,
+            })),
+        )
+        .await;
+
+    let uri = format!("/movies/{}", movie["id"].as_str().unwrap());
+    let (status, body) = app
+        .request("PUT", &uri, Some(json!({ "release_date": "not a date" })))
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["error_message"].is_string());
+}
+
+async fn assert_unknown_id_returns_404(app: TestApp) {
+    let uri = format!("/movies/{}", Uuid::new_v4());
+
+    let (status, body) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body["error_message"].is_string());
+
+    let (status, body) = app
+        .request("PUT", &uri, Some(json!({ "title": "Missing" })))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body["error_message"].is_string());
+
+    let (status, body) = app.request("DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(body["error_message"].is_string());
+}
+
+#[tokio::test]
+async fn unknown_id_returns_404_for_memory_store() {
+    assert_unknown_id_returns_404(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn unknown_id_returns_404_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_unknown_id_returns_404(app).await;
+    }
+}
+
+#[tokio::test]
+async fn unknown_id_returns_404_for_sqlite_store() {
+    assert_unknown_id_returns_404(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn unreachable_pool_returns_503() {
+    let app = TestApp::unreachable_sql().await;
+
+    let (status, body) = app.request("GET", "/movies", None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body["error_message"].is_string());
+
+    let uri = format!("/movies/{}", Uuid::new_v4());
+    let (status, body) = app
+        .request("PUT", &uri, Some(json!({ "title": "Unreachable" })))
+        .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body["error_message"].is_string());
+}
+
+async fn create_movie(app: &TestApp, title: &str) -> Value {
+    create_movie_with(app, title, "Christopher Nolan").await
+}
+
+async fn create_movie_with(app: &TestApp, title: &str, director: &str) -> Value {
+    let (status, movie) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(json!({
+                "title": title,
+                "director": director,
+                "release_date": "2001-01-01T00:00:00",
+                "ticket_price": 8.0,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    movie
+}
+
+async fn assert_list_pages_through_all_movies(app: TestApp) {
+    let mut created = Vec::new();
+    for i in 0..5 {
+        let movie = create_movie(&app, &format!("Inception {}", i)).await;
+        created.push(movie["id"].as_str().unwrap().to_string());
+    }
+
+    let mut listed = Vec::new();
+    let mut uri = "/movies?limit=2".to_string();
+    loop {
+        let (status, page) = app.request("GET", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let movies = page["movies"].as_array().unwrap();
+        assert!(movies.len() <= 2);
+        listed.extend(movies.iter().map(|m| m["id"].as_str().unwrap().to_string()));
+
+        match page["next_cursor"].as_str() {
+            None => break,
+            Some(cursor) => uri = format!("/movies?limit=2&cursor={}", cursor),
+        }
+    }
+
+    let listed_created: Vec<_> = listed.iter().filter(|id| created.contains(id)).collect();
+    assert_eq!(listed_created, created.iter().collect::<Vec<_>>());
+    let mut deduplicated = listed.clone();
+    deduplicated.sort();
+    deduplicated.dedup();
+    assert_eq!(deduplicated.len(), listed.len());
+}
+
+#[tokio::test]
+async fn list_pages_through_all_movies_for_memory_store() {
+    assert_list_pages_through_all_movies(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn list_pages_through_all_movies_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_list_pages_through_all_movies(app).await;
+    }
+}
+
+#[tokio::test]
+async fn list_pages_through_all_movies_for_sqlite_store() {
+    assert_list_pages_through_all_movies(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn list_with_invalid_cursor_returns_400() {
+    let app = TestApp::memory().await;
+
+    let (status, _) = app
+        .request("GET", "/movies?cursor=not-a-cursor", None)
+        .await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+async fn assert_list_filters_and_sorts(app: TestApp) {
+    // a unique director keeps the assertions independent of other rows in a shared database
+    let director = format!("Director {}", Uuid::new_v4());
+    let movies = [
+        ("Alien", "1979-05-25T00:00:00", 9.5),
+        ("Aliens", "1986-07-18T00:00:00", 11.0),
+        ("Alien 3", "1992-05-22T00:00:00", 14.0),
+        ("Prometheus", "2012-06-08T00:00:00", 12.0),
+    ];
+    for (title, release_date, ticket_price) in movies {
+        let (status, _) = app
+            .request(
+                "POST",
+                "/movies",
+                Some(json!({
+                    "title": title,
+                    "director": director,
+                    "release_date": release_date,
+                    "ticket_price": ticket_price,
+                })),
+            )
+            .await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let filters = format!(
+        "director={}&title_contains=ALIEN&release_date_from=1980-01-01T00:00:00&release_date_to=2020-01-01T00:00:00&ticket_price_max=13",
+        director.to_uppercase().replace(' ', "%20")
+    );
+    let (status, page) = app
+        .request("GET", &format!("/movies?{}", filters), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let titles: Vec<_> = page["movies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["title"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(titles, vec!["Aliens"]);
+
+    let mut titles = Vec::new();
+    let mut uri = format!(
+        "/movies?director={}&sort=release_date:desc&limit=1",
+        director.replace(' ', "%20")
+    );
+    loop {
+        let (status, page) = app.request("GET", &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        titles.extend(
+            page["movies"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|m| m["title"].as_str().unwrap().to_string()),
+        );
+        match page["next_cursor"].as_str() {
+            None => break,
+            Some(cursor) => {
+                uri = format!(
+                    "/movies?director={}&sort=release_date:desc&limit=1&cursor={}",
+                    director.replace(' ', "%20"),
+                    cursor
+                )
+            }
+        }
+    }
+    assert_eq!(titles, vec!["Prometheus", "Alien 3", "Aliens", "Alien"]);
+
+    let (status, page) = app
+        .request(
+            "GET",
+            &format!(
+                "/movies?director={}&sort=title",
+                director.replace(' ', "%20")
+            ),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let titles: Vec<_> = page["movies"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|m| m["title"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(titles, vec!["Alien", "Alien 3", "Aliens", "Prometheus"]);
+}
+
+#[tokio::test]
+async fn list_filters_and_sorts_for_memory_store() {
+    assert_list_filters_and_sorts(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn list_filters_and_sorts_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_list_filters_and_sorts(app).await;
+    }
+}
+
+#[tokio::test]
+async fn list_filters_and_sorts_for_sqlite_store() {
+    assert_list_filters_and_sorts(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn list_with_invalid_sort_returns_400() {
+    let app = TestApp::memory().await;
+
+    let (status, _) = app.request("GET", "/movies?sort=budget:desc", None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, page) = app.request("GET", "/movies?limit=1", None).await;
+    assert_eq!(status, StatusCode::OK);
+    if let Some(cursor) = page["next_cursor"].as_str() {
+        let uri = format!("/movies?sort=title&cursor={}", cursor);
+        let (status, _) = app.request("GET", &uri, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
+
+async fn assert_search_ranks_title_matches_first(app: TestApp) {
+    let word = format!("w{}", Uuid::new_v4().simple());
+    let title_match = create_movie_with(&app, &format!("The {} Affair", word), "Jane Doe").await;
+    let director_match = create_movie_with(&app, "Another Story", &format!("{} Smith", word)).await;
+    create_movie_with(&app, "Unrelated", "Nobody").await;
+
+    let (status, results) = app
+        .request(
+            "GET",
+            &format!("/movies/search?q={}", word.to_uppercase()),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let results = results.as_array().unwrap();
+    let ids: Vec<_> = results.iter().map(|r| r["id"].clone()).collect();
+    assert_eq!(
+        ids,
+        vec![title_match["id"].clone(), director_match["id"].clone()]
+    );
+    assert!(results[0]["score"].as_f64().unwrap() > results[1]["score"].as_f64().unwrap());
+
+    let (status, results) = app
+        .request("GET", &format!("/movies/search?q=affair%20{}", word), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let ids: Vec<_> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["id"].clone())
+        .collect();
+    assert_eq!(ids, vec![title_match["id"].clone()]);
+
+    let uri = format!("/movies/{}", title_match["id"].as_str().unwrap());
+    app.request("PUT", &uri, Some(json!({ "title": "Renamed" })))
+        .await;
+    let (_, results) = app
+        .request("GET", &format!("/movies/search?q={}", word), None)
+        .await;
+    let ids: Vec<_> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["id"].clone())
+        .collect();
+    assert_eq!(ids, vec![director_match["id"].clone()]);
+}
+
+#[tokio::test]
+async fn search_ranks_title_matches_first_for_memory_store() {
+    assert_search_ranks_title_matches_first(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn search_ranks_title_matches_first_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_search_ranks_title_matches_first(app).await;
+    }
+}
+
+#[tokio::test]
+async fn search_ranks_title_matches_first_for_sqlite_store() {
+    assert_search_ranks_title_matches_first(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn search_without_query_returns_400() {
+    let app = TestApp::memory().await;
+
+    let (status, _) = app.request("GET", "/movies/search?q=%20", None).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+async fn assert_if_match_guards_writes(app: TestApp) {
+    let movie = create_movie(&app, "Versioned").await;
+    let uri = format!("/movies/{}", movie["id"].as_str().unwrap());
+
+    let (status, headers, _) = app.request_with_headers("GET", &uri, &[], None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["etag"], "\"1\"");
+
+    let (status, headers, body) = app
+        .request_with_headers(
+            "PUT",
+            &uri,
+            &[("If-Match", "\"1\"")],
+            Some(json!({ "title": "Versioned again" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["etag"], "\"2\"");
+    assert_eq!(body["title"], "Versioned again");
+
+    // a stale tag loses instead of overwriting the newer version
+    let (status, _, body) = app
+        .request_with_headers(
+            "PUT",
+            &uri,
+            &[("If-Match", "\"1\"")],
+            Some(json!({ "title": "Stale" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    assert!(body["error_message"].is_string());
+    let (status, _, _) = app
+        .request_with_headers("DELETE", &uri, &[("If-Match", "\"1\"")], None)
+        .await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    let (status, _, _) = app
+        .request_with_headers("DELETE", &uri, &[("If-Match", "W/\"2\"")], None)
+        .await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+
+    let (_, body) = app.request("GET", &uri, None).await;
+    assert_eq!(body["title"], "Versioned again");
+
+    // without If-Match the write is unconditional
+    let (status, headers, _) = app
+        .request_with_headers("PUT", &uri, &[], Some(json!({ "director": "Someone" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["etag"], "\"3\"");
+
+    let (status, _, _) = app
+        .request_with_headers("DELETE", &uri, &[("If-Match", "\"3\"")], None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn if_match_guards_writes_for_memory_store() {
+    assert_if_match_guards_writes(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn if_match_guards_writes_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_if_match_guards_writes(app).await;
+    }
+}
+
+#[tokio::test]
+async fn if_match_guards_writes_for_sqlite_store() {
+    assert_if_match_guards_writes(TestApp::sqlite().await).await;
+}
+
+async fn assert_concurrent_updates_with_same_etag_only_one_wins(app: TestApp) {
+    let movie = create_movie(&app, "Contended").await;
+    let uri = format!("/movies/{}", movie["id"].as_str().unwrap());
+
+    let updates = (0..8).map(|i| {
+        tokio::spawn(app.request_with_headers(
+            "PUT",
+            &uri,
+            &[("If-Match", "\"1\"")],
+            Some(json!({ "title": format!("Editor {}", i) })),
+        ))
+    });
+    let mut statuses = Vec::new();
+    for update in updates.collect::<Vec<_>>() {
+        statuses.push(update.await.unwrap().0);
+    }
+
+    let succeeded = statuses.iter().filter(|s| **s == StatusCode::OK).count();
+    let failed = statuses
+        .iter()
+        .filter(|s| **s == StatusCode::PRECONDITION_FAILED)
+        .count();
+    assert_eq!((succeeded, failed), (1, 7));
+
+    let (_, headers, _) = app.request_with_headers("GET", &uri, &[], None).await;
+    assert_eq!(headers["etag"], "\"2\"");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_updates_with_same_etag_only_one_wins_for_memory_store() {
+    assert_concurrent_updates_with_same_etag_only_one_wins(TestApp::memory().await).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_updates_with_same_etag_only_one_wins_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_concurrent_updates_with_same_etag_only_one_wins(app).await;
+    }
+}
+
+async fn assert_concurrent_partial_updates_are_not_lost(app: TestApp) {
+    let movie = create_movie(&app, &format!("Contended {}", Uuid::new_v4().simple())).await;
+    let uri = format!("/movies/{}", movie["id"].as_str().unwrap());
+
+    // half of the updates change the title and half the ticket price, none may undo another
+    let updates = (0..32).map(|i| {
+        let change = if i % 2 == 0 {
+            json!({ "title": format!("Editor {}", i) })
+        } else {
+            json!({ "ticket_price": 100 + i })
+        };
+        tokio::spawn(app.request_with_headers("PUT", &uri, &[], Some(change)))
+    });
+    for update in updates.collect::<Vec<_>>() {
+        assert_eq!(update.await.unwrap().0, StatusCode::OK);
+    }
+
+    let (_, history) = app.request("GET", &format!("{}/history", uri), None).await;
+    let history = history.as_array().unwrap();
+    assert_eq!(history.len(), 33);
+    for pair in history.windows(2) {
+        let (previous, revision) = (&pair[0]["movie"], &pair[1]["movie"]);
+        let changed: Vec<&str> = ["title", "director", "release_date", "ticket_price"]
+            .into_iter()
+            .filter(|field| previous[field] != revision[field])
+            .collect();
+        assert_eq!(json!(changed), pair[1]["changed_fields"]);
+        assert_eq!(changed.len(), 1);
+    }
+    let (_, headers, stored) = app.request_with_headers("GET", &uri, &[], None).await;
+    assert_eq!(headers["etag"], "\"33\"");
+    assert_eq!(stored["title"], history[32]["movie"]["title"]);
+    assert_eq!(stored["ticket_price"], history[32]["movie"]["ticket_price"]);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_partial_updates_are_not_lost_for_memory_store() {
+    assert_concurrent_partial_updates_are_not_lost(TestApp::memory().await).await;
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn concurrent_partial_updates_are_not_lost_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_concurrent_partial_updates_are_not_lost(app).await;
+    }
+}
+
+async fn assert_soft_delete_restore_and_purge(app: TestApp) {
+    let word = format!("w{}", Uuid::new_v4().simple());
+    let movie = create_movie(&app, &format!("Deleted {}", word)).await;
+    let uri = format!("/movies/{}", movie["id"].as_str().unwrap());
+    let list_uri = format!("/movies?title_contains={}", word);
+
+    let (status, body) = app.request("DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["deleted_at"].is_string());
+
+    // hidden from every read unless asked for
+    let (status, _) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, body) = app
+        .request("GET", &format!("{}?include_deleted=true", uri), None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], movie["id"]);
+    let (_, body) = app.request("GET", &list_uri, None).await;
+    assert!(body["movies"].as_array().unwrap().is_empty());
+    let (_, body) = app
+        .request("GET", &format!("{}&include_deleted=true", list_uri), None)
+        .await;
+    assert_eq!(body["movies"].as_array().unwrap().len(), 1);
+    let (_, body) = app
+        .request("GET", &format!("/movies/search?q={}", word), None)
+        .await;
+    assert!(body.as_array().unwrap().is_empty());
+
+    let (status, _) = app
+        .request("PUT", &uri, Some(json!({ "title": "Edited" })))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.request("DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (status, headers, body) = app
+        .request_with_headers("POST", &format!("{}/restore", uri), &[], None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["etag"], "\"3\"");
+    assert!(body["deleted_at"].is_null());
+    let (status, _) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request("POST", &format!("{}/restore", uri), None).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+
+    let (status, _, _) = app
+        .request_with_headers(
+            "DELETE",
+            &format!("{}/purge", uri),
+            &[("If-Match", "\"2\"")],
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    let (status, _) = app.request("DELETE", &format!("{}/purge", uri), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app
+        .request("GET", &format!("{}?include_deleted=true", uri), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app.request("POST", &format!("{}/restore", uri), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn soft_delete_restore_and_purge_for_memory_store() {
+    assert_soft_delete_restore_and_purge(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn soft_delete_restore_and_purge_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_soft_delete_restore_and_purge(app).await;
+    }
+}
+
+#[tokio::test]
+async fn soft_delete_restore_and_purge_for_sqlite_store() {
+    assert_soft_delete_restore_and_purge(TestApp::sqlite().await).await;
+}
+
+async fn assert_history_as_of_and_revert(app: TestApp) {
+    let movie = create_movie(&app, "First Cut").await;
+    let uri = format!("/movies/{}", movie["id"].as_str().unwrap());
+    let (status, _) = app
+        .request("PUT", &uri, Some(json!({ "title": "Second Cut" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, history) = app.request("GET", &format!("{}/history", uri), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let history = history.as_array().unwrap().clone();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0]["revision"], 1);
+    assert_eq!(
+        history[0]["changed_fields"],
+        json!(["title", "director", "release_date", "ticket_price"])
+    );
+    assert_eq!(history[1]["revision"], 2);
+    assert_eq!(history[1]["changed_fields"], json!(["title"]));
+    assert_eq!(history[1]["movie"]["title"], "Second Cut");
+
+    let as_of = |revision: &Value| {
+        format!(
+            "{}?as_of={}",
+            uri,
+            revision["recorded_at"].as_str().unwrap().replace(' ', "T")
+        )
+    };
+    let (status, body) = app.request("GET", &as_of(&history[0]), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["title"], "First Cut");
+    let (status, _) = app
+        .request("GET", &format!("{}?as_of=2000-01-01T00:00:00", uri), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = app
+        .request("GET", &format!("{}?as_of=yesterday", uri), None)
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = app.request("DELETE", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, body) = app.request("GET", &as_of(&history[1]), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["title"], "Second Cut");
+    let (status, _) = app.request("POST", &format!("{}/restore", uri), None).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, headers, body) = app
+        .request_with_headers(
+            "POST",
+            &format!("{}/revert/1", uri),
+            &[("If-Match", "\"4\"")],
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(headers["etag"], "\"5\"");
+    assert_eq!(body["title"], "First Cut");
+    let (status, _) = app
+        .request("POST", &format!("{}/revert/99", uri), None)
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let (_, history) = app.request("GET", &format!("{}/history", uri), None).await;
+    let changed_fields: Vec<_> = history
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|revision| revision["changed_fields"].clone())
+        .collect();
+    assert_eq!(
+        changed_fields[2..],
+        [
+            json!(["deleted_at"]),
+            json!(["deleted_at"]),
+            json!(["title"])
+        ]
+    );
+
+    let (status, _) = app.request("DELETE", &format!("{}/purge", uri), None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = app.request("GET", &format!("{}/history", uri), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn history_as_of_and_revert_for_memory_store() {
+    assert_history_as_of_and_revert(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn history_as_of_and_revert_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_history_as_of_and_revert(app).await;
+    }
+}
+
+#[tokio::test]
+async fn history_as_of_and_revert_for_sqlite_store() {
+    assert_history_as_of_and_revert(TestApp::sqlite().await).await;
+}
+
+async fn assert_batch_applies_all_or_nothing(app: TestApp) {
+    let word = format!("w{}", Uuid::new_v4().simple());
+    let kept = create_movie(&app, "Kept").await;
+    let removed = create_movie(&app, "Removed").await;
+    let new_movie = |title: &str| {
+        json!({
+            "title": title,
+            "director": word,
+            "release_date": "2001-01-01T00:00:00",
+            "ticket_price": 8.0,
+        })
+    };
+
+    let (status, body) = app
+        .request(
+            "POST",
+            "/movies/batch",
+            Some(json!({ "operations": [
+                { "op": "create", "movie": new_movie("Added") },
+                { "op": "update", "id": kept["id"], "expected_version": 1, "movie": { "title": "Kept Renamed" } },
+                { "op": "delete", "id": removed["id"] },
+            ]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let statuses: Vec<_> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].clone())
+        .collect();
+    assert_eq!(
+        statuses,
+        [json!("created"), json!("updated"), json!("deleted")]
+    );
+    assert_eq!(body["results"][0]["movie"]["title"], "Added");
+    assert_eq!(body["results"][1]["version"], 2);
+    assert!(body["results"][2]["movie"]["deleted_at"].is_string());
+    assert!(body.get("failed_index").is_none());
+
+    let added = body["results"][0]["movie"].clone();
+    let (status, body) = app
+        .request(
+            "POST",
+            "/movies/batch",
+            Some(json!({ "operations": [
+                { "op": "create", "movie": new_movie("Never Added") },
+                { "op": "update", "id": kept["id"], "expected_version": 1, "movie": { "title": "Stale" } },
+                { "op": "delete", "id": added["id"] },
+            ]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::PRECONDITION_FAILED);
+    assert_eq!(body["failed_index"], 1);
+    let statuses: Vec<_> = body["results"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|result| result["status"].clone())
+        .collect();
+    assert_eq!(
+        statuses,
+        [json!("rolled_back"), json!("failed"), json!("not_applied")]
+    );
+
+    let (status, body) = app
+        .request(
+            "POST",
+            "/movies/batch",
+            Some(json!({ "operations": [
+                { "op": "create", "movie": new_movie("Never Added") },
+                { "op": "update", "id": Uuid::new_v4(), "movie": { "title": "Missing" } },
+            ]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["failed_index"], 1);
+
+    let (status, body) = app
+        .request(
+            "POST",
+            "/movies/batch",
+            Some(json!({ "operations": [
+                { "op": "create", "movie": new_movie("Never Added") },
+                { "op": "update", "id": kept["id"], "movie": { "release_date": "not a date" } },
+            ]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["failed_index"], 1);
+
+    // movies revised before the failing operation are put back as they were
+    let (status, body) = app
+        .request(
+            "POST",
+            "/movies/batch",
+            Some(json!({ "operations": [
+                { "op": "update", "id": kept["id"], "movie": { "title": "Renamed Again" } },
+                { "op": "delete", "id": added["id"] },
+                { "op": "update", "id": kept["id"], "movie": { "ticket_price": 9.0 } },
+                { "op": "delete", "id": Uuid::new_v4() },
+            ]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["failed_index"], 3);
+    let (status, _) = app
+        .request(
+            "GET",
+            &format!("/movies/{}", added["id"].as_str().unwrap()),
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, history) = app
+        .request(
+            "GET",
+            &format!("/movies/{}/history", kept["id"].as_str().unwrap()),
+            None,
+        )
+        .await;
+    assert_eq!(history.as_array().unwrap().len(), 2);
+    // two creates and the three operations of the first batch
+    let (_, changes) = app.request("GET", "/movies/changes?limit=100", None).await;
+    let kinds: Vec<_> = changes["changes"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|change| [&kept["id"], &removed["id"], &added["id"]].contains(&&change["movie_id"]))
+        .map(|change| change["kind"].clone())
+        .collect();
+    assert_eq!(
+        kinds,
+        ["created", "created", "created", "updated", "deleted"]
+    );
+
+    let (_, results) = app
+        .request("GET", &format!("/movies/search?q={}", word), None)
+        .await;
+    let titles: Vec<_> = results
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|r| r["title"].clone())
+        .collect();
+    assert_eq!(titles, [json!("Added")]);
+    let uri = format!("/movies/{}", kept["id"].as_str().unwrap());
+    let (_, headers, body) = app.request_with_headers("GET", &uri, &[], None).await;
+    assert_eq!(headers["etag"], "\"2\"");
+    assert_eq!(body["title"], "Kept Renamed");
+}
+
+#[tokio::test]
+async fn batch_applies_all_or_nothing_for_memory_store() {
+    assert_batch_applies_all_or_nothing(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn batch_applies_all_or_nothing_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_batch_applies_all_or_nothing(app).await;
+    }
+}
+
+#[tokio::test]
+async fn batch_applies_all_or_nothing_for_sqlite_store() {
+    assert_batch_applies_all_or_nothing(TestApp::sqlite().await).await;
+}
+
+async fn assert_duplicate_movies_return_409(app: TestApp) {
+    let first = create_movie_with(&app, "Memento", "Christopher Nolan").await;
+    let second = create_movie_with(&app, "Insomnia", "Christopher Nolan").await;
+
+    let (status, body) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(json!({
+                "title": "Memento",
+                "director": "Christopher Nolan",
+                "release_date": "2001-01-01T00:00:00",
+                "ticket_price": 9.0,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error_message"], "movie already exists");
+    assert_eq!(body["existing_id"], first["id"]);
+
+    let uri = format!("/movies/{}", second["id"].as_str().unwrap());
+    let (status, body) = app
+        .request("PUT", &uri, Some(json!({ "title": "Memento" })))
+        .await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["existing_id"], first["id"]);
+
+    // a different release date makes it a different movie
+    let (status, _) = app
+        .request(
+            "PUT",
+            &uri,
+            Some(json!({ "title": "Memento", "release_date": "2000-09-05T00:00:00" })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn duplicate_movies_return_409_for_memory_store() {
+    assert_duplicate_movies_return_409(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn duplicate_movies_return_409_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_duplicate_movies_return_409(app).await;
+    }
+}
+
+#[tokio::test]
+async fn duplicate_movies_return_409_for_sqlite_store() {
+    assert_duplicate_movies_return_409(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn concurrent_updates_wait_for_each_other_for_sqlite_store() {
+    // an in memory database has a single connection, a file lets writes overlap
+    let path = std::env::temp_dir().join(format!("movie-api-{}.db", Uuid::new_v4()));
+    let database_url = format!("sqlite://{}", path.display());
+    let pool = get_sqlite_connection_pool(&database_configuration("sqlite", &database_url))
+        .await
+        .unwrap();
+    let store = SqliteStore::new(pool);
+    store.migrate().await.unwrap();
+    let app = TestApp::new(Arc::new(store) as DynStore).await;
+
+    let (status, movie) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(json!({
+                "title": "Paris, Texas",
+                "director": "Wim Wenders",
+                "release_date": "1984-05-19T00:00:00",
+                "ticket_price": 7.0,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let uri = format!("/movies/{}", movie["id"].as_str().unwrap());
+
+    let updates = (1..=10).map(|price| {
+        app.request(
+            "PUT",
+            &uri,
+            Some(json!({ "ticket_price": 7.0 + f64::from(price) })),
+        )
+    });
+    for (status, _) in join_all(updates).await {
+        assert_eq!(status, StatusCode::OK);
+    }
+    let (_, headers, _) = app.request_with_headers("GET", &uri, &[], None).await;
+    assert_eq!(headers["etag"], "\"11\"");
+
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,219 @@
+use std::fs;
+use std::sync::Arc;
+use std::time::Duration;
+
+use axum::async_trait;
+use axum::http::StatusCode;
+use chrono::Utc;
+use movie_api::outbox::{Dispatcher, DynEventSink, EventSink, FileSink};
+use movie_api::store::memory_store::MemoryStore;
+use movie_api::store::store::{DynStore, MovieEvent};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::helpers::{memory_store, sql_store, sqlite_store, TestApp};
+
+fn new_movie(title: &str) -> Value {
+    json!({
+        "title": title,
+        "director": "Akira Kurosawa",
+        "release_date": "1954-04-26T00:00:00",
+        "ticket_price": 5.0,
+    })
+}
+
+async fn dispatch_all(dispatcher: &Dispatcher) {
+    while dispatcher.dispatch().await.unwrap() > 0 {}
+}
+
+async fn assert_writes_are_delivered_in_order(store: DynStore) {
+    let app = TestApp::new(store.clone()).await;
+    let (status, movie) = app
+        .request("POST", "/movies", Some(new_movie("Seven Samurai")))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let id = movie["id"].as_str().unwrap().to_string();
+    let uri = format!("/movies/{}", id);
+    app.request(
+        "PUT",
+        &uri,
+        Some(json!({ "title": "Shichinin no Samurai" })),
+    )
+    .await;
+    app.request("DELETE", &uri, None).await;
+    app.request("POST", &format!("{}/restore", uri), None).await;
+    app.request("DELETE", &format!("{}/purge", uri), None).await;
+
+    // a rolled back batch leaves no events behind
+    let never_added = format!("w{}", Uuid::new_v4().simple());
+    let (status, _) = app
+        .request(
+            "POST",
+            "/movies/batch",
+            Some(json!({ "operations": [
+                { "op": "create", "movie": new_movie(&never_added) },
+                { "op": "delete", "id": Uuid::new_v4() },
+            ]})),
+        )
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let path = std::env::temp_dir().join(format!("movie-events-{}.ndjson", Uuid::new_v4()));
+    let sink = Arc::new(FileSink::new(&path)) as DynEventSink;
+    let outbox_store = store.outbox_store().await;
+    dispatch_all(&Dispatcher::new(outbox_store.clone(), vec![sink], 50)).await;
+
+    let lines = fs::read_to_string(&path).unwrap();
+    let events: Vec<Value> = lines
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    let kinds: Vec<_> = events
+        .iter()
+        .filter(|event| event["movie_id"] == id.as_str())
+        .map(|event| event["kind"].clone())
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            json!("created"),
+            json!("updated"),
+            json!("deleted"),
+            json!("updated"),
+            json!("deleted")
+        ]
+    );
+    let titles: Vec<_> = events
+        .iter()
+        .filter(|event| event["movie_id"] == id.as_str())
+        .map(|event| event["payload"]["title"].clone())
+        .collect();
+    assert_eq!(titles[0], "Seven Samurai");
+    assert_eq!(titles[1], "Shichinin no Samurai");
+    assert!(events
+        .iter()
+        .all(|event| event["payload"]["title"] != never_added.as_str()));
+    let nothing_left = Dispatcher::new(outbox_store, Vec::new(), 1000);
+    assert_eq!(nothing_left.dispatch().await.unwrap(), 0);
+
+    fs::remove_file(&path).unwrap();
+}
+
+#[tokio::test]
+async fn writes_are_delivered_in_order_for_memory_store() {
+    assert_writes_are_delivered_in_order(memory_store().await).await;
+}
+
+#[tokio::test]
+async fn writes_are_delivered_in_order_for_sql_store() {
+    if let Some(store) = sql_store().await {
+        assert_writes_are_delivered_in_order(store).await;
+    }
+}
+
+#[tokio::test]
+async fn writes_are_delivered_in_order_for_sqlite_store() {
+    assert_writes_are_delivered_in_order(sqlite_store().await).await;
+}
+
+struct FailingSink;
+
+#[async_trait]
+impl EventSink for FailingSink {
+    fn name(&self) -> &'static str {
+        "failing"
+    }
+
+    async fn deliver(&self, _events: &[MovieEvent]) -> Result<(), anyhow::Error> {
+        Err(anyhow::anyhow!("sink is down"))
+    }
+}
+
+#[tokio::test]
+async fn events_stay_pending_until_every_sink_takes_them() {
+    let store = memory_store().await;
+    let app = TestApp::new(store.clone()).await;
+    app.request("POST", "/movies", Some(new_movie("Rashomon")))
+        .await;
+    let outbox_store = store.outbox_store().await;
+
+    let dispatcher = Dispatcher::new(
+        outbox_store.clone(),
+        vec![Arc::new(FailingSink) as DynEventSink],
+        10,
+    );
+    assert!(dispatcher.dispatch().await.is_err());
+
+    let dispatcher = Dispatcher::new(outbox_store.clone(), Vec::new(), 10);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 1);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 0);
+}
+
+// the Postgres outbox is shared by the tests running alongside, so only the local stores are
+// checked here
+async fn assert_claimed_events_are_skipped_until_released_or_lapsed(store: DynStore) {
+    let app = TestApp::new(store.clone()).await;
+    app.request("POST", "/movies", Some(new_movie("Ikiru")))
+        .await;
+    let outbox_store = store.outbox_store().await;
+    let in_a_minute = Utc::now().naive_utc() + Duration::from_secs(60);
+
+    let claimed = outbox_store
+        .claim_pending_events(10, in_a_minute)
+        .await
+        .unwrap();
+    assert_eq!(claimed.len(), 1);
+    let ids = [claimed[0].id];
+    assert!(outbox_store
+        .claim_pending_events(10, in_a_minute)
+        .await
+        .unwrap()
+        .is_empty());
+
+    outbox_store.release_events(&ids).await.unwrap();
+    let lapsed = Utc::now().naive_utc() - Duration::from_secs(1);
+    assert_eq!(
+        outbox_store
+            .claim_pending_events(10, lapsed)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+    assert_eq!(
+        outbox_store
+            .claim_pending_events(10, in_a_minute)
+            .await
+            .unwrap()
+            .len(),
+        1
+    );
+
+    outbox_store.mark_delivered(&ids).await.unwrap();
+    assert!(outbox_store
+        .claim_pending_events(10, lapsed)
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn claimed_events_are_skipped_until_released_or_lapsed_for_memory_store() {
+    assert_claimed_events_are_skipped_until_released_or_lapsed(memory_store().await).await;
+}
+
+#[tokio::test]
+async fn claimed_events_are_skipped_until_released_or_lapsed_for_sqlite_store() {
+    assert_claimed_events_are_skipped_until_released_or_lapsed(sqlite_store().await).await;
+}
+
+#[tokio::test]
+async fn memory_store_queues_no_events_without_an_outbox() {
+    let store = Arc::new(MemoryStore::new().with_outbox(false)) as DynStore;
+    let app = TestApp::new(store.clone()).await;
+    let (status, _) = app.request("POST", "/movies", Some(new_movie("Ran"))).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let dispatcher = Dispatcher::new(store.outbox_store().await, Vec::new(), 10);
+    assert_eq!(dispatcher.dispatch().await.unwrap(), 0);
+}
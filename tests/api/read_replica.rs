@@ -0,0 +1,85 @@
+use axum::http::StatusCode;
+use serde_json::json;
+
+use crate::helpers::{TestApp, UNREACHABLE_DATABASE_URL};
+
+async fn create(app: &TestApp) -> String {
+    let (status, movie) = app
+        .request(
+            "POST",
+            "/movies",
+            Some(json!({
+                "title": "Vertigo",
+                "director": "Alfred Hitchcock",
+                "release_date": "1958-05-09T00:00:00",
+                "ticket_price": 6.5,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    format!("/movies/{}", movie["id"].as_str().unwrap())
+}
+
+#[tokio::test]
+async fn reads_opt_in_to_the_replica() {
+    let Some(app) = TestApp::sql_with_replica(UNREACHABLE_DATABASE_URL).await else {
+        return;
+    };
+    let replica = [("X-Read-Replica", "true")];
+    let uri = create(&app).await;
+
+    let (status, _) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _, _) = app.request_with_headers("GET", &uri, &replica, None).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let (status, _, _) = app
+        .request_with_headers("GET", "/movies", &replica, None)
+        .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    let (status, _, _) = app
+        .request_with_headers("GET", "/movies/search?q=vertigo", &replica, None)
+        .await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+
+    // writes always go to the primary
+    let (status, _, _) = app
+        .request_with_headers("PUT", &uri, &replica, Some(json!({ "title": "Psycho" })))
+        .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (status, health) = app.request("GET", "/health", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(health["store_ok"], true);
+    assert_eq!(health["replica_store_ok"], false);
+}
+
+#[tokio::test]
+async fn replica_reads_see_the_replicated_movie() {
+    let Ok(database_url) = std::env::var("DATABASE_URL") else {
+        return;
+    };
+    let app = TestApp::sql_with_replica(&database_url).await.unwrap();
+    let uri = create(&app).await;
+
+    let (status, _, body) = app
+        .request_with_headers("GET", &uri, &[("X-Read-Replica", "true")], None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["title"], "Vertigo");
+
+    let (_, health) = app.request("GET", "/health", None).await;
+    assert_eq!(health["replica_store_ok"], true);
+}
+
+#[tokio::test]
+async fn stores_without_a_replica_ignore_the_header() {
+    let app = TestApp::memory().await;
+    let uri = create(&app).await;
+
+    let (status, _, _) = app
+        .request_with_headers("GET", &uri, &[("X-Read-Replica", "true")], None)
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let (_, health) = app.request("GET", "/health", None).await;
+    assert!(health.get("replica_store_ok").is_none());
+}
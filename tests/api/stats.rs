@@ -0,0 +1,126 @@
+use axum::http::StatusCode;
+use serde_json::{json, Value};
+
+use crate::helpers::TestApp;
+
+fn movie(director: &str, release_date: &str, ticket_price: f64) -> Value {
+    json!({
+        "title": format!("{} {}", director, release_date),
+        "director": director,
+        "release_date": release_date,
+        "ticket_price": ticket_price,
+    })
+}
+
+async fn assert_stats_are_exact(app: TestApp) {
+    for movie in [
+        movie("Sofia Coppola", "2003-09-12T00:00:00", 9.99),
+        movie("Sofia Coppola", "2006-10-20T00:00:00", 10.01),
+        movie("Wes Anderson", "2003-02-07T00:00:00", 7.5),
+    ] {
+        let (status, _) = app.request("POST", "/movies", Some(movie)).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    let (status, stats) = app.request("GET", "/movies/stats", None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(
+        stats,
+        json!({
+            "overall": {
+                "count": 3,
+                "average_ticket_price": "9.1667",
+                "min_ticket_price": "7.5",
+                "max_ticket_price": "10.01",
+            },
+            "by_director": [
+                {
+                    "director": "Sofia Coppola",
+                    "count": 2,
+                    "average_ticket_price": "10",
+                    "min_ticket_price": "9.99",
+                    "max_ticket_price": "10.01",
+                },
+                {
+                    "director": "Wes Anderson",
+                    "count": 1,
+                    "average_ticket_price": "7.5",
+                    "min_ticket_price": "7.5",
+                    "max_ticket_price": "7.5",
+                },
+            ],
+            "by_release_year": [
+                {
+                    "release_year": 2003,
+                    "count": 2,
+                    "average_ticket_price": "8.745",
+                    "min_ticket_price": "7.5",
+                    "max_ticket_price": "9.99",
+                },
+                {
+                    "release_year": 2006,
+                    "count": 1,
+                    "average_ticket_price": "10.01",
+                    "min_ticket_price": "10.01",
+                    "max_ticket_price": "10.01",
+                },
+            ],
+        })
+    );
+
+    let (status, stats) = app
+        .request(
+            "GET",
+            "/movies/stats?release_date_from=2004-01-01T00:00:00&release_date_to=2010-01-01",
+            None,
+        )
+        .await;
+    assert_eq!(status, StatusCode::BAD_REQUEST, "{}", stats);
+    let (_, stats) = app
+        .request(
+            "GET",
+            "/movies/stats?release_date_from=2004-01-01T00:00:00&release_date_to=2010-01-01T00:00:00",
+            None,
+        )
+        .await;
+    assert_eq!(stats["overall"]["count"], 1);
+    assert_eq!(stats["by_director"][0]["director"], "Sofia Coppola");
+
+    let (_, stats) = app
+        .request(
+            "GET",
+            "/movies/stats?release_date_from=2030-01-01T00:00:00",
+            None,
+        )
+        .await;
+    assert_eq!(
+        stats,
+        json!({
+            "overall": {
+                "count": 0,
+                "average_ticket_price": null,
+                "min_ticket_price": null,
+                "max_ticket_price": null,
+            },
+            "by_director": [],
+            "by_release_year": [],
+        })
+    );
+}
+
+#[tokio::test]
+async fn stats_are_exact_for_memory_store() {
+    assert_stats_are_exact(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn stats_are_exact_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_stats_are_exact(app).await;
+    }
+}
+
+#[tokio::test]
+async fn stats_are_exact_for_sqlite_store() {
+    assert_stats_are_exact(TestApp::sqlite().await).await;
+}
@@ -0,0 +1,96 @@
+use axum::http::StatusCode;
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::helpers::TestApp;
+
+async fn assert_tenants_are_isolated(app: TestApp) {
+    let word = format!("w{}", Uuid::new_v4().simple());
+    let acme = [("X-Tenant-Id", "acme")];
+    let globex = [("X-Tenant-Id", "globex")];
+
+    let (status, _, movie) = app
+        .request_with_headers(
+            "POST",
+            "/movies",
+            &acme,
+            Some(json!({
+                "title": "Heat",
+                "director": word,
+                "release_date": "1995-12-15T00:00:00",
+                "ticket_price": 9.5,
+            })),
+        )
+        .await;
+    assert_eq!(status, StatusCode::OK);
+    let uri = format!("/movies/{}", movie["id"].as_str().unwrap());
+
+    let (status, _, _) = app.request_with_headers("GET", &uri, &acme, None).await;
+    assert_eq!(status, StatusCode::OK);
+    for (method, path) in [
+        ("GET", uri.clone()),
+        ("GET", format!("{}/history", uri)),
+        ("POST", format!("{}/revert/1", uri)),
+        ("DELETE", uri.clone()),
+        ("POST", format!("{}/restore", uri)),
+        ("DELETE", format!("{}/purge", uri)),
+    ] {
+        let (status, _, _) = app.request_with_headers(method, &path, &globex, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND, "{} {}", method, path);
+    }
+    let (status, _, _) = app
+        .request_with_headers("PUT", &uri, &globex, Some(json!({ "title": "Ronin" })))
+        .await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    // requests without the header don't belong to acme either
+    let (status, _) = app.request("GET", &uri, None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let list_uri = format!("/movies?director={}", word);
+    let (_, _, page) = app
+        .request_with_headers("GET", &list_uri, &globex, None)
+        .await;
+    assert_eq!(page["movies"], json!([]));
+    let (_, _, page) = app
+        .request_with_headers("GET", &list_uri, &acme, None)
+        .await;
+    assert_eq!(page["movies"].as_array().unwrap().len(), 1);
+    let search_uri = format!("/movies/search?q={}", word);
+    let (_, _, results) = app
+        .request_with_headers("GET", &search_uri, &globex, None)
+        .await;
+    assert_eq!(results, json!([]));
+
+    let (_, _, movie) = app.request_with_headers("GET", &uri, &acme, None).await;
+    assert_eq!(movie["title"], "Heat");
+    assert!(movie["deleted_at"].is_null());
+}
+
+#[tokio::test]
+async fn tenants_are_isolated_for_memory_store() {
+    assert_tenants_are_isolated(TestApp::memory().await).await;
+}
+
+#[tokio::test]
+async fn tenants_are_isolated_for_sql_store() {
+    if let Some(app) = TestApp::sql().await {
+        assert_tenants_are_isolated(app).await;
+    }
+}
+
+#[tokio::test]
+async fn tenants_are_isolated_for_sqlite_store() {
+    assert_tenants_are_isolated(TestApp::sqlite().await).await;
+}
+
+#[tokio::test]
+async fn invalid_tenant_id_returns_400() {
+    let app = TestApp::memory().await;
+
+    for tenant_id in ["", "a b", "../acme", &"a".repeat(65)] {
+        let (status, _, _) = app
+            .request_with_headers("GET", "/movies", &[("X-Tenant-Id", tenant_id)], None)
+            .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{:?}", tenant_id);
+    }
+}